@@ -0,0 +1,15 @@
+use cosmwasm_schema::write_api;
+
+use usei_transfer_tokens::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, SudoMsg};
+
+// Writes the JSON schema of every message, along with the response of each query,
+// into `schema/`. Run with `cargo schema` after changing any of the messages.
+fn main() {
+    write_api! {
+        instantiate: InstantiateMsg,
+        execute: ExecuteMsg,
+        query: QueryMsg,
+        migrate: MigrateMsg,
+        sudo: SudoMsg,
+    }
+}
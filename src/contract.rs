@@ -1,31 +1,117 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Addr, BankMsg, Binary, Coin, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult, Uint128,
+    coin, from_slice, to_binary, to_vec, Addr, BankMsg, Binary, Coin, CosmosMsg, Decimal,
+    Delegation, Deps, DepsMut, DistributionMsg, Env, Event, MessageInfo, Order, Reply, Response,
+    StakingMsg, StdError, StdResult, Storage, SubMsg, SubMsgResult, Timestamp, Uint128,
 };
-use cosmwasm_storage::Bucket;
+use cosmwasm_storage::ReadonlyBucket;
+use cw2::{set_contract_version, CONTRACT};
+use sha2::{Digest, Sha256};
 
 use crate::error::ContractError;
+use crate::ibc::{ibc_lifecycle_complete, MsgTransfer};
 use crate::msg::{
-    ExecuteMsg, GetOwnerResponse, GetWithdrawableCoinQuantityResponse, InstantiateMsg, QueryMsg,
+    AutoPayoutResponse, BalanceAtHeightResponse, BalanceChangedHookMsg, BatchSendItem,
+    DenomBalanceResponse, EscrowsResponse, ExecuteMsg, ExpiringAccount, ExpiringAccountsResponse,
+    FailedWithdrawalsResponse, GetOwnerResponse, GetWithdrawableCoinQuantityResponse,
+    HasRoleResponse, HooksResponse, InstantiateMsg, InvoicesResponse, MerkleClaimResponse,
+    MigrateMsg, PendingWithdrawalsResponse, QueryMsg, ReceiveSplitMsg, RoleMembersResponse,
+    SplitGroupsResponse, SplitMemo, StakingResponse, StreamResponse, StreamsResponse, SudoMsg,
+    TotalLiabilitiesAtHeightResponse, VoucherNonceResponse, VoucherPayload,
+    WithdrawalPolicyResponse,
+};
+use crate::state::{
+    auto_payouts, auto_payouts_read, backfill, backfill_read, balance_changes,
+    balance_changes_read, config, config_read, credit_sources, credit_sources_read, denom_balances,
+    denom_balances_read, denoms_by_account, denoms_by_account_read, escrows, escrows_by_arbiter,
+    escrows_by_arbiter_read, escrows_by_party, escrows_by_party_read, escrows_read,
+    failed_withdrawals, failed_withdrawals_by_account, failed_withdrawals_by_account_read,
+    failed_withdrawals_read, global_withdrawal_history, global_withdrawal_history_read, hooks,
+    hooks_read, ibc_transfer_in_flight, ibc_transfers, ibc_transfers_read, invoices,
+    invoices_by_creator, invoices_by_creator_read, invoices_by_payer, invoices_by_payer_read,
+    invoices_by_status, invoices_by_status_read, invoices_read, last_activity, last_activity_read,
+    merkle_claims, merkle_claims_read, merkle_distributions, merkle_distributions_read, next_id,
+    pending_withdrawals, pending_withdrawals_by_account, pending_withdrawals_by_account_read,
+    pending_withdrawals_read, reserved_for_withdrawals, reserved_for_withdrawals_read, resolver,
+    resolver_read, reward_index, reward_index_read, reward_snapshots, reward_snapshots_read,
+    role_members, role_members_read, sends, sends_by_reference, sends_by_reference_read,
+    sends_read, split_groups, split_groups_by_owner, split_groups_by_owner_read, split_groups_read,
+    staking_config, staking_config_read, streams, streams_by_recipient, streams_by_recipient_read,
+    streams_read, total_balance, total_balance_read, unapproved_withdrawal_history,
+    unapproved_withdrawal_history_read, unbonding, unbonding_read, untracked_credit,
+    untracked_credit_read, used_voucher_nonces, used_voucher_nonces_read, voucher_pool,
+    voucher_pool_read, withdrawal_history, withdrawal_history_read, withdrawal_policies,
+    withdrawal_policies_read, withdrawals_in_flight, withdrawals_in_flight_read, AccountBalance,
+    Backfill, BalanceExpiry, BatchFeeMode, Config, DuplicateRecipientPolicy, Escrow, EscrowStatus,
+    FailedWithdrawal, Fee, GroupMember, IbcTransfer, Invoice, InvoiceStatus, MerkleDistribution,
+    PendingWithdrawal, Role, SelfSendPolicy, SendRecord, SplitGroup, StakingConfig, Stream,
+    Unbonding, WithdrawalAttempt, WithdrawalLimits, WithdrawalPolicy, WithdrawalRecord,
+    BALANCE_SNAPSHOTS, ESCROW_COUNT_KEY, INVOICE_COUNT_KEY, MERKLE_DISTRIBUTION_COUNT_KEY,
+    PENDING_WITHDRAWAL_COUNT_KEY, SEND_COUNT_KEY, SPLIT_GROUP_COUNT_KEY, STREAM_COUNT_KEY,
+    TOTAL_LIABILITIES, WITHDRAWAL_ATTEMPT_COUNT_KEY,
 };
-use crate::state::{config, config_read, resolver, resolver_read, AccountBalance, Config, Fee};
 
 pub static COIN_DENOM: &str = "usei";
 
-/*
+// Identifies the reply to an IBC transfer dispatched by `WithdrawIbc`.
+pub const IBC_TRANSFER_REPLY_ID: u64 = 1;
+// Identifies the reply to a failed `ReceiveSplit` callback.
+pub const RECEIVE_SPLIT_REPLY_ID: u64 = 2;
+// Replies to failed withdrawals are identified by this plus the withdrawal attempt ID.
+pub const WITHDRAWAL_REPLY_ID_OFFSET: u64 = 1 << 32;
+
+// The most sends a `BatchSend` can carry.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+// The most accounts a `PushPayouts` call can pay out.
+pub const MAX_PAYOUTS_PER_CALL: usize = 30;
+
+// The longest memo and reference ID, in bytes, a send can carry.
+pub const MAX_MEMO_LENGTH: usize = 256;
+pub const MAX_REFERENCE_ID_LENGTH: usize = 64;
+
+// Prefixed to the data hashed for merkle leaves and nodes respectively.
+pub const MERKLE_LEAF_PREFIX: u8 = 0;
+pub const MERKLE_NODE_PREFIX: u8 = 1;
+
+// The number of intervals the withdrawal rate limit window is tracked in, which
+// bounds the records kept for it.
+pub const WITHDRAWAL_WINDOW_INTERVALS: u64 = 24;
+
+// The most hooks that can be registered for balance changes.
+pub const MAX_HOOKS: usize = 10;
+
+// The smallest stream that can be created, and the most open streams one recipient
+// can be paid by, which bounds the work of settling them.
+pub const MIN_STREAM_AMOUNT: u128 = 1000;
+pub const MAX_STREAMS_PER_RECIPIENT: usize = 30;
+
+// The most senders an account's credits are tracked by for refunds when its balance
+// expires. Credits from any others can't be refunded.
+pub const MAX_CREDIT_SOURCES: usize = 10;
+
+// Page sizes for the queries that list entries.
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+// Bounds on how many balances one migration step backfills.
+const DEFAULT_BACKFILL_LIMIT: u32 = 100;
+const MAX_BACKFILL_LIMIT: u32 = 500;
+
 const CONTRACT_NAME: &str = "crates.io:usei-transfer-tokens";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
- */
 
 #[cfg_attr(not(feature = "library"), entry_point)]
+#[allow(clippy::manual_unwrap_or, clippy::manual_unwrap_or_default)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // Get the owner either from the message that the creator instantiates with, if they
     // specify an owner.
     // Otherwise, set the owner as the creator.
@@ -43,11 +129,7 @@ pub fn instantiate(
         Some(percent_fee) => percent_fee,
         None => 0,
     };
-    // Percent fee must be between 0 - 9999 inclusive if set,
-    // to represent a percentage ranging 0% - 99.99%.
-    if percent_fee > 9999 {
-        return Err(ContractError::PercentFeeTooLarge { percent_fee });
-    }
+    validate_percent_fee(percent_fee)?;
 
     // Instantiate the contract.
     let config_state: Config = Config {
@@ -56,13 +138,193 @@ pub fn instantiate(
             flat_fee,
             percent_fee,
         },
+        paused: false,
+        self_send_policy: msg.self_send_policy.unwrap_or_default(),
+        duplicate_recipient_policy: msg.duplicate_recipient_policy.unwrap_or_default(),
+        withdrawal_limits: WithdrawalLimits::default(),
+        fallback_account: None,
+        batch_fee_mode: BatchFeeMode::default(),
+        payout_bounty: 0,
+        balance_expiry: None,
     };
     config(deps.storage).save(&config_state)?;
+
+    // The owner starts out holding every role, and can hand them out from there.
+    for role in Role::ALL {
+        role_members(deps.storage, role)
+            .save(config_state.owner.as_bytes(), &config_state.owner)?;
+    }
     Ok(Response::default())
 }
 
+fn validate_percent_fee(percent_fee: u128) -> Result<(), ContractError> {
+    // Percent fee must be between 0 - 9999 inclusive if set,
+    // to represent a percentage ranging 0% - 99.99%.
+    if percent_fee > 9999 {
+        return Err(ContractError::PercentFeeTooLarge { percent_fee });
+    }
+    Ok(())
+}
+
+// Contracts deployed before balances were snapshotted have their balances backfilled,
+// a page at a time. Contracts deployed before roles existed have their owner granted
+// every role.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    // Deployments from before the version was recorded have none to check.
+    let previous = CONTRACT.may_load(deps.storage)?;
+    if let Some(previous) = &previous {
+        if previous.contract != CONTRACT_NAME {
+            return Err(ContractError::CannotMigrate {
+                contract: previous.contract.clone(),
+            });
+        }
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // Save the config back with the fields that were missing from it defaulted.
+    let config_data = config_read(deps.storage).load()?;
+    config(deps.storage).save(&config_data)?;
+    let has_admin = role_members_read(deps.storage, Role::Admin)
+        .range(None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if !has_admin {
+        for role in Role::ALL {
+            role_members(deps.storage, role)
+                .save(config_data.owner.as_bytes(), &config_data.owner)?;
+        }
+    }
+
+    // The version was first recorded along with the snapshots, so only deployments
+    // without one need their balances backfilled. Balances credited before the total
+    // was tracked aren't part of it either, so it's counted again from scratch.
+    let resp = if previous.is_none() {
+        backfill(deps.storage).save(&Backfill {
+            start_after: None,
+            total: 0,
+        })?;
+        backfill_balances(deps.branch(), &env, msg.limit)?
+    } else {
+        Response::new()
+    };
+    Ok(resp
+        .add_attribute("action", "migrate")
+        .add_attribute("version", CONTRACT_VERSION))
+}
+
+// Snapshot the next `limit` balances at the current height and add them to the
+// total. Each balance counts the staking rewards not yet settled into it, as later
+// snapshots do. The total is only saved once every balance has been counted.
+fn backfill_balances(
+    deps: DepsMut,
+    env: &Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let mut progress = backfill_read(deps.storage)
+        .may_load()?
+        .ok_or(ContractError::NoMigrationInProgress {})?;
+    let limit = limit
+        .unwrap_or(DEFAULT_BACKFILL_LIMIT)
+        .min(MAX_BACKFILL_LIMIT) as usize;
+    // Start just past `start_after`.
+    let start = progress.start_after.as_ref().map(|address| {
+        let mut key = address.as_bytes().to_vec();
+        key.push(0);
+        key
+    });
+    let addresses: Vec<Addr> = resolver_read(deps.storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, balance)| balance.address))
+        .collect::<StdResult<_>>()?;
+
+    let height = env.block.height;
+    for address in &addresses {
+        let balance = get_account_balance(deps.as_ref(), address)?;
+        BALANCE_SNAPSHOTS.save(deps.storage, address, &balance, height)?;
+        progress.total += balance;
+    }
+    let resp = Response::new().add_attribute("accounts", addresses.len().to_string());
+    if addresses.len() == limit {
+        progress.start_after = addresses.last().cloned();
+        backfill(deps.storage).save(&progress)?;
+        return Ok(resp.add_attribute("complete", "false"));
+    }
+    backfill(deps.storage).remove();
+    total_balance(deps.storage).save(&progress.total)?;
+    TOTAL_LIABILITIES.save(deps.storage, &progress.total, height)?;
+    Ok(resp
+        .add_attribute("complete", "true")
+        .add_attribute("total_balance", progress.total.to_string()))
+}
+
+fn execute_continue_migration(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let resp = backfill_balances(deps, &env, limit)?;
+    Ok(resp.add_attribute("action", "continue_migration"))
+}
+
+// Nothing else may change balances until the backfill is done, or the total would
+// count the changes twice.
+fn assert_not_migrating(storage: &dyn Storage) -> Result<(), ContractError> {
+    if backfill_read(storage).may_load()?.is_some() {
+        return Err(ContractError::MigrationInProgress {});
+    }
+    Ok(())
+}
+
+// Count coins credited to `address` during the backfill towards its total, if the
+// account has already been backfilled. Accounts still to come are counted with
+// whatever balance they have by then.
+pub(crate) fn count_in_backfill(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    quantity: u128,
+) -> StdResult<()> {
+    let mut progress = match backfill_read(storage).may_load()? {
+        Some(progress) => progress,
+        None => return Ok(()),
+    };
+    let backfilled = progress
+        .start_after
+        .as_ref()
+        .is_some_and(|last| address.as_bytes() <= last.as_bytes());
+    if backfilled {
+        progress.total += quantity;
+        backfill(storage).save(&progress)?;
+    }
+    Ok(())
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    // Any message from an account with a balance counts as activity, keeping the
+    // balance from expiring.
+    if last_activity_read(deps.storage)
+        .may_load(info.sender.as_bytes())?
+        .is_some()
+    {
+        last_activity(deps.storage).save(info.sender.as_bytes(), &env.block.height)?;
+    }
+    let height = env.block.height;
+    if !matches!(msg, ExecuteMsg::ContinueMigration { .. }) {
+        assert_not_migrating(deps.storage)?;
+    }
+    let resp = dispatch_execute(deps.branch(), env, info, msg)?;
+    add_balance_hooks(deps.storage, height, resp)
+}
+
+fn dispatch_execute(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
@@ -72,186 +334,3847 @@ pub fn execute(
         ExecuteMsg::SendCoins {
             dest_addr1,
             dest_addr2,
-        } => execute_send_coins(deps, env, info, dest_addr1, dest_addr2),
+            notify_addr1,
+            notify_addr2,
+            memo,
+            reference_id,
+        } => execute_send_coins(
+            deps,
+            env,
+            info,
+            (dest_addr1, notify_addr1),
+            (dest_addr2, notify_addr2),
+            memo,
+            reference_id,
+        ),
         ExecuteMsg::WithdrawCoins { quantity } => execute_withdraw_coins(deps, env, info, quantity),
+        ExecuteMsg::GrantRole { role, address } => {
+            execute_grant_role(deps, env, info, role, address)
+        }
+        ExecuteMsg::RevokeRole { role, address } => {
+            execute_revoke_role(deps, env, info, role, address)
+        }
+        ExecuteMsg::UpdateFee {
+            flat_fee,
+            percent_fee,
+        } => execute_update_fee(deps, env, info, flat_fee, percent_fee),
+        ExecuteMsg::SetPaused { paused } => execute_set_paused(deps, env, info, paused),
+        ExecuteMsg::UpdateOwner { owner } => execute_update_owner(deps, env, info, owner),
+        ExecuteMsg::UpdateRecipientPolicies {
+            self_send_policy,
+            duplicate_recipient_policy,
+        } => execute_update_recipient_policies(
+            deps,
+            env,
+            info,
+            self_send_policy,
+            duplicate_recipient_policy,
+        ),
+        ExecuteMsg::CreateSplitGroup { name, members } => {
+            execute_create_split_group(deps, env, info, name, members)
+        }
+        ExecuteMsg::UpdateSplitGroup {
+            group_id,
+            name,
+            members,
+        } => execute_update_split_group(deps, env, info, group_id, name, members),
+        ExecuteMsg::DeleteSplitGroup { group_id } => {
+            execute_delete_split_group(deps, env, info, group_id)
+        }
+        ExecuteMsg::SendToGroup { group_id } => execute_send_to_group(deps, env, info, group_id),
+        ExecuteMsg::CreateStream {
+            recipient,
+            start,
+            end,
+        } => execute_create_stream(deps, env, info, recipient, start, end),
+        ExecuteMsg::CancelStream { stream_id } => execute_cancel_stream(deps, env, info, stream_id),
+        ExecuteMsg::EscrowSendCoins {
+            dest_addr1,
+            dest_addr2,
+            arbiter,
+            timeout,
+        } => execute_escrow_send_coins(deps, env, info, dest_addr1, dest_addr2, arbiter, timeout),
+        ExecuteMsg::ReleaseEscrow { escrow_id } => {
+            execute_release_escrow(deps, env, info, escrow_id)
+        }
+        ExecuteMsg::RefundEscrow { escrow_id } => execute_refund_escrow(deps, env, info, escrow_id),
+        ExecuteMsg::ReclaimEscrow { escrow_id } => {
+            execute_reclaim_escrow(deps, env, info, escrow_id)
+        }
+        ExecuteMsg::SetWithdrawalPolicy {
+            cosigners,
+            threshold,
+            approval_above,
+            expiry_seconds,
+        } => execute_set_withdrawal_policy(
+            deps,
+            env,
+            info,
+            cosigners,
+            threshold,
+            approval_above,
+            expiry_seconds,
+        ),
+        ExecuteMsg::ApprovePolicyRemoval { account } => {
+            execute_approve_policy_removal(deps, env, info, account)
+        }
+        ExecuteMsg::ApproveWithdrawal { withdrawal_id } => {
+            execute_approve_withdrawal(deps, env, info, withdrawal_id)
+        }
+        ExecuteMsg::CancelWithdrawal { withdrawal_id } => {
+            execute_cancel_withdrawal(deps, env, info, withdrawal_id)
+        }
+        ExecuteMsg::ClaimWithdrawal { withdrawal_id } => {
+            execute_claim_withdrawal(deps, env, info, withdrawal_id)
+        }
+        ExecuteMsg::UpdateWithdrawalLimits { limits } => {
+            execute_update_withdrawal_limits(deps, env, info, limits)
+        }
+        ExecuteMsg::ReceiveIbcTransfer { memo } => {
+            execute_receive_ibc_transfer(deps, env, info, memo)
+        }
+        ExecuteMsg::WithdrawDenom { denom, quantity } => {
+            execute_withdraw_denom(deps, env, info, denom, quantity)
+        }
+        ExecuteMsg::UpdateFallbackAccount { address } => {
+            execute_update_fallback_account(deps, env, info, address)
+        }
+        ExecuteMsg::UpdateStaking { staking } => execute_update_staking(deps, env, info, staking),
+        ExecuteMsg::Rebalance {} => execute_rebalance(deps, env, info),
+        ExecuteMsg::ClaimRewards {} => execute_claim_rewards(deps, env, info),
+        ExecuteMsg::AddHook { addr } => execute_add_hook(deps, env, info, addr),
+        ExecuteMsg::RemoveHook { addr } => execute_remove_hook(deps, env, info, addr),
+        ExecuteMsg::CreateInvoice {
+            payers,
+            amount,
+            split,
+            expiry,
+        } => execute_create_invoice(deps, env, info, payers, amount, split, expiry),
+        ExecuteMsg::PayInvoice { invoice_id } => execute_pay_invoice(deps, env, info, invoice_id),
+        ExecuteMsg::CancelInvoice { invoice_id } => {
+            execute_cancel_invoice(deps, env, info, invoice_id)
+        }
+        ExecuteMsg::BatchSend { sends } => execute_batch_send(deps, env, info, sends),
+        ExecuteMsg::UpdateBatchFeeMode { mode } => {
+            execute_update_batch_fee_mode(deps, env, info, mode)
+        }
+        ExecuteMsg::SetAutoPayout { enabled } => execute_set_auto_payout(deps, env, info, enabled),
+        ExecuteMsg::PushPayouts { addresses } => execute_push_payouts(deps, env, info, addresses),
+        ExecuteMsg::UpdatePayoutBounty { bounty } => {
+            execute_update_payout_bounty(deps, env, info, bounty)
+        }
+        ExecuteMsg::UpdateBalanceExpiry {
+            inactive_blocks,
+            treasury,
+        } => execute_update_balance_expiry(deps, env, info, inactive_blocks, treasury),
+        ExecuteMsg::ReclaimExpired { address } => execute_reclaim_expired(deps, env, info, address),
+        ExecuteMsg::FundVoucherPool {} => execute_fund_voucher_pool(deps, env, info),
+        ExecuteMsg::WithdrawVoucherPool { amount } => {
+            execute_withdraw_voucher_pool(deps, env, info, amount)
+        }
+        ExecuteMsg::SetVoucherKey { pubkey } => execute_set_voucher_key(deps, env, info, pubkey),
+        ExecuteMsg::ClaimVoucher {
+            amount,
+            nonce,
+            expiry,
+            signature,
+        } => execute_claim_voucher(deps, env, info, amount, nonce, expiry, signature),
+        ExecuteMsg::FundMerkleDistribution {
+            merkle_root,
+            total,
+            expiry,
+        } => execute_fund_merkle_distribution(deps, env, info, merkle_root, total, expiry),
+        ExecuteMsg::ClaimMerkle {
+            distribution_id,
+            amount,
+            proof,
+        } => execute_claim_merkle(deps, env, info, distribution_id, amount, proof),
+        ExecuteMsg::ReclaimMerkleDistribution { distribution_id } => {
+            execute_reclaim_merkle_distribution(deps, env, info, distribution_id)
+        }
+        ExecuteMsg::WithdrawIbc {
+            channel_id,
+            remote_address,
+            amount,
+            timeout,
+        } => execute_withdraw_ibc(deps, env, info, channel_id, remote_address, amount, timeout),
+        ExecuteMsg::ContinueMigration { limit } => {
+            execute_continue_migration(deps, env, info, limit)
+        }
     }
 }
 
-fn execute_send_coins(
+fn has_role(storage: &dyn Storage, role: Role, address: &Addr) -> StdResult<bool> {
+    Ok(role_members_read(storage, role)
+        .may_load(address.as_bytes())?
+        .is_some())
+}
+
+fn assert_has_role(storage: &dyn Storage, role: Role, address: &Addr) -> Result<(), ContractError> {
+    if !has_role(storage, role, address)? {
+        return Err(ContractError::Unauthorized { role });
+    }
+    Ok(())
+}
+
+fn assert_not_paused(config_data: &Config) -> Result<(), ContractError> {
+    if config_data.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+    Ok(())
+}
+
+fn execute_grant_role(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
-    dest_addr1: String,
-    dest_addr2: String,
+    role: Role,
+    address: String,
 ) -> Result<Response, ContractError> {
-    let config_data = config_read(deps.storage).load()?;
-    let valid_dest_addr1 = deps.api.addr_validate(&dest_addr1)?;
-    let valid_dest_addr2 = deps.api.addr_validate(&dest_addr2)?;
-
-    // Get the coins that the user sent in this transaction.
-    let total_coin_quantity = get_coin_quantity_sent_in_message(info);
+    assert_has_role(deps.storage, Role::Admin, &info.sender)?;
+    let valid_address = deps.api.addr_validate(&address)?;
+    role_members(deps.storage, role).save(valid_address.as_bytes(), &valid_address)?;
+    let resp = Response::new()
+        .add_attribute("action", "grant_role")
+        .add_attribute("role", format!("{:?}", role))
+        .add_attribute("address", valid_address);
+    Ok(resp)
+}
 
-    // Deduct the flat and/or percent fee for the owner from the sent coins.
-    let owner_fee = get_owner_fee(&config_data, total_coin_quantity)?;
-    let owner_address = config_data.owner;
-    if owner_fee > total_coin_quantity {
-        return Err(ContractError::CannotCoverFee {
-            send_quantity: total_coin_quantity,
-        });
+fn execute_revoke_role(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    role: Role,
+    address: String,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::Admin, &info.sender)?;
+    let valid_address = deps.api.addr_validate(&address)?;
+    if role == Role::Admin && has_role(deps.storage, role, &valid_address)? {
+        // Without an admin nobody could ever grant roles again.
+        let admin_count = role_members_read(deps.storage, Role::Admin)
+            .range(None, None, Order::Ascending)
+            .count();
+        if admin_count <= 1 {
+            return Err(ContractError::CannotRevokeLastAdmin {});
+        }
     }
-
-    // From the remaining coin balance, distribute the coins between the two
-    // destination recipients.
-    let coin_quantity_minus_owner_fee = total_coin_quantity - owner_fee;
-    // NOTE: Because of how this rounds down, there is the possibility of losing
-    // the odd coin out. This could be optimized in the future, although right now
-    // I assume it's probably not worth it, given that it's the smallest unit of
-    // the particular token here.
-    let split_coin_quantity = coin_quantity_minus_owner_fee / 2;
-    increase_coins_at_address(
-        &mut resolver(deps.storage),
-        valid_dest_addr1,
-        split_coin_quantity,
-    )?;
-    increase_coins_at_address(
-        &mut resolver(deps.storage),
-        valid_dest_addr2,
-        split_coin_quantity,
-    )?;
-    increase_coins_at_address(&mut resolver(deps.storage), owner_address, owner_fee)?;
-    Ok(Response::default())
+    role_members(deps.storage, role).remove(valid_address.as_bytes());
+    let resp = Response::new()
+        .add_attribute("action", "revoke_role")
+        .add_attribute("role", format!("{:?}", role))
+        .add_attribute("address", valid_address);
+    Ok(resp)
 }
 
-fn get_owner_fee(config_data: &Config, coin_quantity: u128) -> Result<u128, StdError> {
-    let percent_fee = config_data.fee.percent_fee;
-    let flat_fee = config_data.fee.flat_fee;
-    // Divide the flat fee by 5 zeroes, since it's to represent a precision of up to 0.01%.
-    let owner_fee = coin_quantity * percent_fee / 10000 + flat_fee;
-    Ok(owner_fee)
+fn execute_update_fee(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    flat_fee: u128,
+    percent_fee: u128,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::FeeManager, &info.sender)?;
+    update_fee(deps.storage, flat_fee, percent_fee)
 }
 
-fn increase_coins_at_address(
-    resolver: &mut Bucket<AccountBalance>,
-    valid_dest_addr: Addr,
-    coin_quantity: u128,
-) -> Result<AccountBalance, ContractError> {
-    let key = valid_dest_addr.as_bytes();
-    resolver.update(key, |account_balance: Option<AccountBalance>| {
-        if let Some(mut account_balance) = account_balance {
-            account_balance.balance = account_balance.balance + coin_quantity;
-            Ok::<AccountBalance, ContractError>(account_balance)
-        } else {
-            let new_balance = AccountBalance {
-                address: valid_dest_addr.clone(),
-                balance: coin_quantity,
-            };
-            Ok(new_balance)
-        }
-    })
+fn update_fee(
+    storage: &mut dyn Storage,
+    flat_fee: u128,
+    percent_fee: u128,
+) -> Result<Response, ContractError> {
+    validate_percent_fee(percent_fee)?;
+    config(storage).update(|mut config_data| -> StdResult<_> {
+        config_data.fee = Fee {
+            flat_fee,
+            percent_fee,
+        };
+        Ok(config_data)
+    })?;
+    let resp = Response::new()
+        .add_attribute("action", "update_fee")
+        .add_attribute("flat_fee", flat_fee.to_string())
+        .add_attribute("percent_fee", percent_fee.to_string());
+    Ok(resp)
 }
 
-fn execute_withdraw_coins(
+fn execute_set_paused(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
-    quantity: u128,
+    paused: bool,
 ) -> Result<Response, ContractError> {
-    let address = info.sender;
-    if quantity == 0 {
-        return Err(ContractError::EmptyWithdrawQuantity {
-            withdraw_quantity: quantity,
-        });
-    };
-    decrease_coins_at_address(&mut resolver(deps.storage), &address, quantity)?;
+    assert_has_role(deps.storage, Role::Pauser, &info.sender)?;
+    set_paused(deps.storage, paused)
+}
+
+fn set_paused(storage: &mut dyn Storage, paused: bool) -> Result<Response, ContractError> {
+    config(storage).update(|mut config_data| -> StdResult<_> {
+        config_data.paused = paused;
+        Ok(config_data)
+    })?;
     let resp = Response::new()
-        .add_message(BankMsg::Send {
-            to_address: address.clone().into(),
-            amount: vec![Coin {
-                denom: COIN_DENOM.to_string(),
-                amount: Uint128::from(quantity),
-            }],
-        })
-        .add_attribute("action", "withdraw")
-        .add_attribute("to", address);
+        .add_attribute("action", "set_paused")
+        .add_attribute("paused", paused.to_string());
     Ok(resp)
 }
 
-fn decrease_coins_at_address(
-    resolver: &mut Bucket<AccountBalance>,
-    valid_dest_addr: &Addr,
-    coin_quantity: u128,
-) -> Result<AccountBalance, ContractError> {
-    let key = valid_dest_addr.as_bytes();
-    resolver.update(key, |account_balance: Option<AccountBalance>| {
-        if let Some(mut account_balance) = account_balance {
-            if account_balance.balance >= coin_quantity {
-                // Decrease the user balance if their balance exists and
-                // their current balance is higher than the amount to decrease.
-                account_balance.balance = account_balance.balance - coin_quantity;
-                Ok::<AccountBalance, ContractError>(account_balance)
-            } else {
-                // Raise an error if the user balance would otherwise go negative.
-                Err(ContractError::InsufficientFunds {
-                    withdraw_quantity: coin_quantity,
-                    balance: account_balance.balance,
-                })
-            }
-        } else {
-            // Raise an error since a user without a balance would definitely go
-            // negative from any non-zero amount to decrease.
-            Err(ContractError::InsufficientFunds {
-                withdraw_quantity: coin_quantity,
-                balance: 0,
-            })
-        }
-    })
+fn execute_update_withdrawal_limits(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    limits: WithdrawalLimits,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::ConfigAdmin, &info.sender)?;
+    if limits.window_seconds == 0 {
+        return Err(ContractError::InvalidWithdrawalWindow {});
+    }
+    config(deps.storage).update(|mut config_data| -> StdResult<_> {
+        config_data.withdrawal_limits = limits;
+        Ok(config_data)
+    })?;
+    let resp = Response::new().add_attribute("action", "update_withdrawal_limits");
+    Ok(resp)
 }
 
-// Extract the configured coin amount from the user's transaction.
-fn get_coin_quantity_sent_in_message(info: MessageInfo) -> u128 {
-    let coins_sent = info.funds.iter().find(|coin| coin.denom == COIN_DENOM);
-    match coins_sent {
-        Some(coins) => coins.amount.u128(),
-        None => 0,
-    }
+fn execute_update_fallback_account(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    address: Option<String>,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::ConfigAdmin, &info.sender)?;
+    let valid_address = address
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?;
+    config(deps.storage).update(|mut config_data| -> StdResult<_> {
+        config_data.fallback_account = valid_address.clone();
+        Ok(config_data)
+    })?;
+    let resp = Response::new().add_attribute("action", "update_fallback_account");
+    Ok(resp)
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetOwner {} => query_get_owner(deps, env, msg),
-        QueryMsg::Config {} => query_config(deps, env, msg),
-        QueryMsg::GetWithdrawableCoinQuantity { address } => {
-            query_get_account_balance(deps, env, address)
-        }
+fn execute_update_batch_fee_mode(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    mode: BatchFeeMode,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::FeeManager, &info.sender)?;
+    config(deps.storage).update(|mut config_data| -> StdResult<_> {
+        config_data.batch_fee_mode = mode;
+        Ok(config_data)
+    })?;
+    let resp = Response::new()
+        .add_attribute("action", "update_batch_fee_mode")
+        .add_attribute("mode", format!("{:?}", mode));
+    Ok(resp)
+}
+
+fn execute_update_payout_bounty(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    bounty: u128,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::FeeManager, &info.sender)?;
+    if bounty > 9999 {
+        return Err(ContractError::PayoutBountyTooLarge { bounty });
     }
+    config(deps.storage).update(|mut config_data| -> StdResult<_> {
+        config_data.payout_bounty = bounty;
+        Ok(config_data)
+    })?;
+    let resp = Response::new()
+        .add_attribute("action", "update_payout_bounty")
+        .add_attribute("bounty", bounty.to_string());
+    Ok(resp)
 }
 
-fn query_get_owner(deps: Deps, _env: Env, _msg: QueryMsg) -> StdResult<Binary> {
-    let config_data = config_read(deps.storage).load()?;
-    let resp = GetOwnerResponse {
-        owner: String::from(config_data.owner),
+fn execute_update_balance_expiry(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    inactive_blocks: Option<u64>,
+    treasury: Option<String>,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::ConfigAdmin, &info.sender)?;
+    let treasury = treasury
+        .map(|treasury| deps.api.addr_validate(&treasury))
+        .transpose()?;
+    let balance_expiry = match inactive_blocks {
+        Some(0) => return Err(ContractError::InvalidBalanceExpiry {}),
+        Some(inactive_blocks) => Some(BalanceExpiry {
+            inactive_blocks,
+            treasury,
+        }),
+        None => None,
     };
-    to_binary(&resp)
+    config(deps.storage).update(|mut config_data| -> StdResult<_> {
+        config_data.balance_expiry = balance_expiry;
+        Ok(config_data)
+    })?;
+    let resp = Response::new().add_attribute("action", "update_balance_expiry");
+    Ok(resp)
 }
 
-fn query_config(deps: Deps, _env: Env, _msg: QueryMsg) -> StdResult<Binary> {
-    let config_data = config_read(deps.storage).load()?;
-    to_binary(&config_data)
+fn execute_add_hook(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    addr: String,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::ConfigAdmin, &info.sender)?;
+    let hook = deps.api.addr_validate(&addr)?;
+    let mut registered = hooks_read(deps.storage).may_load()?.unwrap_or_default();
+    if registered.contains(&hook) {
+        return Err(ContractError::HookAlreadyRegistered { hook: addr });
+    }
+    // Every hook is called on every balance change, so they have to be kept few.
+    if registered.len() >= MAX_HOOKS {
+        return Err(ContractError::TooManyHooks { max: MAX_HOOKS });
+    }
+    registered.push(hook);
+    hooks(deps.storage).save(&registered)?;
+    let resp = Response::new()
+        .add_attribute("action", "add_hook")
+        .add_attribute("hook", addr);
+    Ok(resp)
 }
 
-fn query_get_account_balance(deps: Deps, _env: Env, address: String) -> StdResult<Binary> {
-    let valid_address = deps.api.addr_validate(&address)?;
-    let balance = get_account_balance(deps, &valid_address)?;
-    let resp = GetWithdrawableCoinQuantityResponse { address, balance };
-    to_binary(&resp)
+fn execute_remove_hook(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    addr: String,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::ConfigAdmin, &info.sender)?;
+    let hook = deps.api.addr_validate(&addr)?;
+    let mut registered = hooks_read(deps.storage).may_load()?.unwrap_or_default();
+    let index = registered
+        .iter()
+        .position(|registered_hook| *registered_hook == hook)
+        .ok_or_else(|| ContractError::HookNotRegistered { hook: addr.clone() })?;
+    registered.remove(index);
+    hooks(deps.storage).save(&registered)?;
+    let resp = Response::new()
+        .add_attribute("action", "remove_hook")
+        .add_attribute("hook", addr);
+    Ok(resp)
 }
 
-fn get_account_balance(deps: Deps, valid_address: &Addr) -> Result<u128, StdError> {
-    let key = valid_address.as_bytes();
-    let balance = match resolver_read(deps.storage).may_load(key)? {
-        Some(ab) => ab.balance,
-        None => 0,
-    };
-    Ok(balance)
+fn execute_update_owner(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    owner: String,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::Treasury, &info.sender)?;
+    let valid_owner = deps.api.addr_validate(&owner)?;
+    set_owner(deps.storage, valid_owner)
+}
+
+fn set_owner(storage: &mut dyn Storage, valid_owner: Addr) -> Result<Response, ContractError> {
+    config(storage).update(|mut config_data| -> StdResult<_> {
+        config_data.owner = valid_owner.clone();
+        Ok(config_data)
+    })?;
+    let resp = Response::new()
+        .add_attribute("action", "update_owner")
+        .add_attribute("owner", valid_owner);
+    Ok(resp)
+}
+
+fn execute_update_recipient_policies(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    self_send_policy: Option<SelfSendPolicy>,
+    duplicate_recipient_policy: Option<DuplicateRecipientPolicy>,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::ConfigAdmin, &info.sender)?;
+    let config_data = config(deps.storage).update(|mut config_data| -> StdResult<_> {
+        if let Some(self_send_policy) = self_send_policy {
+            config_data.self_send_policy = self_send_policy;
+        }
+        if let Some(duplicate_recipient_policy) = duplicate_recipient_policy {
+            config_data.duplicate_recipient_policy = duplicate_recipient_policy;
+        }
+        Ok(config_data)
+    })?;
+    let resp = Response::new()
+        .add_attribute("action", "update_recipient_policies")
+        .add_attribute(
+            "self_send_policy",
+            format!("{:?}", config_data.self_send_policy),
+        )
+        .add_attribute(
+            "duplicate_recipient_policy",
+            format!("{:?}", config_data.duplicate_recipient_policy),
+        );
+    Ok(resp)
+}
+
+// Each destination comes with whether to call it back with `ReceiveSplit` once
+// it has been credited.
+fn execute_send_coins(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    (dest_addr1, notify_addr1): (String, bool),
+    (dest_addr2, notify_addr2): (String, bool),
+    memo: Option<String>,
+    reference_id: Option<String>,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    let valid_dest_addr1 = deps.api.addr_validate(&dest_addr1)?;
+    let valid_dest_addr2 = deps.api.addr_validate(&dest_addr2)?;
+    if memo
+        .as_ref()
+        .is_some_and(|memo| memo.len() > MAX_MEMO_LENGTH)
+    {
+        return Err(ContractError::MemoTooLong {
+            max: MAX_MEMO_LENGTH,
+        });
+    }
+    if let Some(reference_id) = &reference_id {
+        if reference_id.len() > MAX_REFERENCE_ID_LENGTH {
+            return Err(ContractError::ReferenceIdTooLong {
+                max: MAX_REFERENCE_ID_LENGTH,
+            });
+        }
+        // A retried submission must not pay twice.
+        let existing = sends_by_reference_read(deps.storage, &info.sender)
+            .may_load(reference_id.as_bytes())?;
+        if existing.is_some() {
+            return Err(ContractError::DuplicateReferenceId {
+                reference_id: reference_id.clone(),
+            });
+        }
+    }
+
+    // Get the coins that the user sent in this transaction.
+    let total_coin_quantity = get_coin_quantity_sent_in_message(&info);
+
+    // Distribute the coins evenly between the two destination recipients.
+    let shares = credit_split(
+        deps.storage,
+        &config_data,
+        &info.sender,
+        vec![(valid_dest_addr1.clone(), 1), (valid_dest_addr2.clone(), 1)],
+        total_coin_quantity,
+    )?;
+
+    let send_id = next_id(deps.storage, SEND_COUNT_KEY)?;
+    let record = SendRecord {
+        id: send_id,
+        sender: info.sender.clone(),
+        amount: total_coin_quantity,
+        shares: shares.clone(),
+        memo: memo.clone(),
+        reference_id: reference_id.clone(),
+        time: env.block.time,
+    };
+    sends(deps.storage).save(&send_id.to_be_bytes(), &record)?;
+    if let Some(reference_id) = &reference_id {
+        sends_by_reference(deps.storage, &info.sender).save(reference_id.as_bytes(), &send_id)?;
+    }
+
+    let mut resp = Response::new()
+        .add_attribute("action", "send_coins")
+        .add_attribute("send_id", send_id.to_string())
+        .add_attribute("quantity", total_coin_quantity.to_string());
+    if let Some(memo) = &memo {
+        resp = resp.add_attribute("memo", memo);
+    }
+    if let Some(reference_id) = reference_id {
+        resp = resp.add_attribute("reference_id", reference_id);
+    }
+
+    let mut notified: Vec<Addr> = vec![];
+    for (recipient, notify) in [
+        (valid_dest_addr1, notify_addr1),
+        (valid_dest_addr2, notify_addr2),
+    ] {
+        if notify && !notified.contains(&recipient) {
+            notified.push(recipient);
+        }
+    }
+    for recipient in notified {
+        let amount = shares
+            .iter()
+            .filter(|(address, _)| *address == recipient)
+            .map(|(_, share)| share)
+            .sum();
+        let callback = ReceiveSplitMsg {
+            sender: info.sender.to_string(),
+            amount,
+            memo: memo.clone(),
+        };
+        // The recipient is credited whether or not the callback succeeds.
+        resp = resp.add_submessage(SubMsg::reply_on_error(
+            callback.into_cosmos_msg(recipient)?,
+            RECEIVE_SPLIT_REPLY_ID,
+        ));
+    }
+    Ok(resp)
+}
+
+// Credit many splits from one funds attachment. Each send's amount is what its
+// recipients share, and the owner fees are paid on top of the amounts.
+fn execute_batch_send(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    sends: Vec<BatchSendItem>,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    if sends.is_empty() {
+        return Err(ContractError::EmptyBatch {});
+    }
+    if sends.len() > MAX_BATCH_SIZE {
+        return Err(ContractError::BatchTooLarge {
+            max: MAX_BATCH_SIZE,
+        });
+    }
+
+    let total_amount = sends
+        .iter()
+        .try_fold(Uint128::zero(), |total, send| {
+            total.checked_add(Uint128::from(send.amount))
+        })?
+        .u128();
+    let item_fees = sends
+        .iter()
+        .map(|send| match config_data.batch_fee_mode {
+            BatchFeeMode::PerItem => get_owner_fee(&config_data, send.amount),
+            BatchFeeMode::PerBatch => Ok(0),
+        })
+        .collect::<StdResult<Vec<u128>>>()?;
+    let total_fee = match config_data.batch_fee_mode {
+        BatchFeeMode::PerItem => item_fees
+            .iter()
+            .try_fold(Uint128::zero(), |total, fee| {
+                total.checked_add(Uint128::from(*fee))
+            })?
+            .u128(),
+        BatchFeeMode::PerBatch => get_owner_fee(&config_data, total_amount)?,
+    };
+    let expected = Uint128::from(total_amount)
+        .checked_add(Uint128::from(total_fee))?
+        .u128();
+    let received = get_coin_quantity_sent_in_message(&info);
+    if received != expected {
+        return Err(ContractError::BatchFundsMismatch { expected, received });
+    }
+
+    let mut resp = Response::new()
+        .add_attribute("action", "batch_send")
+        .add_attribute("sends", sends.len().to_string())
+        .add_attribute("quantity", received.to_string())
+        .add_attribute("fee", total_fee.to_string());
+    for (index, (send, fee)) in sends.into_iter().zip(item_fees).enumerate() {
+        if send.recipients.is_empty() {
+            return Err(ContractError::EmptyBatchSend { index });
+        }
+        let mut recipients = Vec::with_capacity(send.recipients.len());
+        for (address, weight) in send.recipients {
+            let valid_address = deps.api.addr_validate(&address)?;
+            if weight == 0 {
+                return Err(ContractError::ZeroWeight { address });
+            }
+            recipients.push((valid_address, weight));
+        }
+        let shares = split_coins(&config_data, &info.sender, recipients, send.amount)?;
+        let mut event = Event::new("batch_send_item")
+            .add_attribute("index", index.to_string())
+            .add_attribute("amount", send.amount.to_string())
+            .add_attribute("fee", fee.to_string());
+        for (recipient, share) in shares {
+            event = event
+                .add_attribute("recipient", recipient.as_str())
+                .add_attribute("share", share.to_string());
+            increase_coins_at_address(deps.storage, recipient.clone(), share)?;
+            record_credit_source(deps.storage, &recipient, &info.sender, share)?;
+        }
+        resp = resp.add_event(event);
+    }
+    increase_coins_at_address(deps.storage, config_data.owner, total_fee)?;
+    Ok(resp)
+}
+
+// Deduct the owner fee from `total_coin_quantity`, then credit the rest to the
+// weighted recipients and the fee to the owner. Returns each recipient's share.
+fn credit_split(
+    storage: &mut dyn Storage,
+    config_data: &Config,
+    sender: &Addr,
+    recipients: Vec<(Addr, u64)>,
+    total_coin_quantity: u128,
+) -> Result<Vec<(Addr, u128)>, ContractError> {
+    let coin_quantity_minus_owner_fee =
+        collect_owner_fee(storage, config_data, total_coin_quantity)?;
+
+    // From the remaining coin balance, distribute the coins between the recipients.
+    let shares = split_coins(
+        config_data,
+        sender,
+        recipients,
+        coin_quantity_minus_owner_fee,
+    )?;
+    for (recipient, share) in &shares {
+        increase_coins_at_address(storage, recipient.clone(), *share)?;
+        record_credit_source(storage, recipient, sender, *share)?;
+    }
+    Ok(shares)
+}
+
+// Remember who credited an account, so its balance can be returned to them if it
+// expires.
+fn record_credit_source(
+    storage: &mut dyn Storage,
+    recipient: &Addr,
+    sender: &Addr,
+    quantity: u128,
+) -> StdResult<()> {
+    let credited = credit_sources_read(storage, recipient).may_load(sender.as_bytes())?;
+    let tracked = credited.is_some()
+        || credit_sources_read(storage, recipient)
+            .range(None, None, Order::Ascending)
+            .take(MAX_CREDIT_SOURCES)
+            .count()
+            < MAX_CREDIT_SOURCES;
+    if !tracked {
+        let untracked = untracked_credit_read(storage)
+            .may_load(recipient.as_bytes())?
+            .unwrap_or_default();
+        return untracked_credit(storage).save(recipient.as_bytes(), &(untracked + quantity));
+    }
+    credit_sources(storage, recipient).save(
+        sender.as_bytes(),
+        &(credited.unwrap_or_default() + quantity),
+    )
+}
+
+// Deduct the flat and/or percent fee for the owner from the sent coins, credit it
+// to the owner, and return what's left.
+fn collect_owner_fee(
+    storage: &mut dyn Storage,
+    config_data: &Config,
+    total_coin_quantity: u128,
+) -> Result<u128, ContractError> {
+    let owner_fee = get_owner_fee(config_data, total_coin_quantity)?;
+    if owner_fee > total_coin_quantity {
+        return Err(ContractError::CannotCoverFee {
+            send_quantity: total_coin_quantity,
+        });
+    }
+    increase_coins_at_address(storage, config_data.owner.clone(), owner_fee)?;
+    Ok(total_coin_quantity - owner_fee)
+}
+
+// Apply the configured self-send and duplicate recipient policies to the weighted
+// recipients of a send, and work out each recipient's share of `coin_quantity`.
+fn split_coins(
+    config_data: &Config,
+    sender: &Addr,
+    recipients: Vec<(Addr, u64)>,
+    coin_quantity: u128,
+) -> Result<Vec<(Addr, u128)>, ContractError> {
+    if config_data.self_send_policy == SelfSendPolicy::Reject
+        && recipients.iter().any(|(recipient, _)| recipient == sender)
+    {
+        return Err(ContractError::SelfSendNotAllowed {});
+    }
+
+    let mut weighted: Vec<(Addr, u64)> = Vec::with_capacity(recipients.len());
+    for (recipient, weight) in recipients {
+        let existing = weighted
+            .iter_mut()
+            .find(|(address, _)| *address == recipient);
+        match (existing, config_data.duplicate_recipient_policy) {
+            (Some(_), DuplicateRecipientPolicy::Reject) => {
+                return Err(ContractError::DuplicateRecipient {
+                    address: recipient.into_string(),
+                });
+            }
+            (Some((_, existing_weight)), DuplicateRecipientPolicy::Merge) => {
+                *existing_weight += weight;
+            }
+            _ => weighted.push((recipient, weight)),
+        }
+    }
+
+    // NOTE: Because of how this rounds down, there is the possibility of losing
+    // the odd coin out. This could be optimized in the future, although right now
+    // I assume it's probably not worth it, given that it's the smallest unit of
+    // the particular token here.
+    let total_weight: u128 = weighted.iter().map(|(_, weight)| *weight as u128).sum();
+    let shares = weighted
+        .into_iter()
+        .map(|(recipient, weight)| (recipient, coin_quantity * weight as u128 / total_weight))
+        .collect();
+    Ok(shares)
+}
+
+fn execute_receive_ibc_transfer(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    memo: String,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    // ICS-20 packets carry a single coin, in its IBC denom on this chain.
+    let received = match info.funds.as_slice() {
+        [coin] => coin.clone(),
+        _ => return Err(ContractError::InvalidIbcFunds {}),
+    };
+    let total_coin_quantity = received.amount.u128();
+    let owner_fee = get_owner_fee(&config_data, total_coin_quantity)?;
+    if owner_fee > total_coin_quantity {
+        return Err(ContractError::CannotCoverFee {
+            send_quantity: total_coin_quantity,
+        });
+    }
+    credit_denom(
+        deps.storage,
+        &received.denom,
+        config_data.owner.clone(),
+        owner_fee,
+    )?;
+    let coin_quantity_minus_owner_fee = total_coin_quantity - owner_fee;
+
+    let mut resp = Response::new()
+        .add_attribute("action", "receive_ibc_transfer")
+        .add_attribute("denom", received.denom.clone())
+        .add_attribute("quantity", total_coin_quantity.to_string());
+    // Rather than bouncing the whole transfer back to the other chain, coins whose
+    // instructions can't be followed are kept by the fallback account to sort out.
+    let shares = parse_split_memo(deps.as_ref(), &memo).and_then(|recipients| {
+        split_coins(
+            &config_data,
+            &info.sender,
+            recipients,
+            coin_quantity_minus_owner_fee,
+        )
+    });
+    match shares {
+        Ok(shares) => {
+            for (recipient, share) in shares {
+                credit_denom(deps.storage, &received.denom, recipient, share)?;
+            }
+        }
+        Err(err) => {
+            let fallback = config_data.fallback_account.unwrap_or(config_data.owner);
+            credit_denom(
+                deps.storage,
+                &received.denom,
+                fallback.clone(),
+                coin_quantity_minus_owner_fee,
+            )?;
+            resp = resp
+                .add_attribute("fallback", fallback)
+                .add_attribute("fallback_reason", err.to_string());
+        }
+    }
+    Ok(resp)
+}
+
+fn parse_split_memo(deps: Deps, memo: &str) -> Result<Vec<(Addr, u64)>, ContractError> {
+    let split: SplitMemo = from_slice(memo.as_bytes())?;
+    if split.recipients.is_empty() {
+        return Err(ContractError::EmptySplitMemo {});
+    }
+    let mut recipients: Vec<(Addr, u64)> = Vec::with_capacity(split.recipients.len());
+    for (address, weight) in split.recipients {
+        let valid_address = deps.api.addr_validate(&address)?;
+        if weight == 0 {
+            return Err(ContractError::ZeroWeight { address });
+        }
+        recipients.push((valid_address, weight));
+    }
+    Ok(recipients)
+}
+
+// Credit usei to the main ledger, and any other denom to the ledger for that denom.
+fn credit_denom(
+    storage: &mut dyn Storage,
+    denom: &str,
+    address: Addr,
+    quantity: u128,
+) -> Result<(), ContractError> {
+    if denom == COIN_DENOM {
+        increase_coins_at_address(storage, address, quantity)?;
+    } else {
+        denom_balances(storage, denom).update(address.as_bytes(), |balance| -> StdResult<_> {
+            Ok(balance.unwrap_or_default() + quantity)
+        })?;
+        denoms_by_account(storage, &address).save(denom.as_bytes(), &denom.to_string())?;
+    }
+    Ok(())
+}
+
+fn execute_withdraw_denom(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: String,
+    quantity: u128,
+) -> Result<Response, ContractError> {
+    // usei is kept in the main ledger, along with its withdrawal rules.
+    if denom == COIN_DENOM {
+        return execute_withdraw_coins(deps, env, info, quantity);
+    }
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    if quantity == 0 {
+        return Err(ContractError::EmptyWithdrawQuantity {
+            withdraw_quantity: quantity,
+        });
+    };
+    // The limits count the coins withdrawn of every denom alike.
+    let limits = config_read(deps.storage).load()?.withdrawal_limits;
+    record_withdrawal(
+        deps.storage,
+        &limits,
+        &info.sender,
+        env.block.time,
+        quantity,
+    )?;
+    let balance =
+        denom_balances(deps.storage, &denom).update(info.sender.as_bytes(), |balance| {
+            let balance = balance.unwrap_or_default();
+            if balance < quantity {
+                return Err(ContractError::InsufficientFunds {
+                    withdraw_quantity: quantity,
+                    balance,
+                });
+            }
+            Ok(balance - quantity)
+        })?;
+    if balance == 0 {
+        denoms_by_account(deps.storage, &info.sender).remove(denom.as_bytes());
+    }
+    if let Some(resp) =
+        hold_withdrawal(deps.storage, &env, &limits, &info.sender, &denom, quantity)?
+    {
+        return Ok(resp);
+    }
+
+    let resp = Response::new()
+        .add_submessage(dispatch_withdrawal(
+            deps.storage,
+            &info.sender,
+            &denom,
+            quantity,
+            Some(env.block.time),
+        )?)
+        .add_attribute("action", "withdraw_denom")
+        .add_attribute("denom", denom)
+        .add_attribute("to", info.sender);
+    Ok(resp)
+}
+
+fn validate_group_members(
+    deps: Deps,
+    members: Vec<(String, u64)>,
+) -> Result<Vec<GroupMember>, ContractError> {
+    if members.is_empty() {
+        return Err(ContractError::EmptySplitGroup {});
+    }
+    let mut valid_members: Vec<GroupMember> = Vec::with_capacity(members.len());
+    for (address, weight) in members {
+        let valid_address = deps.api.addr_validate(&address)?;
+        if weight == 0 {
+            return Err(ContractError::ZeroWeight { address });
+        }
+        // A group is a curated list, so a repeated member is always a mistake.
+        if valid_members
+            .iter()
+            .any(|member| member.address == valid_address)
+        {
+            return Err(ContractError::DuplicateRecipient { address });
+        }
+        valid_members.push(GroupMember {
+            address: valid_address,
+            weight,
+        });
+    }
+    Ok(valid_members)
+}
+
+fn load_owned_split_group(
+    storage: &dyn Storage,
+    sender: &Addr,
+    group_id: u64,
+) -> Result<SplitGroup, ContractError> {
+    let group = split_groups_read(storage).load(&group_id.to_be_bytes())?;
+    if group.owner != *sender {
+        return Err(ContractError::NotSplitGroupOwner { group_id });
+    }
+    Ok(group)
+}
+
+fn execute_create_split_group(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    name: String,
+    members: Vec<(String, u64)>,
+) -> Result<Response, ContractError> {
+    let members = validate_group_members(deps.as_ref(), members)?;
+    let id = next_id(deps.storage, SPLIT_GROUP_COUNT_KEY)?;
+    let group = SplitGroup {
+        id,
+        owner: info.sender.clone(),
+        name,
+        members,
+    };
+    split_groups(deps.storage).save(&id.to_be_bytes(), &group)?;
+    split_groups_by_owner(deps.storage, &info.sender).save(&id.to_be_bytes(), &id)?;
+    let resp = Response::new()
+        .add_attribute("action", "create_split_group")
+        .add_attribute("group_id", id.to_string())
+        .add_attribute("owner", info.sender);
+    Ok(resp)
+}
+
+fn execute_update_split_group(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    group_id: u64,
+    name: Option<String>,
+    members: Option<Vec<(String, u64)>>,
+) -> Result<Response, ContractError> {
+    let mut group = load_owned_split_group(deps.storage, &info.sender, group_id)?;
+    if let Some(name) = name {
+        group.name = name;
+    }
+    if let Some(members) = members {
+        group.members = validate_group_members(deps.as_ref(), members)?;
+    }
+    split_groups(deps.storage).save(&group_id.to_be_bytes(), &group)?;
+    let resp = Response::new()
+        .add_attribute("action", "update_split_group")
+        .add_attribute("group_id", group_id.to_string());
+    Ok(resp)
+}
+
+fn execute_delete_split_group(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    group_id: u64,
+) -> Result<Response, ContractError> {
+    load_owned_split_group(deps.storage, &info.sender, group_id)?;
+    split_groups(deps.storage).remove(&group_id.to_be_bytes());
+    split_groups_by_owner(deps.storage, &info.sender).remove(&group_id.to_be_bytes());
+    let resp = Response::new()
+        .add_attribute("action", "delete_split_group")
+        .add_attribute("group_id", group_id.to_string());
+    Ok(resp)
+}
+
+fn execute_send_to_group(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    group_id: u64,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    let group = split_groups_read(deps.storage).load(&group_id.to_be_bytes())?;
+
+    // The members were validated when the group was saved, so they can be credited
+    // by weight as they are.
+    let total_coin_quantity = get_coin_quantity_sent_in_message(&info);
+    let recipients = group
+        .members
+        .into_iter()
+        .map(|member| (member.address, member.weight))
+        .collect();
+    credit_split(
+        deps.storage,
+        &config_data,
+        &info.sender,
+        recipients,
+        total_coin_quantity,
+    )?;
+    let resp = Response::new()
+        .add_attribute("action", "send_to_group")
+        .add_attribute("group_id", group_id.to_string())
+        .add_attribute("quantity", total_coin_quantity.to_string());
+    Ok(resp)
+}
+
+fn execute_create_stream(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    let valid_recipient = deps.api.addr_validate(&recipient)?;
+    if config_data.self_send_policy == SelfSendPolicy::Reject && valid_recipient == info.sender {
+        return Err(ContractError::SelfSendNotAllowed {});
+    }
+    if start >= end || end <= env.block.time {
+        return Err(ContractError::InvalidStreamSchedule {});
+    }
+
+    let total_coin_quantity = get_coin_quantity_sent_in_message(&info);
+    let amount = collect_owner_fee(deps.storage, &config_data, total_coin_quantity)?;
+    if amount < MIN_STREAM_AMOUNT {
+        return Err(ContractError::StreamTooSmall {
+            amount,
+            minimum: MIN_STREAM_AMOUNT,
+        });
+    }
+    // Streams that have paid out in full are closed first, so they don't count.
+    settle_streams_for_recipient(deps.storage, &valid_recipient, env.block.time)?;
+    let open_streams = streams_by_recipient_read(deps.storage, &valid_recipient)
+        .range(None, None, Order::Ascending)
+        .take(MAX_STREAMS_PER_RECIPIENT)
+        .count();
+    if open_streams >= MAX_STREAMS_PER_RECIPIENT {
+        return Err(ContractError::TooManyStreams {
+            recipient: valid_recipient.into_string(),
+        });
+    }
+
+    let id = next_id(deps.storage, STREAM_COUNT_KEY)?;
+    let stream = Stream {
+        id,
+        sender: info.sender,
+        recipient: valid_recipient.clone(),
+        amount,
+        claimed: 0,
+        start,
+        end,
+    };
+    streams(deps.storage).save(&id.to_be_bytes(), &stream)?;
+    streams_by_recipient(deps.storage, &valid_recipient).save(&id.to_be_bytes(), &id)?;
+    let resp = Response::new()
+        .add_attribute("action", "create_stream")
+        .add_attribute("stream_id", id.to_string())
+        .add_attribute("recipient", valid_recipient)
+        .add_attribute("amount", amount.to_string());
+    Ok(resp)
+}
+
+fn execute_cancel_stream(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    stream_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let mut stream = streams_read(deps.storage).load(&stream_id.to_be_bytes())?;
+    // The recipient can turn down a stream it doesn't want, too.
+    if stream.sender != info.sender && stream.recipient != info.sender {
+        return Err(ContractError::CannotCancelStream { stream_id });
+    }
+
+    // Whatever the recipient has earned so far is theirs to withdraw, and the rest
+    // goes back to the sender.
+    let accrued = stream.accrued(env.block.time);
+    settle_stream(deps.storage, &mut stream, env.block.time)?;
+    close_stream(deps.storage, &stream);
+    let refund = stream.amount - accrued;
+
+    let mut resp = Response::new()
+        .add_attribute("action", "cancel_stream")
+        .add_attribute("stream_id", stream_id.to_string())
+        .add_attribute("settled", accrued.to_string())
+        .add_attribute("refunded", refund.to_string());
+    if refund > 0 {
+        resp = resp.add_message(send_usei(&stream.sender, refund));
+    }
+    Ok(resp)
+}
+
+// Move the stream's accrued coins into the recipient's account balance.
+fn settle_stream(
+    storage: &mut dyn Storage,
+    stream: &mut Stream,
+    now: Timestamp,
+) -> Result<(), ContractError> {
+    let unclaimed = stream.unclaimed(now);
+    if unclaimed > 0 {
+        increase_coins_at_address(storage, stream.recipient.clone(), unclaimed)?;
+        stream.claimed += unclaimed;
+        streams(storage).save(&stream.id.to_be_bytes(), stream)?;
+    }
+    Ok(())
+}
+
+fn close_stream(storage: &mut dyn Storage, stream: &Stream) {
+    streams(storage).remove(&stream.id.to_be_bytes());
+    streams_by_recipient(storage, &stream.recipient).remove(&stream.id.to_be_bytes());
+}
+
+fn load_streams_by_recipient(storage: &dyn Storage, recipient: &Addr) -> StdResult<Vec<Stream>> {
+    streams_by_recipient_read(storage, recipient)
+        .range(None, None, Order::Ascending)
+        .map(|item| {
+            let (_, id) = item?;
+            streams_read(storage).load(&id.to_be_bytes())
+        })
+        .collect()
+}
+
+// Move everything the address's streams have accrued into its account balance,
+// closing the streams that have paid out in full.
+fn settle_streams_for_recipient(
+    storage: &mut dyn Storage,
+    recipient: &Addr,
+    now: Timestamp,
+) -> Result<(), ContractError> {
+    for mut stream in load_streams_by_recipient(storage, recipient)? {
+        settle_stream(storage, &mut stream, now)?;
+        if stream.claimed == stream.amount {
+            close_stream(storage, &stream);
+        }
+    }
+    Ok(())
+}
+
+fn execute_escrow_send_coins(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    dest_addr1: String,
+    dest_addr2: String,
+    arbiter: String,
+    timeout: Option<Timestamp>,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    let valid_dest_addr1 = deps.api.addr_validate(&dest_addr1)?;
+    let valid_dest_addr2 = deps.api.addr_validate(&dest_addr2)?;
+    let valid_arbiter = deps.api.addr_validate(&arbiter)?;
+    if let Some(timeout) = timeout {
+        if timeout <= env.block.time {
+            return Err(ContractError::InvalidEscrowTimeout {});
+        }
+    }
+
+    // Work out the split now, so the recipients know exactly what they'd receive,
+    // but hold the coins until the arbiter decides.
+    let total_coin_quantity = get_coin_quantity_sent_in_message(&info);
+    let amount = collect_owner_fee(deps.storage, &config_data, total_coin_quantity)?;
+    let shares = split_coins(
+        &config_data,
+        &info.sender,
+        vec![(valid_dest_addr1, 1), (valid_dest_addr2, 1)],
+        amount,
+    )?;
+
+    let id = next_id(deps.storage, ESCROW_COUNT_KEY)?;
+    let escrow = Escrow {
+        id,
+        sender: info.sender,
+        arbiter: valid_arbiter,
+        amount,
+        shares,
+        timeout,
+        status: EscrowStatus::Pending,
+    };
+    escrows(deps.storage).save(&id.to_be_bytes(), &escrow)?;
+    escrows_by_arbiter(deps.storage, &escrow.arbiter).save(&id.to_be_bytes(), &id)?;
+    let parties = std::iter::once(&escrow.sender).chain(escrow.shares.iter().map(|(r, _)| r));
+    for party in parties {
+        escrows_by_party(deps.storage, party).save(&id.to_be_bytes(), &id)?;
+    }
+    let resp = Response::new()
+        .add_attribute("action", "escrow_send_coins")
+        .add_attribute("escrow_id", id.to_string())
+        .add_attribute("arbiter", escrow.arbiter)
+        .add_attribute("amount", amount.to_string());
+    Ok(resp)
+}
+
+fn load_pending_escrow(storage: &dyn Storage, escrow_id: u64) -> Result<Escrow, ContractError> {
+    let escrow = escrows_read(storage).load(&escrow_id.to_be_bytes())?;
+    if escrow.status != EscrowStatus::Pending {
+        return Err(ContractError::EscrowSettled { escrow_id });
+    }
+    Ok(escrow)
+}
+
+fn execute_release_escrow(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    escrow_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let mut escrow = load_pending_escrow(deps.storage, escrow_id)?;
+    if escrow.arbiter != info.sender {
+        return Err(ContractError::NotEscrowArbiter { escrow_id });
+    }
+
+    for (recipient, share) in escrow.shares.iter() {
+        increase_coins_at_address(deps.storage, recipient.clone(), *share)?;
+    }
+    escrow.status = EscrowStatus::Released;
+    escrows(deps.storage).save(&escrow_id.to_be_bytes(), &escrow)?;
+    let resp = Response::new()
+        .add_attribute("action", "release_escrow")
+        .add_attribute("escrow_id", escrow_id.to_string());
+    Ok(resp)
+}
+
+fn execute_refund_escrow(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    escrow_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let escrow = load_pending_escrow(deps.storage, escrow_id)?;
+    if escrow.arbiter != info.sender {
+        return Err(ContractError::NotEscrowArbiter { escrow_id });
+    }
+    refund_escrow(deps.storage, escrow, "refund_escrow")
+}
+
+fn execute_reclaim_escrow(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    escrow_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let escrow = load_pending_escrow(deps.storage, escrow_id)?;
+    if escrow.sender != info.sender {
+        return Err(ContractError::NotEscrowSender { escrow_id });
+    }
+    match escrow.timeout {
+        Some(timeout) if timeout <= env.block.time => {}
+        _ => return Err(ContractError::EscrowNotTimedOut { escrow_id }),
+    }
+    refund_escrow(deps.storage, escrow, "reclaim_escrow")
+}
+
+// Send the escrowed coins back to the sender. The owner fee isn't refunded.
+fn refund_escrow(
+    storage: &mut dyn Storage,
+    mut escrow: Escrow,
+    action: &str,
+) -> Result<Response, ContractError> {
+    escrow.status = EscrowStatus::Refunded;
+    escrows(storage).save(&escrow.id.to_be_bytes(), &escrow)?;
+    let resp = Response::new()
+        .add_message(send_usei(&escrow.sender, escrow.amount))
+        .add_attribute("action", action)
+        .add_attribute("escrow_id", escrow.id.to_string())
+        .add_attribute("amount", escrow.amount.to_string());
+    Ok(resp)
+}
+
+fn execute_create_invoice(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    payers: Option<Vec<String>>,
+    amount: u128,
+    split: Vec<(String, u64)>,
+    expiry: Option<Timestamp>,
+) -> Result<Response, ContractError> {
+    if amount == 0 {
+        return Err(ContractError::EmptyInvoice {});
+    }
+    if let Some(expiry) = expiry {
+        if expiry <= env.block.time {
+            return Err(ContractError::InvalidInvoiceExpiry {});
+        }
+    }
+    if split.is_empty() {
+        return Err(ContractError::EmptyInvoiceSplit {});
+    }
+    let split = validate_group_members(deps.as_ref(), split)?;
+    let payers = payers
+        .map(|payers| {
+            payers
+                .iter()
+                .map(|payer| deps.api.addr_validate(payer))
+                .collect::<StdResult<Vec<Addr>>>()
+        })
+        .transpose()?;
+
+    let id = next_id(deps.storage, INVOICE_COUNT_KEY)?;
+    let invoice = Invoice {
+        id,
+        creator: info.sender,
+        payers,
+        amount,
+        split,
+        expiry,
+        status: InvoiceStatus::Open,
+        paid_by: None,
+    };
+    invoices(deps.storage).save(&id.to_be_bytes(), &invoice)?;
+    invoices_by_creator(deps.storage, &invoice.creator).save(&id.to_be_bytes(), &id)?;
+    for payer in invoice.payers.iter().flatten() {
+        invoices_by_payer(deps.storage, payer).save(&id.to_be_bytes(), &id)?;
+    }
+    invoices_by_status(deps.storage, invoice.status).save(&id.to_be_bytes(), &id)?;
+    let resp = Response::new()
+        .add_attribute("action", "create_invoice")
+        .add_attribute("invoice_id", id.to_string())
+        .add_attribute("creator", invoice.creator)
+        .add_attribute("amount", amount.to_string());
+    Ok(resp)
+}
+
+fn load_open_invoice(storage: &dyn Storage, invoice_id: u64) -> Result<Invoice, ContractError> {
+    let invoice = invoices_read(storage).load(&invoice_id.to_be_bytes())?;
+    if invoice.status != InvoiceStatus::Open {
+        return Err(ContractError::InvoiceNotOpen { invoice_id });
+    }
+    Ok(invoice)
+}
+
+fn set_invoice_status(
+    storage: &mut dyn Storage,
+    invoice: &mut Invoice,
+    status: InvoiceStatus,
+) -> StdResult<()> {
+    let key = invoice.id.to_be_bytes();
+    invoices_by_status(storage, invoice.status).remove(&key);
+    invoices_by_status(storage, status).save(&key, &invoice.id)?;
+    invoice.status = status;
+    invoices(storage).save(&key, invoice)
+}
+
+fn execute_pay_invoice(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    invoice_id: u64,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    let mut invoice = load_open_invoice(deps.storage, invoice_id)?;
+    if invoice.expired(env.block.time) {
+        return Err(ContractError::InvoiceExpired { invoice_id });
+    }
+    if let Some(payers) = &invoice.payers {
+        if !payers.contains(&info.sender) {
+            return Err(ContractError::NotInvoicePayer { invoice_id });
+        }
+    }
+    let total_coin_quantity = get_coin_quantity_sent_in_message(&info);
+    if total_coin_quantity != invoice.amount {
+        return Err(ContractError::WrongInvoicePayment {
+            expected: invoice.amount,
+            received: total_coin_quantity,
+        });
+    }
+
+    // The owner fee comes out of the invoiced amount, as for any other send.
+    let recipients = invoice
+        .split
+        .iter()
+        .map(|member| (member.address.clone(), member.weight))
+        .collect();
+    credit_split(
+        deps.storage,
+        &config_data,
+        &info.sender,
+        recipients,
+        total_coin_quantity,
+    )?;
+    invoice.paid_by = Some(info.sender.clone());
+    set_invoice_status(deps.storage, &mut invoice, InvoiceStatus::Paid)?;
+    invoices_by_payer(deps.storage, &info.sender).save(&invoice_id.to_be_bytes(), &invoice_id)?;
+    let resp = Response::new()
+        .add_attribute("action", "pay_invoice")
+        .add_attribute("invoice_id", invoice_id.to_string())
+        .add_attribute("payer", info.sender)
+        .add_attribute("amount", total_coin_quantity.to_string());
+    Ok(resp)
+}
+
+fn execute_cancel_invoice(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    invoice_id: u64,
+) -> Result<Response, ContractError> {
+    let mut invoice = load_open_invoice(deps.storage, invoice_id)?;
+    if invoice.creator != info.sender {
+        return Err(ContractError::NotInvoiceCreator { invoice_id });
+    }
+    set_invoice_status(deps.storage, &mut invoice, InvoiceStatus::Cancelled)?;
+    let resp = Response::new()
+        .add_attribute("action", "cancel_invoice")
+        .add_attribute("invoice_id", invoice_id.to_string());
+    Ok(resp)
+}
+
+fn execute_fund_voucher_pool(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::Treasury, &info.sender)?;
+    let quantity = get_coin_quantity_sent_in_message(&info);
+    let mut pool = voucher_pool_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    pool.balance += quantity;
+    voucher_pool(deps.storage).save(&pool)?;
+    let resp = Response::new()
+        .add_attribute("action", "fund_voucher_pool")
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("balance", pool.balance.to_string());
+    Ok(resp)
+}
+
+fn execute_withdraw_voucher_pool(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    amount: u128,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::Treasury, &info.sender)?;
+    if amount == 0 {
+        return Err(ContractError::EmptyWithdrawQuantity {
+            withdraw_quantity: amount,
+        });
+    }
+    let mut pool = voucher_pool_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    if amount > pool.balance {
+        return Err(ContractError::InsufficientVoucherPool {
+            available: pool.balance,
+        });
+    }
+    pool.balance -= amount;
+    voucher_pool(deps.storage).save(&pool)?;
+    let resp = Response::new()
+        .add_message(send_usei(&info.sender, amount))
+        .add_attribute("action", "withdraw_voucher_pool")
+        .add_attribute("quantity", amount.to_string())
+        .add_attribute("balance", pool.balance.to_string());
+    Ok(resp)
+}
+
+fn execute_set_voucher_key(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    pubkey: Binary,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::Treasury, &info.sender)?;
+    // A secp256k1 public key, either compressed or uncompressed.
+    if pubkey.len() != 33 && pubkey.len() != 65 {
+        return Err(ContractError::InvalidVoucherKey {});
+    }
+    let mut pool = voucher_pool_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    pool.pubkey = Some(pubkey.clone());
+    voucher_pool(deps.storage).save(&pool)?;
+    let resp = Response::new()
+        .add_attribute("action", "set_voucher_key")
+        .add_attribute("pubkey", pubkey.to_base64());
+    Ok(resp)
+}
+
+fn execute_claim_voucher(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: u128,
+    nonce: u64,
+    expiry: Timestamp,
+    signature: Binary,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let mut pool = voucher_pool_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    let pubkey = pool.pubkey.clone().ok_or(ContractError::NoVoucherKey {})?;
+    if expiry <= env.block.time {
+        return Err(ContractError::VoucherExpired {});
+    }
+    let nonce_key = nonce.to_be_bytes();
+    if used_voucher_nonces_read(deps.storage)
+        .may_load(&nonce_key)?
+        .is_some()
+    {
+        return Err(ContractError::VoucherNonceUsed { nonce });
+    }
+
+    // The voucher is only good for the sender, on this contract and chain.
+    let payload = VoucherPayload {
+        chain_id: env.block.chain_id,
+        contract: env.contract.address.into_string(),
+        recipient: info.sender.to_string(),
+        amount,
+        nonce,
+        expiry,
+    };
+    let message_hash = Sha256::digest(&to_vec(&payload)?);
+    let verified = deps
+        .api
+        .secp256k1_verify(&message_hash, &signature, &pubkey);
+    if !matches!(verified, Ok(true)) {
+        return Err(ContractError::InvalidVoucherSignature {});
+    }
+
+    if amount > pool.balance {
+        return Err(ContractError::InsufficientVoucherPool {
+            available: pool.balance,
+        });
+    }
+    pool.balance -= amount;
+    voucher_pool(deps.storage).save(&pool)?;
+    used_voucher_nonces(deps.storage).save(&nonce_key, &env.block.time)?;
+    increase_coins_at_address(deps.storage, info.sender.clone(), amount)?;
+    let resp = Response::new()
+        .add_attribute("action", "claim_voucher")
+        .add_attribute("recipient", info.sender)
+        .add_attribute("nonce", nonce.to_string())
+        .add_attribute("quantity", amount.to_string());
+    Ok(resp)
+}
+
+fn execute_fund_merkle_distribution(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    merkle_root: String,
+    total: u128,
+    expiry: Timestamp,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let mut root = [0u8; 32];
+    hex::decode_to_slice(&merkle_root, &mut root)
+        .map_err(|_| ContractError::InvalidMerkleRoot {})?;
+    if expiry <= env.block.time {
+        return Err(ContractError::InvalidMerkleExpiry {});
+    }
+    let received = get_coin_quantity_sent_in_message(&info);
+    if received != total {
+        return Err(ContractError::WrongMerkleFunding {
+            expected: total,
+            received,
+        });
+    }
+
+    let id = next_id(deps.storage, MERKLE_DISTRIBUTION_COUNT_KEY)?;
+    let distribution = MerkleDistribution {
+        id,
+        funder: info.sender,
+        merkle_root,
+        total,
+        claimed: 0,
+        expiry,
+        reclaimed: false,
+    };
+    merkle_distributions(deps.storage).save(&id.to_be_bytes(), &distribution)?;
+    let resp = Response::new()
+        .add_attribute("action", "fund_merkle_distribution")
+        .add_attribute("distribution_id", id.to_string())
+        .add_attribute("funder", distribution.funder)
+        .add_attribute("total", total.to_string());
+    Ok(resp)
+}
+
+// Check that the claimant's leaf, the SHA-256 hash of `MERKLE_LEAF_PREFIX` followed by
+// "<address>:<amount>", is part of the tree with `merkle_root`. Each pair of nodes is
+// hashed in sorted order after `MERKLE_NODE_PREFIX`, so a leaf can't pass for a node.
+fn verify_merkle_proof(
+    merkle_root: &str,
+    claimant: &Addr,
+    amount: u128,
+    proof: &[String],
+) -> Result<(), ContractError> {
+    let leaf = format!("{}:{}", claimant, amount);
+    let mut hash: [u8; 32] = Sha256::new()
+        .chain([MERKLE_LEAF_PREFIX])
+        .chain(leaf.as_bytes())
+        .finalize()
+        .into();
+    for node in proof {
+        let mut sibling = [0u8; 32];
+        hex::decode_to_slice(node, &mut sibling)
+            .map_err(|_| ContractError::InvalidMerkleProof {})?;
+        let (first, second) = if hash < sibling {
+            (hash, sibling)
+        } else {
+            (sibling, hash)
+        };
+        hash = Sha256::new()
+            .chain([MERKLE_NODE_PREFIX])
+            .chain(first)
+            .chain(second)
+            .finalize()
+            .into();
+    }
+    if hex::encode(hash) != merkle_root.to_lowercase() {
+        return Err(ContractError::InvalidMerkleProof {});
+    }
+    Ok(())
+}
+
+fn execute_claim_merkle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    distribution_id: u64,
+    amount: u128,
+    proof: Vec<String>,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let key = distribution_id.to_be_bytes();
+    let mut distribution = merkle_distributions_read(deps.storage).load(&key)?;
+    if distribution.expiry <= env.block.time {
+        return Err(ContractError::MerkleDistributionExpired { distribution_id });
+    }
+    let claims = merkle_claims_read(deps.storage, distribution_id);
+    if claims.may_load(info.sender.as_bytes())?.is_some() {
+        return Err(ContractError::MerkleAlreadyClaimed { distribution_id });
+    }
+    verify_merkle_proof(&distribution.merkle_root, &info.sender, amount, &proof)?;
+    // A tree whose leaves add up to more than was funded can't pay out more.
+    if distribution.claimed + amount > distribution.total {
+        return Err(ContractError::MerkleDistributionExhausted { distribution_id });
+    }
+
+    distribution.claimed += amount;
+    merkle_distributions(deps.storage).save(&key, &distribution)?;
+    merkle_claims(deps.storage, distribution_id).save(info.sender.as_bytes(), &amount)?;
+    increase_coins_at_address(deps.storage, info.sender.clone(), amount)?;
+    let resp = Response::new()
+        .add_attribute("action", "claim_merkle")
+        .add_attribute("distribution_id", distribution_id.to_string())
+        .add_attribute("claimant", info.sender)
+        .add_attribute("quantity", amount.to_string());
+    Ok(resp)
+}
+
+// Send what wasn't claimed back to the funder once the distribution has expired.
+fn execute_reclaim_merkle_distribution(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    distribution_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let key = distribution_id.to_be_bytes();
+    let mut distribution = merkle_distributions_read(deps.storage).load(&key)?;
+    if distribution.funder != info.sender {
+        return Err(ContractError::NotMerkleFunder { distribution_id });
+    }
+    if distribution.expiry > env.block.time {
+        return Err(ContractError::MerkleDistributionNotExpired { distribution_id });
+    }
+    if distribution.reclaimed {
+        return Err(ContractError::MerkleDistributionReclaimed { distribution_id });
+    }
+
+    distribution.reclaimed = true;
+    merkle_distributions(deps.storage).save(&key, &distribution)?;
+    let remainder = distribution.total - distribution.claimed;
+    let mut resp = Response::new()
+        .add_attribute("action", "reclaim_merkle_distribution")
+        .add_attribute("distribution_id", distribution_id.to_string())
+        .add_attribute("quantity", remainder.to_string());
+    if remainder > 0 {
+        resp = resp.add_message(send_usei(&distribution.funder, remainder));
+    }
+    Ok(resp)
+}
+
+fn get_owner_fee(config_data: &Config, coin_quantity: u128) -> Result<u128, StdError> {
+    let percent_fee = config_data.fee.percent_fee;
+    let flat_fee = config_data.fee.flat_fee;
+    // Divide the flat fee by 5 zeroes, since it's to represent a precision of up to 0.01%.
+    let percent_part =
+        Uint128::from(coin_quantity).checked_mul(Uint128::from(percent_fee))? / Uint128::new(10000);
+    let owner_fee = percent_part.checked_add(Uint128::from(flat_fee))?;
+    Ok(owner_fee.u128())
+}
+
+pub(crate) fn increase_coins_at_address(
+    storage: &mut dyn Storage,
+    valid_dest_addr: Addr,
+    coin_quantity: u128,
+) -> Result<AccountBalance, ContractError> {
+    let old_balance = get_balance(storage, &valid_dest_addr)?;
+    settle_rewards(storage, &valid_dest_addr)?;
+    let key = valid_dest_addr.as_bytes();
+    let account_balance =
+        resolver(storage).update(key, |account_balance: Option<AccountBalance>| {
+            if let Some(mut account_balance) = account_balance {
+                account_balance.balance += coin_quantity;
+                Ok::<AccountBalance, ContractError>(account_balance)
+            } else {
+                let new_balance = AccountBalance {
+                    address: valid_dest_addr.clone(),
+                    balance: coin_quantity,
+                };
+                Ok(new_balance)
+            }
+        })?;
+    update_total_balance(storage, |total| total + coin_quantity)?;
+    record_balance_change(storage, &account_balance, old_balance)?;
+    Ok(account_balance)
+}
+
+fn execute_set_auto_payout(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    if enabled {
+        auto_payouts(deps.storage).save(info.sender.as_bytes(), &true)?;
+    } else {
+        auto_payouts(deps.storage).remove(info.sender.as_bytes());
+    }
+    let resp = Response::new()
+        .add_attribute("action", "set_auto_payout")
+        .add_attribute("account", info.sender)
+        .add_attribute("enabled", enabled.to_string());
+    Ok(resp)
+}
+
+// Withdraw the whole balance of each opted-in account in `addresses` to it, and
+// pay the caller the bounty out of the owner's fees. Accounts that have nothing to
+// pay out, or whose withdrawals would need co-signers, wait out the timelock or go
+// over the rate limits, are skipped.
+fn execute_push_payouts(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    addresses: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    if addresses.len() > MAX_PAYOUTS_PER_CALL {
+        return Err(ContractError::TooManyPayouts {
+            max: MAX_PAYOUTS_PER_CALL,
+        });
+    }
+
+    let mut resp = Response::new().add_attribute("action", "push_payouts");
+    let mut paid: u128 = 0;
+    let mut paid_quantity: u128 = 0;
+    let mut bounty_quantity: u128 = 0;
+    for address in addresses {
+        let valid_address = deps.api.addr_validate(&address)?;
+        let opted_in = auto_payouts_read(deps.storage)
+            .may_load(valid_address.as_bytes())?
+            .unwrap_or(false);
+        let has_policy = withdrawal_policies_read(deps.storage)
+            .may_load(valid_address.as_bytes())?
+            .is_some();
+        if !opted_in || has_policy {
+            continue;
+        }
+        settle_streams_for_recipient(deps.storage, &valid_address, env.block.time)?;
+        let balance = get_account_balance(deps.as_ref(), &valid_address)?;
+        let timelocked = config_data
+            .withdrawal_limits
+            .timelock
+            .as_ref()
+            .is_some_and(|timelock| balance > timelock.above);
+        if balance == 0 || timelocked {
+            continue;
+        }
+
+        // An account over its rate limit is paid out on a later call instead.
+        match record_withdrawal(
+            deps.storage,
+            &config_data.withdrawal_limits,
+            &valid_address,
+            env.block.time,
+            balance,
+        ) {
+            Err(ContractError::WithdrawalLimitExceeded { .. }) => continue,
+            result => result?,
+        }
+        decrease_coins_at_address(deps.storage, &valid_address, balance)?;
+        // The bounty is a share of each payout, so splitting the coins between many
+        // accounts doesn't earn more, and it's only paid once the payout goes through.
+        // Paying out your own balance earns nothing.
+        let bounty = if valid_address == info.sender {
+            0
+        } else {
+            Uint128::from(balance)
+                .multiply_ratio(config_data.payout_bounty, 10000u128)
+                .u128()
+        };
+        let attempt = WithdrawalAttempt {
+            account: valid_address.clone(),
+            denom: COIN_DENOM.to_string(),
+            amount: balance,
+            counted_at: Some(env.block.time),
+            bounty: (bounty > 0).then(|| (info.sender.clone(), bounty)),
+        };
+        resp = resp
+            .add_submessage(dispatch_attempt(deps.storage, attempt)?)
+            .add_event(
+                Event::new("push_payout")
+                    .add_attribute("address", valid_address)
+                    .add_attribute("quantity", balance.to_string()),
+            );
+        paid += 1;
+        paid_quantity += balance;
+        bounty_quantity += bounty;
+    }
+
+    let resp = resp
+        .add_attribute("paid", paid.to_string())
+        .add_attribute("quantity", paid_quantity.to_string())
+        .add_attribute("bounty", bounty_quantity.to_string());
+    Ok(resp)
+}
+
+// The block height from which the balance of `address` can be reclaimed, if it can
+// expire at all.
+fn balance_expires_at(
+    storage: &dyn Storage,
+    config_data: &Config,
+    address: &Addr,
+) -> StdResult<Option<u64>> {
+    let inactive_blocks = match &config_data.balance_expiry {
+        Some(expiry) if *address != config_data.owner => expiry.inactive_blocks,
+        _ => return Ok(None),
+    };
+    let last_active = last_activity_read(storage).may_load(address.as_bytes())?;
+    Ok(last_active.map(|height| height + inactive_blocks))
+}
+
+// Move the balance of an account that has been inactive for the configured period
+// back to the senders who credited it, in proportion to what each sent, or to the
+// treasury if one is configured. No sender gets back more than they sent; coins that
+// came from anywhere else go to the owner.
+fn execute_reclaim_expired(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let config_data = config_read(deps.storage).load()?;
+    assert_not_paused(&config_data)?;
+    let valid_address = deps.api.addr_validate(&address)?;
+    match balance_expires_at(deps.storage, &config_data, &valid_address)? {
+        Some(expires_at) if expires_at <= env.block.height => {}
+        _ => return Err(ContractError::BalanceNotExpired { address }),
+    }
+
+    settle_streams_for_recipient(deps.storage, &valid_address, env.block.time)?;
+    let balance = get_account_balance(deps.as_ref(), &valid_address)?;
+    let sources = credit_sources_read(deps.storage, &valid_address)
+        .range(None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|(sender, credited)| {
+                (Addr::unchecked(String::from_utf8_lossy(&sender)), credited)
+            })
+        })
+        .collect::<StdResult<Vec<(Addr, u128)>>>()?;
+    for (sender, _) in &sources {
+        credit_sources(deps.storage, &valid_address).remove(sender.as_bytes());
+    }
+    let untracked = untracked_credit_read(deps.storage)
+        .may_load(valid_address.as_bytes())?
+        .unwrap_or_default();
+    untracked_credit(deps.storage).remove(valid_address.as_bytes());
+    last_activity(deps.storage).remove(valid_address.as_bytes());
+
+    let treasury = config_data
+        .balance_expiry
+        .and_then(|expiry| expiry.treasury);
+    let credited: u128 = sources.iter().map(|(_, credited)| credited).sum::<u128>() + untracked;
+    let mut refunds: Vec<(Addr, u128)> = match treasury {
+        Some(_) => vec![],
+        None => sources
+            .into_iter()
+            .map(|(sender, sent)| {
+                let share = Uint128::from(balance).multiply_ratio(sent, credited.max(1));
+                (sender, share.u128().min(sent))
+            })
+            .filter(|(_, refund)| *refund > 0)
+            .collect(),
+    };
+    // Whatever isn't refunded, including the share of untracked senders and coins
+    // lost to rounding, goes to the treasury or the owner.
+    let refunded: u128 = refunds.iter().map(|(_, refund)| refund).sum();
+    if balance > refunded {
+        refunds.push((treasury.unwrap_or(config_data.owner), balance - refunded));
+    }
+
+    let mut event = Event::new("reclaim_expired")
+        .add_attribute("address", valid_address.as_str())
+        .add_attribute("quantity", balance.to_string());
+    if balance > 0 {
+        decrease_coins_at_address(deps.storage, &valid_address, balance)?;
+        for (recipient, refund) in refunds {
+            event = event
+                .add_attribute("recipient", recipient.as_str())
+                .add_attribute("refund", refund.to_string());
+            increase_coins_at_address(deps.storage, recipient, refund)?;
+        }
+    }
+    let resp = Response::new()
+        .add_attribute("action", "reclaim_expired")
+        .add_event(event);
+    Ok(resp)
+}
+
+fn execute_withdraw_coins(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    quantity: u128,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let address = info.sender;
+    if quantity == 0 {
+        return Err(ContractError::EmptyWithdrawQuantity {
+            withdraw_quantity: quantity,
+        });
+    };
+    let limits = config_read(deps.storage).load()?.withdrawal_limits;
+    record_withdrawal(deps.storage, &limits, &address, env.block.time, quantity)?;
+    // Coins accrued in streams are withdrawable too, so move them into the balance
+    // before drawing from it.
+    settle_streams_for_recipient(deps.storage, &address, env.block.time)?;
+    decrease_coins_at_address(deps.storage, &address, quantity)?;
+    if let Some(resp) =
+        hold_withdrawal(deps.storage, &env, &limits, &address, COIN_DENOM, quantity)?
+    {
+        return Ok(resp);
+    }
+    if let Some(resp) = hold_for_liquidity(deps.branch(), &env, &address, quantity)? {
+        return Ok(resp);
+    }
+
+    let resp = Response::new()
+        .add_submessage(dispatch_withdrawal(
+            deps.storage,
+            &address,
+            COIN_DENOM,
+            quantity,
+            Some(env.block.time),
+        )?)
+        .add_attribute("action", "withdraw")
+        .add_attribute("to", address);
+    Ok(resp)
+}
+
+// Large withdrawals from an account with a policy wait for its co-signers, and large
+// withdrawals from any account wait out the timelock. The coins are debited first so
+// they can't be withdrawn twice in the meantime. Returns the response for a held
+// withdrawal, or `None` if it can be paid out now.
+fn hold_withdrawal(
+    storage: &mut dyn Storage,
+    env: &Env,
+    limits: &WithdrawalLimits,
+    address: &Addr,
+    denom: &str,
+    quantity: u128,
+) -> Result<Option<Response>, ContractError> {
+    let policy = match withdrawal_policies_read(storage).may_load(address.as_bytes())? {
+        Some(policy)
+            if needs_approval(
+                storage,
+                limits,
+                &policy,
+                address,
+                denom,
+                env.block.time,
+                quantity,
+            )? =>
+        {
+            Some(policy)
+        }
+        _ => None,
+    };
+    let delay_seconds = limits
+        .timelock
+        .as_ref()
+        .filter(|timelock| quantity > timelock.above)
+        .map(|timelock| timelock.delay_seconds);
+    if policy.is_none() && delay_seconds.is_none() {
+        return Ok(None);
+    }
+    let id = next_id(storage, PENDING_WITHDRAWAL_COUNT_KEY)?;
+    let pending = PendingWithdrawal {
+        id,
+        account: address.clone(),
+        denom: denom.to_string(),
+        amount: quantity,
+        approvals: vec![],
+        required_approvals: policy.as_ref().map_or(0, |policy| policy.threshold),
+        expires: policy.map(|policy| env.block.time.plus_seconds(policy.expiry_seconds)),
+        unlocks_at: env.block.time.plus_seconds(delay_seconds.unwrap_or(0)),
+        counted_at: Some(env.block.time),
+    };
+    save_pending_withdrawal(storage, &pending)?;
+    let resp = Response::new()
+        .add_attribute("action", "withdraw_pending")
+        .add_attribute("withdrawal_id", id.to_string())
+        .add_attribute("to", address);
+    Ok(Some(resp))
+}
+
+// A usei withdrawal larger than the contract's liquid coins undelegates the
+// shortfall and waits for it to unbond, rather than failing in the bank send. The
+// held coins are kept out of `execute_rebalance`'s reach until they're claimed.
+fn hold_for_liquidity(
+    mut deps: DepsMut,
+    env: &Env,
+    address: &Addr,
+    quantity: u128,
+) -> Result<Option<Response>, ContractError> {
+    // Without staking, the contract's coins are all liquid.
+    let staking = match staking_config_read(deps.storage).may_load()? {
+        Some(staking) if staking.enabled => staking,
+        _ => return Ok(None),
+    };
+    let contract = &env.contract.address;
+    let liquid = liquid_usei(deps.as_ref(), contract)?;
+    if liquid >= quantity {
+        return Ok(None);
+    }
+    let mut delegations = deps.querier.query_all_delegations(contract)?;
+    if delegations.is_empty() {
+        return Ok(None);
+    }
+    // Undelegating pays out the rewards so far, so they're claimed first to be
+    // shared out like any others.
+    let resp = claim_rewards(deps.branch(), contract)?;
+
+    let mut unbondings = unbonding_read(deps.storage).may_load()?.unwrap_or_default();
+    unbondings.retain(|unbonding| unbonding.completes > env.block.time);
+    let completes = env.block.time.plus_seconds(staking.unbonding_seconds);
+    let msgs = undelegate(
+        &staking,
+        &mut delegations,
+        &mut unbondings,
+        quantity - liquid,
+        completes,
+    );
+    unbonding(deps.storage).save(&unbondings)?;
+
+    let id = next_id(deps.storage, PENDING_WITHDRAWAL_COUNT_KEY)?;
+    let pending = PendingWithdrawal {
+        id,
+        account: address.clone(),
+        denom: COIN_DENOM.to_string(),
+        amount: quantity,
+        approvals: vec![],
+        required_approvals: 0,
+        expires: None,
+        unlocks_at: completes,
+        counted_at: Some(env.block.time),
+    };
+    save_pending_withdrawal(deps.storage, &pending)?;
+    let resp = resp
+        .add_messages(msgs)
+        .add_attribute("action", "withdraw_pending")
+        .add_attribute("withdrawal_id", id.to_string())
+        .add_attribute("to", address);
+    Ok(Some(resp))
+}
+
+// The contract's usei that isn't owed to withdrawals waiting to be paid out.
+fn liquid_usei(deps: Deps, contract: &Addr) -> StdResult<u128> {
+    let reserved = reserved_for_withdrawals_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    let balance = deps.querier.query_balance(contract, COIN_DENOM)?.amount;
+    Ok(balance.u128().saturating_sub(reserved))
+}
+
+fn execute_withdraw_ibc(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    remote_address: String,
+    amount: u128,
+    timeout: Timestamp,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let address = info.sender;
+    if amount == 0 {
+        return Err(ContractError::EmptyWithdrawQuantity {
+            withdraw_quantity: amount,
+        });
+    };
+    if timeout <= env.block.time {
+        return Err(ContractError::InvalidIbcTimeout {});
+    }
+    // A transfer can't wait for co-signers or a timelock, so it has to be small
+    // enough to skip them.
+    let limits = config_read(deps.storage).load()?.withdrawal_limits;
+    let held_for_approval =
+        match withdrawal_policies_read(deps.storage).may_load(address.as_bytes())? {
+            Some(policy) => needs_approval(
+                deps.storage,
+                &limits,
+                &policy,
+                &address,
+                COIN_DENOM,
+                env.block.time,
+                amount,
+            )?,
+            None => false,
+        };
+    let timelocked = limits
+        .timelock
+        .as_ref()
+        .is_some_and(|timelock| amount > timelock.above);
+    if held_for_approval || timelocked {
+        return Err(ContractError::IbcWithdrawalHeld {});
+    }
+    record_withdrawal(deps.storage, &limits, &address, env.block.time, amount)?;
+    settle_streams_for_recipient(deps.storage, &address, env.block.time)?;
+    decrease_coins_at_address(deps.storage, &address, amount)?;
+
+    let transfer = IbcTransfer {
+        account: address.clone(),
+        amount,
+        channel_id: channel_id.clone(),
+        remote_address: remote_address.clone(),
+        counted_at: Some(env.block.time),
+    };
+    ibc_transfer_in_flight(deps.storage).save(&transfer)?;
+    // The memo has ibc-hooks tell the contract how the transfer went, through
+    // `SudoMsg::IbcLifecycleComplete`.
+    let memo = format!(r#"{{"ibc_callback":"{}"}}"#, env.contract.address);
+    let msg = MsgTransfer {
+        source_port: String::from("transfer"),
+        source_channel: channel_id.clone(),
+        token: Coin {
+            denom: COIN_DENOM.to_string(),
+            amount: Uint128::from(amount),
+        },
+        sender: env.contract.address.to_string(),
+        receiver: remote_address.clone(),
+        timeout_timestamp: timeout,
+        memo,
+    }
+    .into_cosmos_msg();
+    let resp = Response::new()
+        .add_submessage(SubMsg::reply_on_success(msg, IBC_TRANSFER_REPLY_ID))
+        .add_attribute("action", "withdraw_ibc")
+        .add_attribute("account", address)
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("to", remote_address);
+    Ok(resp)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(mut deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    let height = env.block.height;
+    let resp = match msg.id {
+        IBC_TRANSFER_REPLY_ID => reply_ibc_transfer(deps.branch(), msg.result),
+        RECEIVE_SPLIT_REPLY_ID => reply_receive_split(msg.result),
+        id if id >= WITHDRAWAL_REPLY_ID_OFFSET => reply_withdrawal(
+            deps.branch(),
+            env,
+            id - WITHDRAWAL_REPLY_ID_OFFSET,
+            msg.result,
+        ),
+        id => Err(ContractError::UnknownReplyId { id }),
+    }?;
+    add_balance_hooks(deps.storage, height, resp)
+}
+
+// A recipient contract's `ReceiveSplit` callback failed. It's only logged, leaving the
+// recipient's credit in place.
+fn reply_receive_split(result: SubMsgResult) -> Result<Response, ContractError> {
+    let resp = match result {
+        SubMsgResult::Err(error) => Response::new()
+            .add_attribute("action", "receive_split_failed")
+            .add_attribute("error", error),
+        SubMsgResult::Ok(_) => Response::new(),
+    };
+    Ok(resp)
+}
+
+// A withdrawal's bank send finished, so forget it. If it failed, give the coins back
+// to the account and log why, and otherwise pay the bounty it earned, if any.
+fn reply_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    attempt_id: u64,
+    result: SubMsgResult,
+) -> Result<Response, ContractError> {
+    let key = attempt_id.to_be_bytes();
+    let attempt = withdrawals_in_flight_read(deps.storage).load(&key)?;
+    withdrawals_in_flight(deps.storage).remove(&key);
+    let error = match result {
+        SubMsgResult::Err(error) => error,
+        SubMsgResult::Ok(_) => return pay_payout_bounty(deps, attempt.bounty),
+    };
+    credit_denom(
+        deps.storage,
+        &attempt.denom,
+        attempt.account.clone(),
+        attempt.amount,
+    )?;
+    if let Some(counted_at) = attempt.counted_at {
+        let limits = config_read(deps.storage).load()?.withdrawal_limits;
+        unrecord_withdrawal(
+            deps.storage,
+            &limits,
+            &attempt.account,
+            counted_at,
+            attempt.amount,
+        )?;
+    }
+
+    let failed = FailedWithdrawal {
+        id: attempt_id,
+        account: attempt.account,
+        denom: attempt.denom,
+        amount: attempt.amount,
+        error,
+        time: env.block.time,
+    };
+    failed_withdrawals(deps.storage).save(&key, &failed)?;
+    failed_withdrawals_by_account(deps.storage, &failed.account).save(&key, &attempt_id)?;
+    let resp = Response::new()
+        .add_attribute("action", "withdraw_failed")
+        .add_attribute("account", failed.account)
+        .add_attribute("quantity", failed.amount.to_string())
+        .add_attribute("error", failed.error);
+    Ok(resp)
+}
+
+// Pay the caller of `PushPayouts` its bounty for a payout that went through, capped
+// at what the owner has collected in fees by now.
+fn pay_payout_bounty(
+    deps: DepsMut,
+    bounty: Option<(Addr, u128)>,
+) -> Result<Response, ContractError> {
+    let (caller, bounty) = match bounty {
+        Some(bounty) => bounty,
+        None => return Ok(Response::new()),
+    };
+    let owner = config_read(deps.storage).load()?.owner;
+    let bounty = bounty.min(get_account_balance(deps.as_ref(), &owner)?);
+    if bounty > 0 {
+        decrease_coins_at_address(deps.storage, &owner, bounty)?;
+        increase_coins_at_address(deps.storage, caller.clone(), bounty)?;
+    }
+    let resp = Response::new()
+        .add_attribute("action", "payout_bounty")
+        .add_attribute("to", caller)
+        .add_attribute("bounty", bounty.to_string());
+    Ok(resp)
+}
+
+// Store the transfer under the sequence of its packet, so it can be found again
+// when the packet is acknowledged or times out.
+fn reply_ibc_transfer(deps: DepsMut, result: SubMsgResult) -> Result<Response, ContractError> {
+    let response = result.into_result().map_err(StdError::generic_err)?;
+    let transfer = ibc_transfer_in_flight(deps.storage).load()?;
+    ibc_transfer_in_flight(deps.storage).remove();
+
+    let sequence = response
+        .events
+        .iter()
+        .filter(|event| event.ty == "send_packet")
+        .flat_map(|event| event.attributes.iter())
+        .find(|attr| attr.key == "packet_sequence")
+        .ok_or_else(|| StdError::generic_err("send_packet event has no packet_sequence"))?
+        .value
+        .parse::<u64>()
+        .map_err(|err| StdError::parse_err("u64", err))?;
+    ibc_transfers(deps.storage, &transfer.channel_id).save(&sequence.to_be_bytes(), &transfer)?;
+    let resp = Response::new()
+        .add_attribute("action", "ibc_transfer_sent")
+        .add_attribute("channel_id", transfer.channel_id)
+        .add_attribute("sequence", sequence.to_string());
+    Ok(resp)
+}
+
+fn execute_update_staking(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    staking: StakingConfig,
+) -> Result<Response, ContractError> {
+    assert_has_role(deps.storage, Role::Treasury, &info.sender)?;
+    if staking.stake_percent > 10000 {
+        return Err(ContractError::StakePercentTooLarge {
+            stake_percent: staking.stake_percent,
+        });
+    }
+    for validator in &staking.validators {
+        if deps.querier.query_validator(validator)?.is_none() {
+            return Err(ContractError::UnknownValidator {
+                validator: validator.clone(),
+            });
+        }
+    }
+    staking_config(deps.storage).save(&staking)?;
+    let resp = Response::new()
+        .add_attribute("action", "update_staking")
+        .add_attribute("enabled", staking.enabled.to_string())
+        .add_attribute("stake_percent", staking.stake_percent.to_string());
+    Ok(resp)
+}
+
+// Moves the contract's delegations back towards the staked share of the usei owed
+// to account balances, claiming the rewards so far on the way. The usei held for
+// escrows, streams, distributions and vouchers is never staked. With staking disabled, this undelegates
+// everything. Only the treasury can do this, since it decides when the contract's
+// coins are locked up for the unbonding period.
+fn execute_rebalance(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    assert_has_role(deps.storage, Role::Treasury, &info.sender)?;
+    let staking = staking_config_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    let contract = env.contract.address.clone();
+    let resp = claim_rewards(deps.branch(), &contract)?;
+
+    let mut delegations = deps.querier.query_all_delegations(&contract)?;
+    let delegated: u128 = delegations
+        .iter()
+        .map(|delegation| delegation.amount.amount.u128())
+        .sum();
+    let mut unbondings = unbonding_read(deps.storage).may_load()?.unwrap_or_default();
+    unbondings.retain(|unbonding| unbonding.completes > env.block.time);
+    // Held withdrawals have already left the balances, so they aren't staked.
+    let liabilities = total_balance_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+
+    let target = if staking.enabled {
+        liabilities * staking.stake_percent / 10000
+    } else {
+        0
+    };
+    let mut msgs: Vec<StakingMsg> = vec![];
+    if target > delegated && !staking.validators.is_empty() {
+        // Coins that are still unbonding, or owed to held withdrawals, can't be
+        // delegated.
+        let liquid = liquid_usei(deps.as_ref(), &contract)?;
+        let quantity = (target - delegated).min(liquid);
+        let validator_count = staking.validators.len() as u128;
+        for (i, validator) in staking.validators.iter().enumerate() {
+            let mut share = quantity / validator_count;
+            if i == 0 {
+                share += quantity % validator_count;
+            }
+            if share > 0 {
+                msgs.push(StakingMsg::Delegate {
+                    validator: validator.clone(),
+                    amount: coin(share, COIN_DENOM),
+                });
+            }
+        }
+    } else if target < delegated {
+        msgs = undelegate(
+            &staking,
+            &mut delegations,
+            &mut unbondings,
+            delegated - target,
+            env.block.time.plus_seconds(staking.unbonding_seconds),
+        );
+    }
+    unbonding(deps.storage).save(&unbondings)?;
+
+    let resp = resp
+        .add_messages(msgs)
+        .add_attribute("action", "rebalance")
+        .add_attribute("delegated", delegated.to_string())
+        .add_attribute("target", target.to_string());
+    Ok(resp)
+}
+
+// Undelegates `quantity` usei, leaving validators that are no longer configured
+// first, and records it as unbonding until `completes`.
+fn undelegate(
+    staking: &StakingConfig,
+    delegations: &mut [Delegation],
+    unbondings: &mut Vec<Unbonding>,
+    quantity: u128,
+    completes: Timestamp,
+) -> Vec<StakingMsg> {
+    delegations.sort_by_key(|delegation| staking.validators.contains(&delegation.validator));
+    let mut msgs = vec![];
+    let mut remaining = quantity;
+    for delegation in delegations.iter() {
+        let quantity = remaining.min(delegation.amount.amount.u128());
+        if quantity == 0 {
+            break;
+        }
+        msgs.push(StakingMsg::Undelegate {
+            validator: delegation.validator.clone(),
+            amount: coin(quantity, COIN_DENOM),
+        });
+        unbondings.push(Unbonding {
+            amount: quantity,
+            completes,
+        });
+        remaining -= quantity;
+    }
+    msgs
+}
+
+fn execute_claim_rewards(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let resp = claim_rewards(deps, &env.contract.address)?.add_attribute("action", "claim_rewards");
+    Ok(resp)
+}
+
+// Withdraw the usei rewards from every delegation, and share them out pro rata to
+// the account balances.
+fn claim_rewards(deps: DepsMut, contract: &Addr) -> Result<Response, ContractError> {
+    let mut resp = Response::new();
+    let mut rewards: u128 = 0;
+    for delegation in deps.querier.query_all_delegations(contract)? {
+        let accumulated: u128 = deps
+            .querier
+            .query_delegation(contract, &delegation.validator)?
+            .map(|full| {
+                full.accumulated_rewards
+                    .iter()
+                    .filter(|reward| reward.denom == COIN_DENOM)
+                    .map(|reward| reward.amount.u128())
+                    .sum()
+            })
+            .unwrap_or_default();
+        if accumulated > 0 {
+            resp = resp.add_message(DistributionMsg::WithdrawDelegatorReward {
+                validator: delegation.validator,
+            });
+            rewards += accumulated;
+        }
+    }
+    distribute_rewards(deps.storage, rewards)?;
+    Ok(resp.add_attribute("rewards", rewards.to_string()))
+}
+
+// Raise the reward index so every balance earns its share of `rewards`, which is
+// settled into the balance the next time it changes.
+fn distribute_rewards(storage: &mut dyn Storage, rewards: u128) -> Result<(), ContractError> {
+    if rewards == 0 {
+        return Ok(());
+    }
+    let total = total_balance_read(storage).may_load()?.unwrap_or_default();
+    if total == 0 {
+        // There's nobody to share with, so the owner keeps the rewards.
+        let owner = config_read(storage).load()?.owner;
+        increase_coins_at_address(storage, owner, rewards)?;
+        return Ok(());
+    }
+    let index = reward_index_read(storage).may_load()?.unwrap_or_default();
+    reward_index(storage).save(&(index + Decimal::from_ratio(rewards, total)))?;
+    // The rewards don't show up in the balances until they're settled, but they
+    // already count towards the total the next rewards are shared over.
+    update_total_balance(storage, |total| total + rewards)?;
+    Ok(())
+}
+
+// Count a withdrawal against the per-account and global limits, failing if it would
+// go over either of them.
+fn record_withdrawal(
+    storage: &mut dyn Storage,
+    limits: &WithdrawalLimits,
+    account: &Addr,
+    now: Timestamp,
+    quantity: u128,
+) -> Result<(), ContractError> {
+    if let Some(limit) = limits.per_account {
+        let mut history = withdrawal_history_read(storage)
+            .may_load(account.as_bytes())?
+            .unwrap_or_default();
+        add_to_window(&mut history, limits.window_seconds, limit, now, quantity)?;
+        withdrawal_history(storage).save(account.as_bytes(), &history)?;
+    }
+    if let Some(limit) = limits.global {
+        let mut history = global_withdrawal_history_read(storage)
+            .may_load()?
+            .unwrap_or_default();
+        add_to_window(&mut history, limits.window_seconds, limit, now, quantity)?;
+        global_withdrawal_history(storage).save(&history)?;
+    }
+    Ok(())
+}
+
+// Whether a withdrawal would take more than the policy's `approval_above` out of the
+// account without approval within the window. If it wouldn't, it's counted towards
+// that, so the threshold can't be dodged with a run of smaller withdrawals.
+fn needs_approval(
+    storage: &mut dyn Storage,
+    limits: &WithdrawalLimits,
+    policy: &WithdrawalPolicy,
+    account: &Addr,
+    denom: &str,
+    now: Timestamp,
+    quantity: u128,
+) -> Result<bool, ContractError> {
+    let mut history = unapproved_withdrawal_history_read(storage, account)
+        .may_load(denom.as_bytes())?
+        .unwrap_or_default();
+    match add_to_window(
+        &mut history,
+        limits.window_seconds,
+        policy.approval_above,
+        now,
+        quantity,
+    ) {
+        Ok(()) => {
+            unapproved_withdrawal_history(storage, account).save(denom.as_bytes(), &history)?;
+            Ok(false)
+        }
+        Err(ContractError::WithdrawalLimitExceeded { .. }) => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+// Take a failed withdrawal counted at `counted_at` back out of the limits, if its
+// interval is still in the window.
+pub(crate) fn unrecord_withdrawal(
+    storage: &mut dyn Storage,
+    limits: &WithdrawalLimits,
+    account: &Addr,
+    counted_at: Timestamp,
+    quantity: u128,
+) -> StdResult<()> {
+    let interval = counted_at.seconds() / interval_seconds(limits.window_seconds);
+    if limits.per_account.is_some() {
+        let mut history = withdrawal_history_read(storage)
+            .may_load(account.as_bytes())?
+            .unwrap_or_default();
+        remove_from_window(&mut history, interval, quantity);
+        withdrawal_history(storage).save(account.as_bytes(), &history)?;
+    }
+    if limits.global.is_some() {
+        let mut history = global_withdrawal_history_read(storage)
+            .may_load()?
+            .unwrap_or_default();
+        remove_from_window(&mut history, interval, quantity);
+        global_withdrawal_history(storage).save(&history)?;
+    }
+    Ok(())
+}
+
+fn interval_seconds(window_seconds: u64) -> u64 {
+    (window_seconds / WITHDRAWAL_WINDOW_INTERVALS).max(1)
+}
+
+// Drop the intervals that have left the rolling window, then add the withdrawal to
+// the current one if the total stays within `limit`.
+fn add_to_window(
+    history: &mut Vec<WithdrawalRecord>,
+    window_seconds: u64,
+    limit: u128,
+    now: Timestamp,
+    quantity: u128,
+) -> Result<(), ContractError> {
+    let interval_seconds = interval_seconds(window_seconds);
+    // An interval stays in the window until a whole window has passed since it ended,
+    // so no window ever sees more than the limit withdrawn.
+    let leaves_window = |interval: u64| (interval + 1) * interval_seconds + window_seconds;
+    history.retain(|record| leaves_window(record.interval) > now.seconds());
+    let used: u128 = history.iter().map(|record| record.amount).sum();
+    if used + quantity > limit {
+        // The allowance grows again as soon as the oldest interval leaves the window.
+        let resets_at = history.first().map_or(now, |record| {
+            Timestamp::from_seconds(leaves_window(record.interval))
+        });
+        return Err(ContractError::WithdrawalLimitExceeded {
+            remaining: limit.saturating_sub(used),
+            resets_at,
+        });
+    }
+    let interval = now.seconds() / interval_seconds;
+    match history.last_mut() {
+        Some(record) if record.interval == interval => record.amount += quantity,
+        _ => history.push(WithdrawalRecord {
+            interval,
+            amount: quantity,
+        }),
+    }
+    Ok(())
+}
+
+fn remove_from_window(history: &mut [WithdrawalRecord], interval: u64, quantity: u128) {
+    if let Some(record) = history
+        .iter_mut()
+        .find(|record| record.interval == interval)
+    {
+        record.amount = record.amount.saturating_sub(quantity);
+    }
+}
+
+fn decrease_coins_at_address(
+    storage: &mut dyn Storage,
+    valid_dest_addr: &Addr,
+    coin_quantity: u128,
+) -> Result<AccountBalance, ContractError> {
+    let old_balance = get_balance(storage, valid_dest_addr)?;
+    settle_rewards(storage, valid_dest_addr)?;
+    let key = valid_dest_addr.as_bytes();
+    let account_balance =
+        resolver(storage).update(key, |account_balance: Option<AccountBalance>| {
+            if let Some(mut account_balance) = account_balance {
+                if account_balance.balance >= coin_quantity {
+                    // Decrease the user balance if their balance exists and
+                    // their current balance is higher than the amount to decrease.
+                    account_balance.balance -= coin_quantity;
+                    Ok::<AccountBalance, ContractError>(account_balance)
+                } else {
+                    // Raise an error if the user balance would otherwise go negative.
+                    Err(ContractError::InsufficientFunds {
+                        withdraw_quantity: coin_quantity,
+                        balance: account_balance.balance,
+                    })
+                }
+            } else {
+                // Raise an error since a user without a balance would definitely go
+                // negative from any non-zero amount to decrease.
+                Err(ContractError::InsufficientFunds {
+                    withdraw_quantity: coin_quantity,
+                    balance: 0,
+                })
+            }
+        })?;
+    // Balances credited before the total was tracked aren't part of it.
+    update_total_balance(storage, |total| total.saturating_sub(coin_quantity))?;
+    record_balance_change(storage, &account_balance, old_balance)?;
+    Ok(account_balance)
+}
+
+fn get_balance(storage: &dyn Storage, address: &Addr) -> StdResult<u128> {
+    let balance = resolver_read(storage).may_load(address.as_bytes())?;
+    Ok(balance.map_or(0, |balance| balance.balance))
+}
+
+// Queue a balance change to be snapshotted and sent to the registered hooks once the
+// entry point that made it returns.
+fn record_balance_change(
+    storage: &mut dyn Storage,
+    account_balance: &AccountBalance,
+    old_balance: u128,
+) -> StdResult<()> {
+    if account_balance.balance == old_balance {
+        return Ok(());
+    }
+    let mut changes = balance_changes_read(storage)
+        .may_load()?
+        .unwrap_or_default();
+    changes.push(BalanceChangedHookMsg {
+        address: account_balance.address.to_string(),
+        old_balance,
+        new_balance: account_balance.balance,
+    });
+    balance_changes(storage).save(&changes)
+}
+
+// Snapshot the balance changes queued so far at `height`, and tell every hook about
+// each of them.
+pub(crate) fn take_balance_hook_msgs(
+    storage: &mut dyn Storage,
+    height: u64,
+) -> StdResult<Vec<CosmosMsg>> {
+    // Staking rewards move the total without changing any balance. The total isn't
+    // known until a backfill finishes.
+    let total = total_balance_read(storage).may_load()?.unwrap_or_default();
+    if backfill_read(storage).may_load()?.is_none()
+        && TOTAL_LIABILITIES.may_load(storage)?.unwrap_or_default() != total
+    {
+        TOTAL_LIABILITIES.save(storage, &total, height)?;
+    }
+    let changes = balance_changes_read(storage)
+        .may_load()?
+        .unwrap_or_default();
+    if changes.is_empty() {
+        return Ok(vec![]);
+    }
+    balance_changes(storage).remove();
+    let hooks = hooks_read(storage).may_load()?.unwrap_or_default();
+    let mut msgs = Vec::with_capacity(changes.len() * hooks.len());
+    for change in changes {
+        let address = Addr::unchecked(&change.address);
+        BALANCE_SNAPSHOTS.save(storage, &address, &change.new_balance, height)?;
+        track_activity(storage, &address, change.new_balance, height)?;
+        for hook in &hooks {
+            msgs.push(change.clone().into_cosmos_msg(hook)?);
+        }
+    }
+    Ok(msgs)
+}
+
+// Only accounts holding a balance are tracked for expiry. An account's inactivity
+// period starts when it's first credited, and an emptied account forgets who
+// credited it.
+fn track_activity(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    balance: u128,
+    height: u64,
+) -> StdResult<()> {
+    if balance > 0 {
+        if last_activity_read(storage)
+            .may_load(address.as_bytes())?
+            .is_none()
+        {
+            last_activity(storage).save(address.as_bytes(), &height)?;
+        }
+        return Ok(());
+    }
+    last_activity(storage).remove(address.as_bytes());
+    let senders = credit_sources_read(storage, address)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(sender, _)| sender))
+        .collect::<StdResult<Vec<Vec<u8>>>>()?;
+    for sender in senders {
+        credit_sources(storage, address).remove(&sender);
+    }
+    untracked_credit(storage).remove(address.as_bytes());
+    Ok(())
+}
+
+fn add_balance_hooks(
+    storage: &mut dyn Storage,
+    height: u64,
+    resp: Response,
+) -> Result<Response, ContractError> {
+    Ok(resp.add_messages(take_balance_hook_msgs(storage, height)?))
+}
+
+// Fold the staking rewards `address` earned since it was last settled into its balance.
+fn settle_rewards(storage: &mut dyn Storage, address: &Addr) -> StdResult<()> {
+    let key = address.as_bytes();
+    let index = reward_index_read(storage).may_load()?.unwrap_or_default();
+    let snapshot = reward_snapshots_read(storage)
+        .may_load(key)?
+        .unwrap_or_default();
+    if index == snapshot {
+        return Ok(());
+    }
+    reward_snapshots(storage).save(key, &index)?;
+    let balance = resolver_read(storage).may_load(key)?;
+    if let Some(mut balance) = balance {
+        // The total already counts the rewards, from when they were distributed.
+        let rewards = earned_rewards(balance.balance, index, snapshot);
+        if rewards > 0 {
+            let old_balance = balance.balance;
+            balance.balance += rewards;
+            resolver(storage).save(key, &balance)?;
+            record_balance_change(storage, &balance, old_balance)?;
+        }
+    }
+    Ok(())
+}
+
+fn update_total_balance(
+    storage: &mut dyn Storage,
+    update: impl FnOnce(u128) -> u128,
+) -> StdResult<()> {
+    let total = total_balance_read(storage).may_load()?.unwrap_or_default();
+    total_balance(storage).save(&update(total))
+}
+
+fn earned_rewards(balance: u128, index: Decimal, snapshot: Decimal) -> u128 {
+    (Uint128::from(balance) * (index - snapshot)).u128()
+}
+
+fn execute_set_withdrawal_policy(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    cosigners: Vec<String>,
+    threshold: u32,
+    approval_above: u128,
+    expiry_seconds: u64,
+) -> Result<Response, ContractError> {
+    // Once set, a policy can only be lifted by its co-signers, or else whoever holds
+    // the account key could simply remove it before withdrawing.
+    if withdrawal_policies_read(deps.storage)
+        .may_load(info.sender.as_bytes())?
+        .is_some()
+    {
+        return Err(ContractError::WithdrawalPolicyExists {});
+    }
+
+    let mut valid_cosigners: Vec<Addr> = Vec::with_capacity(cosigners.len());
+    for cosigner in cosigners {
+        let valid_cosigner = deps.api.addr_validate(&cosigner)?;
+        if !valid_cosigners.contains(&valid_cosigner) {
+            valid_cosigners.push(valid_cosigner);
+        }
+    }
+    let cosigner_count = valid_cosigners.len() as u32;
+    if threshold == 0 || threshold > cosigner_count {
+        return Err(ContractError::InvalidThreshold {
+            threshold,
+            cosigner_count,
+        });
+    }
+
+    let policy = WithdrawalPolicy {
+        cosigners: valid_cosigners,
+        threshold,
+        approval_above,
+        expiry_seconds,
+        removal_approvals: vec![],
+    };
+    withdrawal_policies(deps.storage).save(info.sender.as_bytes(), &policy)?;
+    let resp = Response::new()
+        .add_attribute("action", "set_withdrawal_policy")
+        .add_attribute("account", info.sender)
+        .add_attribute("threshold", threshold.to_string());
+    Ok(resp)
+}
+
+fn execute_approve_policy_removal(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    account: String,
+) -> Result<Response, ContractError> {
+    let valid_account = deps.api.addr_validate(&account)?;
+    let mut policy = withdrawal_policies_read(deps.storage)
+        .may_load(valid_account.as_bytes())?
+        .ok_or(ContractError::NoWithdrawalPolicy {})?;
+    record_approval(
+        &policy.cosigners,
+        &mut policy.removal_approvals,
+        &info.sender,
+    )?;
+
+    let removed = policy.removal_approvals.len() as u32 >= policy.threshold;
+    if removed {
+        withdrawal_policies(deps.storage).remove(valid_account.as_bytes());
+    } else {
+        withdrawal_policies(deps.storage).save(valid_account.as_bytes(), &policy)?;
+    }
+    let resp = Response::new()
+        .add_attribute("action", "approve_policy_removal")
+        .add_attribute("account", valid_account)
+        .add_attribute("removed", removed.to_string());
+    Ok(resp)
+}
+
+// Add `cosigner`'s approval, making sure they're allowed to give it and only do so once.
+fn record_approval(
+    cosigners: &[Addr],
+    approvals: &mut Vec<Addr>,
+    cosigner: &Addr,
+) -> Result<(), ContractError> {
+    if !cosigners.contains(cosigner) {
+        return Err(ContractError::NotCosigner {});
+    }
+    if approvals.contains(cosigner) {
+        return Err(ContractError::AlreadyApproved {});
+    }
+    approvals.push(cosigner.clone());
+    Ok(())
+}
+
+fn execute_approve_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    withdrawal_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let mut pending = pending_withdrawals_read(deps.storage).load(&withdrawal_id.to_be_bytes())?;
+    if pending.is_expired(env.block.time) {
+        return Err(ContractError::WithdrawalExpired { withdrawal_id });
+    }
+    let policy = withdrawal_policies_read(deps.storage)
+        .may_load(pending.account.as_bytes())?
+        .ok_or(ContractError::NoWithdrawalPolicy {})?;
+    record_approval(&policy.cosigners, &mut pending.approvals, &info.sender)?;
+
+    let mut resp = Response::new()
+        .add_attribute("action", "approve_withdrawal")
+        .add_attribute("withdrawal_id", withdrawal_id.to_string());
+    // A timelocked withdrawal has to be claimed once it unlocks.
+    if pending.is_approved() && pending.unlocks_at <= env.block.time {
+        remove_pending_withdrawal(deps.storage, &pending)?;
+        resp = resp
+            .add_submessage(dispatch_withdrawal(
+                deps.storage,
+                &pending.account,
+                &pending.denom,
+                pending.amount,
+                None,
+            )?)
+            .add_attribute("to", pending.account);
+    } else {
+        pending_withdrawals(deps.storage).save(&withdrawal_id.to_be_bytes(), &pending)?;
+    }
+    Ok(resp)
+}
+
+fn execute_cancel_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    withdrawal_id: u64,
+) -> Result<Response, ContractError> {
+    let pending = pending_withdrawals_read(deps.storage).load(&withdrawal_id.to_be_bytes())?;
+    // Anyone can clean up an expired withdrawal, but only the account can change
+    // its mind about one that's still waiting.
+    if pending.account != info.sender && !pending.is_expired(env.block.time) {
+        return Err(ContractError::CannotCancelWithdrawal { withdrawal_id });
+    }
+    remove_pending_withdrawal(deps.storage, &pending)?;
+    credit_denom(
+        deps.storage,
+        &pending.denom,
+        pending.account.clone(),
+        pending.amount,
+    )?;
+    // The coins never left, so they stop counting against the withdrawal limits.
+    if let Some(counted_at) = pending.counted_at {
+        let limits = config_read(deps.storage).load()?.withdrawal_limits;
+        unrecord_withdrawal(
+            deps.storage,
+            &limits,
+            &pending.account,
+            counted_at,
+            pending.amount,
+        )?;
+    }
+    let resp = Response::new()
+        .add_attribute("action", "cancel_withdrawal")
+        .add_attribute("withdrawal_id", withdrawal_id.to_string())
+        .add_attribute("account", pending.account);
+    Ok(resp)
+}
+
+fn execute_claim_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    withdrawal_id: u64,
+) -> Result<Response, ContractError> {
+    assert_not_paused(&config_read(deps.storage).load()?)?;
+    let pending = pending_withdrawals_read(deps.storage).load(&withdrawal_id.to_be_bytes())?;
+    if !pending.is_approved() {
+        return Err(ContractError::WithdrawalNotApproved { withdrawal_id });
+    }
+    if pending.unlocks_at > env.block.time {
+        return Err(ContractError::WithdrawalLocked {
+            withdrawal_id,
+            unlocks_at: pending.unlocks_at,
+        });
+    }
+    remove_pending_withdrawal(deps.storage, &pending)?;
+    let resp = Response::new()
+        .add_submessage(dispatch_withdrawal(
+            deps.storage,
+            &pending.account,
+            &pending.denom,
+            pending.amount,
+            None,
+        )?)
+        .add_attribute("action", "claim_withdrawal")
+        .add_attribute("withdrawal_id", withdrawal_id.to_string())
+        .add_attribute("to", pending.account);
+    Ok(resp)
+}
+
+fn save_pending_withdrawal(
+    storage: &mut dyn Storage,
+    pending: &PendingWithdrawal,
+) -> StdResult<()> {
+    pending_withdrawals(storage).save(&pending.id.to_be_bytes(), pending)?;
+    pending_withdrawals_by_account(storage, &pending.account)
+        .save(&pending.id.to_be_bytes(), &pending.id)?;
+    if pending.denom == COIN_DENOM {
+        let reserved = reserved_for_withdrawals_read(storage)
+            .may_load()?
+            .unwrap_or_default();
+        reserved_for_withdrawals(storage).save(&(reserved + pending.amount))?;
+    }
+    Ok(())
+}
+
+fn remove_pending_withdrawal(
+    storage: &mut dyn Storage,
+    pending: &PendingWithdrawal,
+) -> StdResult<()> {
+    pending_withdrawals(storage).remove(&pending.id.to_be_bytes());
+    pending_withdrawals_by_account(storage, &pending.account).remove(&pending.id.to_be_bytes());
+    if pending.denom == COIN_DENOM {
+        let reserved = reserved_for_withdrawals_read(storage)
+            .may_load()?
+            .unwrap_or_default();
+        reserved_for_withdrawals(storage).save(&reserved.saturating_sub(pending.amount))?;
+    }
+    Ok(())
+}
+
+// Send a withdrawal so that a failing bank send is handed to `reply`, which puts the
+// coins back, rather than reverting the whole transaction. A withdrawal released
+// from pending was counted against the rate limits when it was requested, and is
+// passed no `counted_at`, since it's usually left the window by then.
+fn dispatch_withdrawal(
+    storage: &mut dyn Storage,
+    account: &Addr,
+    denom: &str,
+    quantity: u128,
+    counted_at: Option<Timestamp>,
+) -> Result<SubMsg, ContractError> {
+    let attempt = WithdrawalAttempt {
+        account: account.clone(),
+        denom: denom.to_string(),
+        amount: quantity,
+        counted_at,
+        bounty: None,
+    };
+    dispatch_attempt(storage, attempt)
+}
+
+fn dispatch_attempt(
+    storage: &mut dyn Storage,
+    attempt: WithdrawalAttempt,
+) -> Result<SubMsg, ContractError> {
+    let id = next_id(storage, WITHDRAWAL_ATTEMPT_COUNT_KEY)?;
+    withdrawals_in_flight(storage).save(&id.to_be_bytes(), &attempt)?;
+    let msg = BankMsg::Send {
+        to_address: attempt.account.into(),
+        amount: vec![Coin {
+            denom: attempt.denom,
+            amount: Uint128::from(attempt.amount),
+        }],
+    };
+    // Replied to either way, so the attempt is forgotten once the send has run.
+    Ok(SubMsg::reply_always(msg, WITHDRAWAL_REPLY_ID_OFFSET + id))
+}
+
+fn send_usei(to_address: &Addr, quantity: u128) -> BankMsg {
+    BankMsg::Send {
+        to_address: to_address.into(),
+        amount: vec![Coin {
+            denom: COIN_DENOM.to_string(),
+            amount: Uint128::from(quantity),
+        }],
+    }
+}
+
+// Extract the configured coin amount from the user's transaction.
+fn get_coin_quantity_sent_in_message(info: &MessageInfo) -> u128 {
+    let coins_sent = info.funds.iter().find(|coin| coin.denom == COIN_DENOM);
+    match coins_sent {
+        Some(coins) => coins.amount.u128(),
+        None => 0,
+    }
+}
+
+// Governance can step in through sudo, for instance if the owner key is lost. Each
+// action is recorded in a `sudo` event.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(mut deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    // ibc-hooks doesn't retry a callback that fails, so a failed transfer has to be
+    // restored even mid-backfill.
+    if !matches!(msg, SudoMsg::IbcLifecycleComplete(_)) {
+        assert_not_migrating(deps.storage)?;
+    }
+    let height = env.block.height;
+    let (resp, event) = match msg {
+        SudoMsg::UpdateOwner { owner } => sudo_update_owner(deps.branch(), env, owner)?,
+        SudoMsg::UpdateFee {
+            flat_fee,
+            percent_fee,
+        } => (
+            update_fee(deps.storage, flat_fee, percent_fee)?,
+            Event::new("sudo")
+                .add_attribute("action", "update_fee")
+                .add_attribute("flat_fee", flat_fee.to_string())
+                .add_attribute("percent_fee", percent_fee.to_string()),
+        ),
+        SudoMsg::SetPaused { paused } => (
+            set_paused(deps.storage, paused)?,
+            Event::new("sudo")
+                .add_attribute("action", "set_paused")
+                .add_attribute("paused", paused.to_string()),
+        ),
+        SudoMsg::ForceRefund { address } => sudo_force_refund(deps.branch(), env, address)?,
+        SudoMsg::IbcLifecycleComplete(complete) => {
+            ibc_lifecycle_complete(deps.branch(), env, complete)?
+        }
+    };
+    add_balance_hooks(deps.storage, height, resp.add_event(event))
+}
+
+// Hand the contract to a new owner, moving every role the old owner held with it.
+fn sudo_update_owner(
+    deps: DepsMut,
+    _env: Env,
+    owner: String,
+) -> Result<(Response, Event), ContractError> {
+    let valid_owner = deps.api.addr_validate(&owner)?;
+    let previous_owner = config_read(deps.storage).load()?.owner;
+    for role in Role::ALL {
+        if has_role(deps.storage, role, &previous_owner)? {
+            role_members(deps.storage, role).remove(previous_owner.as_bytes());
+            role_members(deps.storage, role).save(valid_owner.as_bytes(), &valid_owner)?;
+        }
+    }
+    let resp = set_owner(deps.storage, valid_owner.clone())?;
+    let event = Event::new("sudo")
+        .add_attribute("action", "update_owner")
+        .add_attribute("previous_owner", previous_owner)
+        .add_attribute("owner", valid_owner);
+    Ok((resp, event))
+}
+
+// Pay out an account's whole balance, in every denom, along with any withdrawals it
+// has waiting, whatever the pause, policy and limits say.
+fn sudo_force_refund(
+    deps: DepsMut,
+    env: Env,
+    address: String,
+) -> Result<(Response, Event), ContractError> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    settle_streams_for_recipient(deps.storage, &valid_address, env.block.time)?;
+    let balance = get_account_balance(deps.as_ref(), &valid_address)?;
+    let mut resp = Response::new()
+        .add_attribute("action", "force_refund")
+        .add_attribute("to", valid_address.clone());
+    if balance > 0 {
+        decrease_coins_at_address(deps.storage, &valid_address, balance)?;
+        resp = resp.add_submessage(dispatch_withdrawal(
+            deps.storage,
+            &valid_address,
+            COIN_DENOM,
+            balance,
+            None,
+        )?);
+    }
+    let denoms = denoms_by_account_read(deps.storage, &valid_address)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(_, denom)| denom))
+        .collect::<StdResult<Vec<String>>>()?;
+    for denom in denoms {
+        denoms_by_account(deps.storage, &valid_address).remove(denom.as_bytes());
+        let quantity = denom_balances_read(deps.storage, &denom)
+            .may_load(valid_address.as_bytes())?
+            .unwrap_or_default();
+        if quantity == 0 {
+            continue;
+        }
+        denom_balances(deps.storage, &denom).remove(valid_address.as_bytes());
+        resp = resp
+            .add_submessage(dispatch_withdrawal(
+                deps.storage,
+                &valid_address,
+                &denom,
+                quantity,
+                None,
+            )?)
+            .add_attribute(denom, quantity.to_string());
+    }
+    let pending_ids = pending_withdrawals_by_account_read(deps.storage, &valid_address)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(_, id)| id))
+        .collect::<StdResult<Vec<u64>>>()?;
+    for id in pending_ids {
+        let pending = pending_withdrawals_read(deps.storage).load(&id.to_be_bytes())?;
+        remove_pending_withdrawal(deps.storage, &pending)?;
+        resp = resp
+            .add_submessage(dispatch_withdrawal(
+                deps.storage,
+                &pending.account,
+                &pending.denom,
+                pending.amount,
+                None,
+            )?)
+            .add_attribute("withdrawal_id", id.to_string());
+    }
+    let event = Event::new("sudo")
+        .add_attribute("action", "force_refund")
+        .add_attribute("address", valid_address)
+        .add_attribute("quantity", balance.to_string());
+    Ok((resp, event))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetOwner {} => query_get_owner(deps, env, msg),
+        QueryMsg::Config {} => query_config(deps, env, msg),
+        QueryMsg::GetWithdrawableCoinQuantity { address } => {
+            query_get_account_balance(deps, env, address)
+        }
+        QueryMsg::HasRole { role, address } => query_has_role(deps, env, role, address),
+        QueryMsg::RoleMembers { role } => query_role_members(deps, env, role),
+        QueryMsg::SplitGroup { group_id } => query_split_group(deps, env, group_id),
+        QueryMsg::SplitGroupsByOwner {
+            owner,
+            start_after,
+            limit,
+        } => query_split_groups_by_owner(deps, env, owner, start_after, limit),
+        QueryMsg::Stream { stream_id } => query_stream(deps, env, stream_id),
+        QueryMsg::StreamsByRecipient {
+            recipient,
+            start_after,
+            limit,
+        } => query_streams_by_recipient(deps, env, recipient, start_after, limit),
+        QueryMsg::Escrow { escrow_id } => query_escrow(deps, env, escrow_id),
+        QueryMsg::EscrowsByArbiter {
+            arbiter,
+            start_after,
+            limit,
+        } => {
+            let valid_arbiter = deps.api.addr_validate(&arbiter)?;
+            let index = escrows_by_arbiter_read(deps.storage, &valid_arbiter);
+            query_escrows(deps, index, start_after, limit)
+        }
+        QueryMsg::EscrowsByParty {
+            party,
+            start_after,
+            limit,
+        } => {
+            let valid_party = deps.api.addr_validate(&party)?;
+            let index = escrows_by_party_read(deps.storage, &valid_party);
+            query_escrows(deps, index, start_after, limit)
+        }
+        QueryMsg::WithdrawalPolicy { account } => query_withdrawal_policy(deps, env, account),
+        QueryMsg::PendingWithdrawal { withdrawal_id } => {
+            query_pending_withdrawal(deps, env, withdrawal_id)
+        }
+        QueryMsg::PendingWithdrawals {
+            account,
+            start_after,
+            limit,
+        } => query_pending_withdrawals(deps, env, account, start_after, limit),
+        QueryMsg::IbcTransfer {
+            channel_id,
+            sequence,
+        } => query_ibc_transfer(deps, env, channel_id, sequence),
+        QueryMsg::DenomBalance { address, denom } => query_denom_balance(deps, env, address, denom),
+        QueryMsg::FailedWithdrawals {
+            account,
+            start_after,
+            limit,
+        } => query_failed_withdrawals(deps, env, account, start_after, limit),
+        QueryMsg::Staking {} => query_staking(deps, env),
+        QueryMsg::Hooks {} => query_hooks(deps, env),
+        QueryMsg::Send { send_id } => query_send(deps, env, send_id),
+        QueryMsg::Invoice { invoice_id } => query_invoice(deps, env, invoice_id),
+        QueryMsg::VoucherPool {} => query_voucher_pool(deps, env),
+        QueryMsg::ExpiringAccounts {
+            within_blocks,
+            start_after,
+            limit,
+        } => query_expiring_accounts(deps, env, within_blocks, start_after, limit),
+        QueryMsg::AutoPayout { address } => query_auto_payout(deps, env, address),
+        QueryMsg::VoucherNonce { nonce } => query_voucher_nonce(deps, env, nonce),
+        QueryMsg::MerkleDistribution { distribution_id } => {
+            query_merkle_distribution(deps, env, distribution_id)
+        }
+        QueryMsg::MerkleClaim {
+            distribution_id,
+            address,
+        } => query_merkle_claim(deps, env, distribution_id, address),
+        QueryMsg::InvoicesByCreator {
+            creator,
+            start_after,
+            limit,
+        } => {
+            let valid_creator = deps.api.addr_validate(&creator)?;
+            let index = invoices_by_creator_read(deps.storage, &valid_creator);
+            query_invoices(deps, index, start_after, limit)
+        }
+        QueryMsg::InvoicesByPayer {
+            payer,
+            start_after,
+            limit,
+        } => {
+            let valid_payer = deps.api.addr_validate(&payer)?;
+            let index = invoices_by_payer_read(deps.storage, &valid_payer);
+            query_invoices(deps, index, start_after, limit)
+        }
+        QueryMsg::InvoicesByStatus {
+            status,
+            start_after,
+            limit,
+        } => match status {
+            InvoiceStatus::Open => query_open_invoices(deps, env, start_after, limit),
+            _ => {
+                let index = invoices_by_status_read(deps.storage, status);
+                query_invoices(deps, index, start_after, limit)
+            }
+        },
+        QueryMsg::SendByReference {
+            sender,
+            reference_id,
+        } => query_send_by_reference(deps, env, sender, reference_id),
+        QueryMsg::BalanceAtHeight { address, height } => {
+            query_balance_at_height(deps, env, address, height)
+        }
+        QueryMsg::TotalLiabilitiesAtHeight { height } => {
+            query_total_liabilities_at_height(deps, env, height)
+        }
+    }
+}
+
+fn query_get_owner(deps: Deps, _env: Env, _msg: QueryMsg) -> StdResult<Binary> {
+    let config_data = config_read(deps.storage).load()?;
+    let resp = GetOwnerResponse {
+        owner: String::from(config_data.owner),
+    };
+    to_binary(&resp)
+}
+
+fn query_config(deps: Deps, _env: Env, _msg: QueryMsg) -> StdResult<Binary> {
+    let config_data = config_read(deps.storage).load()?;
+    to_binary(&config_data)
+}
+
+fn query_get_account_balance(deps: Deps, env: Env, address: String) -> StdResult<Binary> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    let streamed: u128 = load_streams_by_recipient(deps.storage, &valid_address)?
+        .iter()
+        .map(|stream| stream.unclaimed(env.block.time))
+        .sum();
+    let balance = get_account_balance(deps, &valid_address)? + streamed;
+    let resp = GetWithdrawableCoinQuantityResponse { address, balance };
+    to_binary(&resp)
+}
+
+fn get_account_balance(deps: Deps, valid_address: &Addr) -> Result<u128, StdError> {
+    let key = valid_address.as_bytes();
+    let balance = match resolver_read(deps.storage).may_load(key)? {
+        Some(ab) => ab.balance,
+        None => 0,
+    };
+    // Count the staking rewards that haven't been settled into the balance yet.
+    let index = reward_index_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    let snapshot = reward_snapshots_read(deps.storage)
+        .may_load(key)?
+        .unwrap_or_default();
+    Ok(balance + earned_rewards(balance, index, snapshot))
+}
+
+fn query_has_role(deps: Deps, _env: Env, role: Role, address: String) -> StdResult<Binary> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    let resp = HasRoleResponse {
+        role,
+        address,
+        has_role: has_role(deps.storage, role, &valid_address)?,
+    };
+    to_binary(&resp)
+}
+
+fn query_role_members(deps: Deps, _env: Env, role: Role) -> StdResult<Binary> {
+    let members = role_members_read(deps.storage, role)
+        .range(None, None, Order::Ascending)
+        .map(|item| item.map(|(_, member)| member.into_string()))
+        .collect::<StdResult<Vec<String>>>()?;
+    let resp = RoleMembersResponse { role, members };
+    to_binary(&resp)
+}
+
+// Read a page of the IDs in an index, in ascending order.
+fn paginate_ids(
+    index: ReadonlyBucket<u64>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<u64>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| (id + 1).to_be_bytes());
+    index
+        .range(start.as_ref().map(|key| &key[..]), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, id)| id))
+        .collect()
+}
+
+fn query_split_group(deps: Deps, _env: Env, group_id: u64) -> StdResult<Binary> {
+    let group = split_groups_read(deps.storage).load(&group_id.to_be_bytes())?;
+    to_binary(&group)
+}
+
+fn query_split_groups_by_owner(
+    deps: Deps,
+    _env: Env,
+    owner: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let valid_owner = deps.api.addr_validate(&owner)?;
+    let ids = paginate_ids(
+        split_groups_by_owner_read(deps.storage, &valid_owner),
+        start_after,
+        limit,
+    )?;
+    let groups = ids
+        .into_iter()
+        .map(|id| split_groups_read(deps.storage).load(&id.to_be_bytes()))
+        .collect::<StdResult<Vec<SplitGroup>>>()?;
+    to_binary(&SplitGroupsResponse { groups })
+}
+
+fn query_stream(deps: Deps, env: Env, stream_id: u64) -> StdResult<Binary> {
+    let stream = streams_read(deps.storage).load(&stream_id.to_be_bytes())?;
+    let resp = StreamResponse {
+        withdrawable: stream.unclaimed(env.block.time),
+        stream,
+    };
+    to_binary(&resp)
+}
+
+fn query_streams_by_recipient(
+    deps: Deps,
+    _env: Env,
+    recipient: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let valid_recipient = deps.api.addr_validate(&recipient)?;
+    let ids = paginate_ids(
+        streams_by_recipient_read(deps.storage, &valid_recipient),
+        start_after,
+        limit,
+    )?;
+    let streams = ids
+        .into_iter()
+        .map(|id| streams_read(deps.storage).load(&id.to_be_bytes()))
+        .collect::<StdResult<Vec<Stream>>>()?;
+    to_binary(&StreamsResponse { streams })
+}
+
+fn query_send(deps: Deps, _env: Env, send_id: u64) -> StdResult<Binary> {
+    let record = sends_read(deps.storage).load(&send_id.to_be_bytes())?;
+    to_binary(&record)
+}
+
+fn query_send_by_reference(
+    deps: Deps,
+    _env: Env,
+    sender: String,
+    reference_id: String,
+) -> StdResult<Binary> {
+    let valid_sender = deps.api.addr_validate(&sender)?;
+    let send_id =
+        sends_by_reference_read(deps.storage, &valid_sender).load(reference_id.as_bytes())?;
+    let record = sends_read(deps.storage).load(&send_id.to_be_bytes())?;
+    to_binary(&record)
+}
+
+fn query_auto_payout(deps: Deps, _env: Env, address: String) -> StdResult<Binary> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    let enabled = auto_payouts_read(deps.storage)
+        .may_load(valid_address.as_bytes())?
+        .unwrap_or(false);
+    to_binary(&AutoPayoutResponse { address, enabled })
+}
+
+// List the accounts with a balance that expires within `within_blocks` blocks, or
+// has already expired, in address order. At most `limit` accounts are looked at.
+fn query_expiring_accounts(
+    deps: Deps,
+    env: Env,
+    within_blocks: u64,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let config_data = config_read(deps.storage).load()?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    // Start just past `start_after`.
+    let start = start_after.map(|address| {
+        let mut key = address.into_bytes();
+        key.push(0);
+        key
+    });
+    let mut accounts = vec![];
+    let mut scanned = 0;
+    let mut next_start_after = None;
+    for item in last_activity_read(deps.storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+    {
+        let (key, last_active) = item?;
+        let address = Addr::unchecked(String::from_utf8_lossy(&key));
+        scanned += 1;
+        if scanned == limit {
+            next_start_after = Some(address.to_string());
+        }
+        let expires_at = match balance_expires_at(deps.storage, &config_data, &address)? {
+            Some(expires_at) if expires_at <= env.block.height + within_blocks => expires_at,
+            _ => continue,
+        };
+        let balance = get_account_balance(deps, &address)?;
+        if balance == 0 {
+            continue;
+        }
+        accounts.push(ExpiringAccount {
+            address: address.into_string(),
+            balance,
+            last_active,
+            expires_at,
+        });
+    }
+    to_binary(&ExpiringAccountsResponse {
+        accounts,
+        next_start_after,
+    })
+}
+
+fn query_voucher_pool(deps: Deps, _env: Env) -> StdResult<Binary> {
+    let pool = voucher_pool_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default();
+    to_binary(&pool)
+}
+
+fn query_voucher_nonce(deps: Deps, _env: Env, nonce: u64) -> StdResult<Binary> {
+    let used_at = used_voucher_nonces_read(deps.storage).may_load(&nonce.to_be_bytes())?;
+    to_binary(&VoucherNonceResponse { nonce, used_at })
+}
+
+fn query_merkle_distribution(deps: Deps, _env: Env, distribution_id: u64) -> StdResult<Binary> {
+    let distribution =
+        merkle_distributions_read(deps.storage).load(&distribution_id.to_be_bytes())?;
+    to_binary(&distribution)
+}
+
+fn query_merkle_claim(
+    deps: Deps,
+    _env: Env,
+    distribution_id: u64,
+    address: String,
+) -> StdResult<Binary> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    let claimed =
+        merkle_claims_read(deps.storage, distribution_id).may_load(valid_address.as_bytes())?;
+    to_binary(&MerkleClaimResponse {
+        distribution_id,
+        address,
+        claimed,
+    })
+}
+
+fn query_invoice(deps: Deps, _env: Env, invoice_id: u64) -> StdResult<Binary> {
+    let invoice = invoices_read(deps.storage).load(&invoice_id.to_be_bytes())?;
+    to_binary(&invoice)
+}
+
+fn query_invoices(
+    deps: Deps,
+    index: ReadonlyBucket<u64>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let ids = paginate_ids(index, start_after, Some(limit))?;
+    let next_start_after = (ids.len() == limit as usize)
+        .then(|| ids.last().copied())
+        .flatten();
+    let invoices = ids
+        .into_iter()
+        .map(|id| invoices_read(deps.storage).load(&id.to_be_bytes()))
+        .collect::<StdResult<Vec<Invoice>>>()?;
+    to_binary(&InvoicesResponse {
+        invoices,
+        next_start_after,
+    })
+}
+
+// Expired invoices keep the open status, but can't be paid, so they're left out.
+fn query_open_invoices(
+    deps: Deps,
+    env: Env,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let index = invoices_by_status_read(deps.storage, InvoiceStatus::Open);
+    // At most `limit` invoices are looked at, expired or not.
+    let ids = paginate_ids(index, start_after, Some(limit))?;
+    let next_start_after = (ids.len() == limit as usize)
+        .then(|| ids.last().copied())
+        .flatten();
+    let mut invoices = Vec::with_capacity(ids.len());
+    for id in ids {
+        let invoice = invoices_read(deps.storage).load(&id.to_be_bytes())?;
+        if !invoice.expired(env.block.time) {
+            invoices.push(invoice);
+        }
+    }
+    to_binary(&InvoicesResponse {
+        invoices,
+        next_start_after,
+    })
+}
+
+fn query_escrow(deps: Deps, _env: Env, escrow_id: u64) -> StdResult<Binary> {
+    let escrow = escrows_read(deps.storage).load(&escrow_id.to_be_bytes())?;
+    to_binary(&escrow)
+}
+
+fn query_escrows(
+    deps: Deps,
+    index: ReadonlyBucket<u64>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let escrows = paginate_ids(index, start_after, limit)?
+        .into_iter()
+        .map(|id| escrows_read(deps.storage).load(&id.to_be_bytes()))
+        .collect::<StdResult<Vec<Escrow>>>()?;
+    to_binary(&EscrowsResponse { escrows })
+}
+
+fn query_withdrawal_policy(deps: Deps, _env: Env, account: String) -> StdResult<Binary> {
+    let valid_account = deps.api.addr_validate(&account)?;
+    let policy = withdrawal_policies_read(deps.storage).may_load(valid_account.as_bytes())?;
+    to_binary(&WithdrawalPolicyResponse { policy })
+}
+
+fn query_pending_withdrawal(deps: Deps, _env: Env, withdrawal_id: u64) -> StdResult<Binary> {
+    let pending = pending_withdrawals_read(deps.storage).load(&withdrawal_id.to_be_bytes())?;
+    to_binary(&pending)
+}
+
+fn query_pending_withdrawals(
+    deps: Deps,
+    _env: Env,
+    account: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let valid_account = deps.api.addr_validate(&account)?;
+    let ids = paginate_ids(
+        pending_withdrawals_by_account_read(deps.storage, &valid_account),
+        start_after,
+        limit,
+    )?;
+    let withdrawals = ids
+        .into_iter()
+        .map(|id| pending_withdrawals_read(deps.storage).load(&id.to_be_bytes()))
+        .collect::<StdResult<Vec<PendingWithdrawal>>>()?;
+    to_binary(&PendingWithdrawalsResponse { withdrawals })
+}
+
+fn query_ibc_transfer(
+    deps: Deps,
+    _env: Env,
+    channel_id: String,
+    sequence: u64,
+) -> StdResult<Binary> {
+    let transfer = ibc_transfers_read(deps.storage, &channel_id).load(&sequence.to_be_bytes())?;
+    to_binary(&transfer)
+}
+
+fn query_denom_balance(deps: Deps, _env: Env, address: String, denom: String) -> StdResult<Binary> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    let balance = if denom == COIN_DENOM {
+        get_account_balance(deps, &valid_address)?
+    } else {
+        denom_balances_read(deps.storage, &denom)
+            .may_load(valid_address.as_bytes())?
+            .unwrap_or_default()
+    };
+    to_binary(&DenomBalanceResponse {
+        address,
+        denom,
+        balance,
+    })
+}
+
+fn query_failed_withdrawals(
+    deps: Deps,
+    _env: Env,
+    account: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let valid_account = deps.api.addr_validate(&account)?;
+    let ids = paginate_ids(
+        failed_withdrawals_by_account_read(deps.storage, &valid_account),
+        start_after,
+        limit,
+    )?;
+    let withdrawals = ids
+        .into_iter()
+        .map(|id| failed_withdrawals_read(deps.storage).load(&id.to_be_bytes()))
+        .collect::<StdResult<Vec<FailedWithdrawal>>>()?;
+    to_binary(&FailedWithdrawalsResponse { withdrawals })
+}
+
+fn query_staking(deps: Deps, _env: Env) -> StdResult<Binary> {
+    to_binary(&StakingResponse {
+        staking: staking_config_read(deps.storage)
+            .may_load()?
+            .unwrap_or_default(),
+        unbonding: unbonding_read(deps.storage).may_load()?.unwrap_or_default(),
+        reward_index: reward_index_read(deps.storage)
+            .may_load()?
+            .unwrap_or_default(),
+        total_balance: total_balance_read(deps.storage)
+            .may_load()?
+            .unwrap_or_default(),
+    })
+}
+
+// Snapshots hold the value from before the first change at a height, so the balance
+// at the end of `height` is the one snapshotted at the next height. Heights before
+// balances were first snapshotted read as zero. Staking rewards count once they're
+// settled into the balance.
+fn query_balance_at_height(
+    deps: Deps,
+    _env: Env,
+    address: String,
+    height: u64,
+) -> StdResult<Binary> {
+    let valid_address = deps.api.addr_validate(&address)?;
+    let balance = BALANCE_SNAPSHOTS
+        .may_load_at_height(deps.storage, &valid_address, height.saturating_add(1))?
+        .unwrap_or_default();
+    to_binary(&BalanceAtHeightResponse {
+        address,
+        height,
+        balance,
+    })
+}
+
+fn query_total_liabilities_at_height(deps: Deps, _env: Env, height: u64) -> StdResult<Binary> {
+    let total = TOTAL_LIABILITIES
+        .may_load_at_height(deps.storage, height.saturating_add(1))?
+        .unwrap_or_default();
+    to_binary(&TotalLiabilitiesAtHeightResponse { height, total })
+}
+
+fn query_hooks(deps: Deps, _env: Env) -> StdResult<Binary> {
+    let hooks = hooks_read(deps.storage)
+        .may_load()?
+        .unwrap_or_default()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    to_binary(&HooksResponse { hooks })
 }
@@ -1,11 +1,16 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{OverflowError, StdError, Timestamp};
 use thiserror::Error;
 
+use crate::state::Role;
+
 #[derive(Error, Debug)]
 pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
     #[error("Withdrawal quantity {withdraw_quantity:?} exceeds balance {balance:?}")]
     InsufficientFunds {
         withdraw_quantity: u128,
@@ -21,4 +26,243 @@ pub enum ContractError {
     // TODO: Could use better string formatting here.
     #[error("The percent fee must be below 100% but is {percent_fee:?}")]
     PercentFeeTooLarge { percent_fee: u128 },
+
+    #[error("Unauthorized: sender is missing the {role:?} role")]
+    Unauthorized { role: Role },
+
+    #[error("Can't revoke the last remaining admin")]
+    CannotRevokeLastAdmin {},
+
+    #[error("The contract is paused")]
+    ContractPaused {},
+
+    #[error("The sender can't be a destination of their own send")]
+    SelfSendNotAllowed {},
+
+    #[error("Destination {address:?} is named more than once")]
+    DuplicateRecipient { address: String },
+
+    #[error("A split group needs at least one member")]
+    EmptySplitGroup {},
+
+    #[error("Member {address:?} must have a non-zero weight")]
+    ZeroWeight { address: String },
+
+    #[error("Only the owner of split group {group_id:?} can change it")]
+    NotSplitGroupOwner { group_id: u64 },
+
+    #[error("A stream must end after it starts, and after the current block time")]
+    InvalidStreamSchedule {},
+
+    #[error(
+        "A stream must carry at least {minimum:?} coins after the contract fee, not {amount:?}"
+    )]
+    StreamTooSmall { amount: u128, minimum: u128 },
+
+    #[error("Only the sender or recipient of stream {stream_id:?} can cancel it")]
+    CannotCancelStream { stream_id: u64 },
+
+    #[error("An escrow's timeout must be after the current block time")]
+    InvalidEscrowTimeout {},
+
+    #[error("Only the arbiter of escrow {escrow_id:?} can release or refund it")]
+    NotEscrowArbiter { escrow_id: u64 },
+
+    #[error("Only the sender of escrow {escrow_id:?} can reclaim it")]
+    NotEscrowSender { escrow_id: u64 },
+
+    #[error("Escrow {escrow_id:?} has already been settled")]
+    EscrowSettled { escrow_id: u64 },
+
+    #[error("Escrow {escrow_id:?} can't be reclaimed before its timeout")]
+    EscrowNotTimedOut { escrow_id: u64 },
+
+    #[error("The account already has a withdrawal policy")]
+    WithdrawalPolicyExists {},
+
+    #[error("The account has no withdrawal policy")]
+    NoWithdrawalPolicy {},
+
+    #[error("Threshold {threshold:?} must be between 1 and the {cosigner_count:?} co-signers")]
+    InvalidThreshold { threshold: u32, cosigner_count: u32 },
+
+    #[error("Sender is not a co-signer of the account's withdrawal policy")]
+    NotCosigner {},
+
+    #[error("Sender has already approved")]
+    AlreadyApproved {},
+
+    #[error("Pending withdrawal {withdrawal_id:?} has expired")]
+    WithdrawalExpired { withdrawal_id: u64 },
+
+    #[error("Pending withdrawal {withdrawal_id:?} can only be cancelled by its account until it expires")]
+    CannotCancelWithdrawal { withdrawal_id: u64 },
+
+    #[error("Pending withdrawal {withdrawal_id:?} hasn't been approved by enough co-signers")]
+    WithdrawalNotApproved { withdrawal_id: u64 },
+
+    #[error("Pending withdrawal {withdrawal_id:?} is timelocked until {unlocks_at}")]
+    WithdrawalLocked {
+        withdrawal_id: u64,
+        unlocks_at: Timestamp,
+    },
+
+    #[error("The withdrawal limit window must be longer than 0 seconds")]
+    InvalidWithdrawalWindow {},
+
+    #[error("Withdrawal exceeds the limit, only {remaining:?} can be withdrawn until {resets_at}")]
+    WithdrawalLimitExceeded {
+        remaining: u128,
+        resets_at: Timestamp,
+    },
+
+    #[error("IBC transfer timeout must be in the future")]
+    InvalidIbcTimeout {},
+
+    #[error("Withdrawals that wait for co-signers or a timelock can't be sent over IBC")]
+    IbcWithdrawalHeld {},
+
+    #[error("No IBC transfer {sequence:?} on channel {channel_id}")]
+    UnknownIbcTransfer { channel_id: String, sequence: u64 },
+
+    #[error("Unexpected reply id {id:?}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Expected exactly one coin from the IBC transfer")]
+    InvalidIbcFunds {},
+
+    #[error("Split memo names no recipients")]
+    EmptySplitMemo {},
+
+    #[error("Stake percent must be at most 10000, got {stake_percent:?}")]
+    StakePercentTooLarge { stake_percent: u128 },
+
+    #[error("Unknown validator {validator}")]
+    UnknownValidator { validator: String },
+
+    #[error("Hook {hook} is already registered")]
+    HookAlreadyRegistered { hook: String },
+
+    #[error("Hook {hook} isn't registered")]
+    HookNotRegistered { hook: String },
+
+    #[error("No more than {max:?} hooks can be registered")]
+    TooManyHooks { max: usize },
+
+    #[error("Memo is longer than {max:?} bytes")]
+    MemoTooLong { max: usize },
+
+    #[error("Reference ID is longer than {max:?} bytes")]
+    ReferenceIdTooLong { max: usize },
+
+    #[error("A send with reference ID {reference_id} was already made")]
+    DuplicateReferenceId { reference_id: String },
+
+    #[error("An invoice must request a non-zero amount")]
+    EmptyInvoice {},
+
+    #[error("An invoice's expiry must be after the current block time")]
+    InvalidInvoiceExpiry {},
+
+    #[error("An invoice must be split between at least one recipient")]
+    EmptyInvoiceSplit {},
+
+    #[error("Invoice {invoice_id:?} has already been paid or cancelled")]
+    InvoiceNotOpen { invoice_id: u64 },
+
+    #[error("Invoice {invoice_id:?} has expired")]
+    InvoiceExpired { invoice_id: u64 },
+
+    #[error("The sender isn't one of the payers of invoice {invoice_id:?}")]
+    NotInvoicePayer { invoice_id: u64 },
+
+    #[error("Only the creator of invoice {invoice_id:?} can cancel it")]
+    NotInvoiceCreator { invoice_id: u64 },
+
+    #[error("Invoice asks for {expected:?} usei, but {received:?} were sent")]
+    WrongInvoicePayment { expected: u128, received: u128 },
+
+    #[error("The voucher key must be a compressed or uncompressed secp256k1 public key")]
+    InvalidVoucherKey {},
+
+    #[error("No voucher key has been published")]
+    NoVoucherKey {},
+
+    #[error("Voucher has expired")]
+    VoucherExpired {},
+
+    #[error("Voucher nonce {nonce:?} has already been claimed")]
+    VoucherNonceUsed { nonce: u64 },
+
+    #[error("Voucher signature doesn't match the voucher key")]
+    InvalidVoucherSignature {},
+
+    #[error("Voucher pool only holds {available:?} usei")]
+    InsufficientVoucherPool { available: u128 },
+
+    #[error("A merkle root must be a hex encoded SHA-256 hash")]
+    InvalidMerkleRoot {},
+
+    #[error("A distribution's expiry must be after the current block time")]
+    InvalidMerkleExpiry {},
+
+    #[error("Distribution is funded with {expected:?} usei, but {received:?} were sent")]
+    WrongMerkleFunding { expected: u128, received: u128 },
+
+    #[error("Merkle proof doesn't match the distribution's root")]
+    InvalidMerkleProof {},
+
+    #[error("The sender has already claimed from distribution {distribution_id:?}")]
+    MerkleAlreadyClaimed { distribution_id: u64 },
+
+    #[error("Distribution {distribution_id:?} has expired")]
+    MerkleDistributionExpired { distribution_id: u64 },
+
+    #[error("Distribution {distribution_id:?} has no coins left for the claim")]
+    MerkleDistributionExhausted { distribution_id: u64 },
+
+    #[error("Only the funder of distribution {distribution_id:?} can reclaim it")]
+    NotMerkleFunder { distribution_id: u64 },
+
+    #[error("Distribution {distribution_id:?} can't be reclaimed before its expiry")]
+    MerkleDistributionNotExpired { distribution_id: u64 },
+
+    #[error("Distribution {distribution_id:?} has already been reclaimed")]
+    MerkleDistributionReclaimed { distribution_id: u64 },
+
+    #[error("A batch needs at least one send")]
+    EmptyBatch {},
+
+    #[error("A batch can't have more than {max:?} sends")]
+    BatchTooLarge { max: usize },
+
+    #[error("Send {index:?} of the batch has no recipients")]
+    EmptyBatchSend { index: usize },
+
+    #[error("Batch needs {expected:?} usei including fees, but {received:?} were sent")]
+    BatchFundsMismatch { expected: u128, received: u128 },
+
+    #[error("No more than {max:?} accounts can be paid out at once")]
+    TooManyPayouts { max: usize },
+
+    #[error("Balances must be inactive for at least one block to expire")]
+    InvalidBalanceExpiry {},
+
+    #[error("Balance of {address} hasn't expired")]
+    BalanceNotExpired { address: String },
+
+    #[error("Can't migrate from {contract}")]
+    CannotMigrate { contract: String },
+
+    #[error("Payout bounty {bounty:?} is more than 9999, or 99.99%")]
+    PayoutBountyTooLarge { bounty: u128 },
+
+    #[error("Balances are still being backfilled after a migration")]
+    MigrationInProgress {},
+
+    #[error("No migration is in progress")]
+    NoMigrationInProgress {},
+
+    #[error("{recipient} already has the most open streams it can be paid by")]
+    TooManyStreams { recipient: String },
 }
@@ -0,0 +1,87 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{
+    to_binary, Addr, Coin, CosmosMsg, CustomQuery, QuerierWrapper, StdResult, WasmMsg, WasmQuery,
+};
+
+use crate::msg::{ExecuteMsg, GetOwnerResponse, GetWithdrawableCoinQuantityResponse, QueryMsg};
+use crate::state::Config;
+
+// UseiTransferContract is a wrapper around the address of a deployed instance of this
+// contract, so that other contracts can build its messages and run its queries with
+// the right types.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct UseiTransferContract(pub Addr);
+
+impl UseiTransferContract {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    // Build a message executing `msg` on this contract, with `funds` attached.
+    pub fn call<T: Into<ExecuteMsg>>(&self, msg: T, funds: Vec<Coin>) -> StdResult<CosmosMsg> {
+        let msg = to_binary(&msg.into())?;
+        Ok(WasmMsg::Execute {
+            contract_addr: self.addr().into(),
+            msg,
+            funds,
+        }
+        .into())
+    }
+
+    pub fn send_coins(
+        &self,
+        dest_addr1: impl Into<String>,
+        dest_addr2: impl Into<String>,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: dest_addr1.into(),
+            dest_addr2: dest_addr2.into(),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        self.call(msg, funds)
+    }
+
+    pub fn withdraw_coins(&self, quantity: u128) -> StdResult<CosmosMsg> {
+        self.call(ExecuteMsg::WithdrawCoins { quantity }, vec![])
+    }
+
+    fn query<CQ, T>(&self, querier: &QuerierWrapper<CQ>, msg: &QueryMsg) -> StdResult<T>
+    where
+        CQ: CustomQuery,
+        T: serde::de::DeserializeOwned,
+    {
+        let query = WasmQuery::Smart {
+            contract_addr: self.addr().into(),
+            msg: to_binary(msg)?,
+        };
+        querier.query(&query.into())
+    }
+
+    pub fn owner<CQ: CustomQuery>(
+        &self,
+        querier: &QuerierWrapper<CQ>,
+    ) -> StdResult<GetOwnerResponse> {
+        self.query(querier, &QueryMsg::GetOwner {})
+    }
+
+    pub fn withdrawable_coin_quantity<CQ: CustomQuery>(
+        &self,
+        querier: &QuerierWrapper<CQ>,
+        address: impl Into<String>,
+    ) -> StdResult<GetWithdrawableCoinQuantityResponse> {
+        let msg = QueryMsg::GetWithdrawableCoinQuantity {
+            address: address.into(),
+        };
+        self.query(querier, &msg)
+    }
+
+    pub fn config<CQ: CustomQuery>(&self, querier: &QuerierWrapper<CQ>) -> StdResult<Config> {
+        self.query(querier, &QueryMsg::Config {})
+    }
+}
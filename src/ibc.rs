@@ -0,0 +1,162 @@
+use cosmwasm_std::{Binary, Coin, CosmosMsg, DepsMut, Env, Event, Response, Storage, Timestamp};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::contract::{count_in_backfill, increase_coins_at_address, unrecord_withdrawal};
+use crate::error::ContractError;
+use crate::state::{config_read, ibc_transfers, ibc_transfers_read, IbcTransfer};
+
+pub const MSG_TRANSFER_TYPE_URL: &str = "/ibc.applications.transfer.v1.MsgTransfer";
+
+// How a transfer sent with an `ibc_callback` memo turned out. The ibc-hooks
+// middleware reports it to the contract through `sudo`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IbcLifecycleComplete {
+    IbcAck {
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    IbcTimeout {
+        channel: String,
+        sequence: u64,
+    },
+}
+
+// An ICS-20 `MsgTransfer`, encoded by hand so the contract doesn't need a protobuf
+// library for this one message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MsgTransfer {
+    pub source_port: String,
+    pub source_channel: String,
+    pub token: Coin,
+    pub sender: String,
+    pub receiver: String,
+    pub timeout_timestamp: Timestamp,
+    pub memo: String,
+}
+
+impl MsgTransfer {
+    pub fn encode(&self) -> Binary {
+        let mut token = vec![];
+        encode_string(&mut token, 1, &self.token.denom);
+        encode_string(&mut token, 2, &self.token.amount.to_string());
+
+        let mut buf = vec![];
+        encode_string(&mut buf, 1, &self.source_port);
+        encode_string(&mut buf, 2, &self.source_channel);
+        encode_bytes(&mut buf, 3, &token);
+        encode_string(&mut buf, 4, &self.sender);
+        encode_string(&mut buf, 5, &self.receiver);
+        // Field 6, the timeout height, is left unset.
+        if self.timeout_timestamp.nanos() > 0 {
+            encode_varint(&mut buf, 7 << 3);
+            encode_varint(&mut buf, self.timeout_timestamp.nanos());
+        }
+        encode_string(&mut buf, 8, &self.memo);
+        Binary::from(buf)
+    }
+
+    pub fn into_cosmos_msg(self) -> CosmosMsg {
+        CosmosMsg::Stargate {
+            type_url: MSG_TRANSFER_TYPE_URL.to_string(),
+            value: self.encode(),
+        }
+    }
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+// A length-delimited field, which proto3 leaves out when it's empty.
+fn encode_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    encode_varint(buf, field << 3 | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_string(buf: &mut Vec<u8>, field: u64, value: &str) {
+    encode_bytes(buf, field, value.as_bytes());
+}
+
+// Settle the transfer the callback is about, giving the coins back to the account if
+// the transfer failed or timed out.
+pub fn ibc_lifecycle_complete(
+    deps: DepsMut,
+    _env: Env,
+    complete: IbcLifecycleComplete,
+) -> Result<(Response, Event), ContractError> {
+    let (channel, sequence, error) = match complete {
+        IbcLifecycleComplete::IbcAck {
+            channel,
+            sequence,
+            ack,
+            success,
+        } => (channel, sequence, (!success).then_some(ack)),
+        IbcLifecycleComplete::IbcTimeout { channel, sequence } => {
+            (channel, sequence, Some(String::from("timeout")))
+        }
+    };
+    let transfer = take_ibc_transfer(deps.storage, &channel, sequence)?;
+    let mut event = Event::new("ibc_lifecycle_complete")
+        .add_attribute("channel_id", channel)
+        .add_attribute("sequence", sequence.to_string())
+        .add_attribute("account", transfer.account.as_str());
+    event = match error {
+        None => event.add_attribute("action", "ibc_transfer_acknowledged"),
+        Some(error) => {
+            restore_ibc_transfer(deps, &transfer)?;
+            event
+                .add_attribute("action", "ibc_transfer_failed")
+                .add_attribute("error", error)
+                .add_attribute("amount", transfer.amount.to_string())
+        }
+    };
+    Ok((Response::new(), event))
+}
+
+// Load and forget the transfer sent as `sequence` on `channel_id`, since it's now
+// settled one way or the other.
+fn take_ibc_transfer(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+    sequence: u64,
+) -> Result<IbcTransfer, ContractError> {
+    let key = sequence.to_be_bytes();
+    let transfer = ibc_transfers_read(storage, channel_id)
+        .may_load(&key)?
+        .ok_or_else(|| ContractError::UnknownIbcTransfer {
+            channel_id: channel_id.to_string(),
+            sequence,
+        })?;
+    ibc_transfers(storage, channel_id).remove(&key);
+    Ok(transfer)
+}
+
+// Give the coins of a failed transfer back to the account, so it can withdraw them
+// again, and stop counting them against the withdrawal limits.
+fn restore_ibc_transfer(deps: DepsMut, transfer: &IbcTransfer) -> Result<(), ContractError> {
+    increase_coins_at_address(deps.storage, transfer.account.clone(), transfer.amount)?;
+    count_in_backfill(deps.storage, &transfer.account, transfer.amount)?;
+    if let Some(counted_at) = transfer.counted_at {
+        let limits = config_read(deps.storage).load()?.withdrawal_limits;
+        unrecord_withdrawal(
+            deps.storage,
+            &limits,
+            &transfer.account,
+            counted_at,
+            transfer.amount,
+        )?;
+    }
+    Ok(())
+}
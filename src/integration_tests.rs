@@ -0,0 +1,218 @@
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{coin, coins, Addr, Empty, Uint128};
+    use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
+
+    use crate::contract::{execute, instantiate, query, reply, COIN_DENOM};
+    use crate::helpers::UseiTransferContract;
+    use crate::msg::{ExecuteMsg, FailedWithdrawalsResponse, InstantiateMsg, QueryMsg};
+
+    const SENDER: &str = "sender";
+    const OWNER: &str = "owner";
+    const ALICE: &str = "alice";
+    const BOB: &str = "bob";
+
+    fn contract_usei_transfer() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(execute, instantiate, query).with_reply(reply);
+        Box::new(contract)
+    }
+
+    fn mock_app() -> App {
+        AppBuilder::new().build(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &Addr::unchecked(SENDER), coins(1000, COIN_DENOM))
+                .unwrap();
+        })
+    }
+
+    // Store and instantiate the contract with a flat fee of 10 going to OWNER.
+    fn proper_instantiate() -> (App, UseiTransferContract) {
+        let mut app = mock_app();
+        let code_id = app.store_code(contract_usei_transfer());
+
+        let msg = InstantiateMsg {
+            owner: Some(OWNER.to_string()),
+            flat_fee: Some(10),
+            percent_fee: None,
+            self_send_policy: None,
+            duplicate_recipient_policy: None,
+        };
+        let contract_addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked(OWNER),
+                &msg,
+                &[],
+                "usei-transfer-tokens",
+                None,
+            )
+            .unwrap();
+
+        (app, UseiTransferContract(contract_addr))
+    }
+
+    fn send_coins(app: &mut App, contract: &UseiTransferContract, quantity: u128) {
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: ALICE.to_string(),
+            dest_addr2: BOB.to_string(),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        app.execute_contract(
+            Addr::unchecked(SENDER),
+            contract.addr(),
+            &msg,
+            &coins(quantity, COIN_DENOM),
+        )
+        .unwrap();
+    }
+
+    fn assert_bank_balance(app: &App, address: &str, expected: u128) {
+        let balance = app.wrap().query_balance(address, COIN_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::from(expected));
+    }
+
+    fn assert_withdrawable(
+        app: &App,
+        contract: &UseiTransferContract,
+        address: &str,
+        expected: u128,
+    ) {
+        let resp = contract
+            .withdrawable_coin_quantity(&app.wrap(), address)
+            .unwrap();
+        assert_eq!(resp.balance, expected);
+    }
+
+    #[test]
+    fn send_coins_moves_funds_into_contract() {
+        let (mut app, contract) = proper_instantiate();
+        send_coins(&mut app, &contract, 300);
+
+        // The sender paid the full 300, which the contract now holds on behalf of
+        // Alice, Bob and the owner.
+        assert_bank_balance(&app, SENDER, 700);
+        assert_bank_balance(&app, contract.addr().as_str(), 300);
+        assert_withdrawable(&app, &contract, ALICE, 145);
+        assert_withdrawable(&app, &contract, BOB, 145);
+        assert_withdrawable(&app, &contract, OWNER, 10);
+
+        // Nothing has been paid out to the recipients yet.
+        assert_bank_balance(&app, ALICE, 0);
+        assert_bank_balance(&app, OWNER, 0);
+        assert_eq!(contract.owner(&app.wrap()).unwrap().owner, OWNER);
+    }
+
+    #[test]
+    fn withdrawals_pay_out_recipients_and_owner() {
+        let (mut app, contract) = proper_instantiate();
+        send_coins(&mut app, &contract, 300);
+
+        app.execute(
+            Addr::unchecked(ALICE),
+            contract.withdraw_coins(100).unwrap(),
+        )
+        .unwrap();
+        app.execute(Addr::unchecked(BOB), contract.withdraw_coins(145).unwrap())
+            .unwrap();
+        app.execute(Addr::unchecked(OWNER), contract.withdraw_coins(10).unwrap())
+            .unwrap();
+
+        assert_bank_balance(&app, ALICE, 100);
+        assert_bank_balance(&app, BOB, 145);
+        assert_bank_balance(&app, OWNER, 10);
+        // Only Alice's remaining 45 is left in the contract.
+        assert_bank_balance(&app, contract.addr().as_str(), 45);
+        assert_withdrawable(&app, &contract, ALICE, 45);
+        assert_withdrawable(&app, &contract, BOB, 0);
+        assert_withdrawable(&app, &contract, OWNER, 0);
+    }
+
+    #[test]
+    fn send_more_than_sender_holds() {
+        let (mut app, contract) = proper_instantiate();
+
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: ALICE.to_string(),
+            dest_addr2: BOB.to_string(),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        app.execute_contract(
+            Addr::unchecked(SENDER),
+            contract.addr(),
+            &msg,
+            &coins(5000, COIN_DENOM),
+        )
+        .unwrap_err();
+
+        // The bank rejects the transfer, so nobody is credited.
+        assert_bank_balance(&app, SENDER, 1000);
+        assert_bank_balance(&app, contract.addr().as_str(), 0);
+        assert_withdrawable(&app, &contract, ALICE, 0);
+    }
+
+    #[test]
+    fn withdraw_more_than_balance() {
+        let (mut app, contract) = proper_instantiate();
+        send_coins(&mut app, &contract, 300);
+
+        app.execute(
+            Addr::unchecked(ALICE),
+            contract.withdraw_coins(146).unwrap(),
+        )
+        .unwrap_err();
+
+        assert_bank_balance(&app, ALICE, 0);
+        assert_bank_balance(&app, contract.addr().as_str(), 300);
+        assert_withdrawable(&app, &contract, ALICE, 145);
+    }
+
+    #[test]
+    fn withdraw_with_insufficient_contract_funds() {
+        let (mut app, contract) = proper_instantiate();
+        send_coins(&mut app, &contract, 300);
+
+        // Drain the contract's bank balance out from under the ledger.
+        app.send_tokens(
+            contract.addr(),
+            Addr::unchecked("elsewhere"),
+            &[coin(250, COIN_DENOM)],
+        )
+        .unwrap();
+
+        // Alice's ledger balance says 145, but the contract only holds 50, so the
+        // bank send fails and her balance is restored.
+        app.execute(
+            Addr::unchecked(ALICE),
+            contract.withdraw_coins(145).unwrap(),
+        )
+        .unwrap();
+        assert_bank_balance(&app, ALICE, 0);
+        assert_bank_balance(&app, contract.addr().as_str(), 50);
+        assert_withdrawable(&app, &contract, ALICE, 145);
+
+        // The failure is logged for Alice to look up.
+        let msg = QueryMsg::FailedWithdrawals {
+            account: ALICE.to_string(),
+            start_after: None,
+            limit: None,
+        };
+        let resp: FailedWithdrawalsResponse =
+            app.wrap().query_wasm_smart(contract.addr(), &msg).unwrap();
+        assert_eq!(resp.withdrawals.len(), 1);
+        assert_eq!(resp.withdrawals[0].amount, 145);
+        assert_eq!(resp.withdrawals[0].denom, COIN_DENOM);
+
+        // A withdrawal the contract can still cover goes through.
+        app.execute(Addr::unchecked(ALICE), contract.withdraw_coins(50).unwrap())
+            .unwrap();
+        assert_bank_balance(&app, ALICE, 50);
+        assert_withdrawable(&app, &contract, ALICE, 95);
+    }
+}
@@ -1,9 +1,13 @@
 pub mod contract;
 mod error;
+pub mod helpers;
+pub mod ibc;
+mod integration_tests;
 pub mod msg;
 pub mod state;
 
 #[cfg(test)]
+#[allow(clippy::module_inception, clippy::get_first)]
 mod tests;
 
 pub use crate::error::ContractError;
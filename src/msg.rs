@@ -1,12 +1,24 @@
+use cosmwasm_schema::QueryResponses;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use cosmwasm_std::{to_binary, Binary, CosmosMsg, Decimal, StdResult, Timestamp, WasmMsg};
+
+use crate::ibc::IbcLifecycleComplete;
+use crate::state::{
+    BatchFeeMode, Config, DuplicateRecipientPolicy, Escrow, FailedWithdrawal, IbcTransfer, Invoice,
+    InvoiceStatus, MerkleDistribution, PendingWithdrawal, Role, SelfSendPolicy, SendRecord,
+    SplitGroup, StakingConfig, Stream, Unbonding, VoucherPool, WithdrawalLimits, WithdrawalPolicy,
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct InstantiateMsg {
     pub owner: Option<String>,
     pub flat_fee: Option<u128>,
     pub percent_fee: Option<u128>,
+    pub self_send_policy: Option<SelfSendPolicy>,
+    pub duplicate_recipient_policy: Option<DuplicateRecipientPolicy>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -15,18 +27,375 @@ pub enum ExecuteMsg {
     SendCoins {
         dest_addr1: String,
         dest_addr2: String,
+        // Whether to call the destination back with `ReceiveSplit` once it has been
+        // credited, for recipients that are contracts.
+        #[serde(default)]
+        notify_addr1: bool,
+        #[serde(default)]
+        notify_addr2: bool,
+        // Recorded with the send and passed on to the recipients called back.
+        #[serde(default)]
+        memo: Option<String>,
+        // The sender's own ID for the payment; a second send with the same ID is
+        // rejected.
+        #[serde(default)]
+        reference_id: Option<String>,
     },
     WithdrawCoins {
         quantity: u128,
     },
+    GrantRole {
+        role: Role,
+        address: String,
+    },
+    RevokeRole {
+        role: Role,
+        address: String,
+    },
+    UpdateFee {
+        flat_fee: u128,
+        percent_fee: u128,
+    },
+    SetPaused {
+        paused: bool,
+    },
+    UpdateOwner {
+        owner: String,
+    },
+    UpdateRecipientPolicies {
+        self_send_policy: Option<SelfSendPolicy>,
+        duplicate_recipient_policy: Option<DuplicateRecipientPolicy>,
+    },
+    CreateSplitGroup {
+        name: String,
+        members: Vec<(String, u64)>,
+    },
+    UpdateSplitGroup {
+        group_id: u64,
+        name: Option<String>,
+        members: Option<Vec<(String, u64)>>,
+    },
+    DeleteSplitGroup {
+        group_id: u64,
+    },
+    SendToGroup {
+        group_id: u64,
+    },
+    CreateStream {
+        recipient: String,
+        start: Timestamp,
+        end: Timestamp,
+    },
+    CancelStream {
+        stream_id: u64,
+    },
+    EscrowSendCoins {
+        dest_addr1: String,
+        dest_addr2: String,
+        arbiter: String,
+        timeout: Option<Timestamp>,
+    },
+    ReleaseEscrow {
+        escrow_id: u64,
+    },
+    RefundEscrow {
+        escrow_id: u64,
+    },
+    ReclaimEscrow {
+        escrow_id: u64,
+    },
+    SetWithdrawalPolicy {
+        cosigners: Vec<String>,
+        threshold: u32,
+        approval_above: u128,
+        expiry_seconds: u64,
+    },
+    ApprovePolicyRemoval {
+        account: String,
+    },
+    ApproveWithdrawal {
+        withdrawal_id: u64,
+    },
+    CancelWithdrawal {
+        withdrawal_id: u64,
+    },
+    ClaimWithdrawal {
+        withdrawal_id: u64,
+    },
+    UpdateWithdrawalLimits {
+        limits: WithdrawalLimits,
+    },
+    WithdrawIbc {
+        channel_id: String,
+        remote_address: String,
+        amount: u128,
+        timeout: Timestamp,
+    },
+    // Called through IBC hooks for an incoming ICS-20 transfer, with the split
+    // instructions from its memo as a JSON encoded `SplitMemo`.
+    ReceiveIbcTransfer {
+        memo: String,
+    },
+    WithdrawDenom {
+        denom: String,
+        quantity: u128,
+    },
+    UpdateFallbackAccount {
+        address: Option<String>,
+    },
+    UpdateStaking {
+        staking: StakingConfig,
+    },
+    Rebalance {},
+    ClaimRewards {},
+    AddHook {
+        addr: String,
+    },
+    RemoveHook {
+        addr: String,
+    },
+    // Request a payment of `amount` coins, to be split by weight between `split`
+    // once paid. Anyone can pay the invoice unless `payers` is given.
+    CreateInvoice {
+        payers: Option<Vec<String>>,
+        amount: u128,
+        split: Vec<(String, u64)>,
+        expiry: Option<Timestamp>,
+    },
+    PayInvoice {
+        invoice_id: u64,
+    },
+    CancelInvoice {
+        invoice_id: u64,
+    },
+    // Credit every send from the attached coins, which must cover their amounts
+    // plus the owner fees.
+    BatchSend {
+        sends: Vec<BatchSendItem>,
+    },
+    UpdateBatchFeeMode {
+        mode: BatchFeeMode,
+    },
+    // Opt in or out of having the sender's balance pushed to them by `PushPayouts`.
+    SetAutoPayout {
+        enabled: bool,
+    },
+    // Withdraw the balances of the opted-in `addresses` to them. Anyone can call
+    // this, and is paid the payout bounty's share of the coins paid out to others
+    // once their bank sends go through.
+    PushPayouts {
+        addresses: Vec<String>,
+    },
+    UpdatePayoutBounty {
+        bounty: u128,
+    },
+    // Let balances expire after `inactive_blocks` blocks without activity, or never
+    // if it's not set. Expired balances go to `treasury` if it's set, and otherwise
+    // back to the senders who credited them.
+    UpdateBalanceExpiry {
+        inactive_blocks: Option<u64>,
+        treasury: Option<String>,
+    },
+    ReclaimExpired {
+        address: String,
+    },
+    // Add the attached coins to the pool that signed vouchers are paid from.
+    FundVoucherPool {},
+    // Take coins back out of the voucher pool and send them to the sender.
+    WithdrawVoucherPool {
+        amount: u128,
+    },
+    // Publish the public key that vouchers must be signed with.
+    SetVoucherKey {
+        pubkey: Binary,
+    },
+    // Credit the sender with a voucher signed over the SHA-256 hash of the JSON
+    // encoding of its `VoucherPayload`.
+    ClaimVoucher {
+        amount: u128,
+        nonce: u64,
+        expiry: Timestamp,
+        signature: Binary,
+    },
+    // Hold the attached `total` coins for the claimants in the merkle tree with the
+    // hex encoded `merkle_root`, until `expiry`.
+    FundMerkleDistribution {
+        merkle_root: String,
+        total: u128,
+        expiry: Timestamp,
+    },
+    // Credit the sender with their `amount` in a distribution, proven by the hex
+    // encoded sibling hashes from their leaf up to the root.
+    ClaimMerkle {
+        distribution_id: u64,
+        amount: u128,
+        proof: Vec<String>,
+    },
+    ReclaimMerkleDistribution {
+        distribution_id: u64,
+    },
+    // Backfill the next `limit` balances left by `migrate`. Anyone can send this.
+    ContinueMigration {
+        limit: Option<u32>,
+    },
+}
+
+// One split of a `BatchSend`: `amount` is shared between the recipients by weight.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BatchSendItem {
+    pub amount: u128,
+    pub recipients: Vec<(String, u64)>,
+}
+
+// What a voucher's signature covers. Each nonce can only be claimed once.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct VoucherPayload {
+    pub chain_id: String,
+    pub contract: String,
+    pub recipient: String,
+    pub amount: u128,
+    pub nonce: u64,
+    pub expiry: Timestamp,
+}
+
+// Split instructions for the coins of an incoming IBC transfer.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SplitMemo {
+    pub recipients: Vec<(String, u64)>,
 }
 
+// Messages that only the chain can send: through governance, or from the ibc-hooks
+// middleware once a transfer the contract sent is acknowledged or times out.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+pub enum SudoMsg {
+    UpdateOwner { owner: String },
+    UpdateFee { flat_fee: u128, percent_fee: u128 },
+    SetPaused { paused: bool },
+    ForceRefund { address: String },
+    IbcLifecycleComplete(IbcLifecycleComplete),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, QueryResponses)]
+#[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+    #[returns(GetOwnerResponse)]
     GetOwner {},
+    #[returns(GetWithdrawableCoinQuantityResponse)]
     GetWithdrawableCoinQuantity { address: String },
+    #[returns(Config)]
     Config {},
+    #[returns(HasRoleResponse)]
+    HasRole { role: Role, address: String },
+    #[returns(RoleMembersResponse)]
+    RoleMembers { role: Role },
+    #[returns(SplitGroup)]
+    SplitGroup { group_id: u64 },
+    #[returns(SplitGroupsResponse)]
+    SplitGroupsByOwner {
+        owner: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(StreamResponse)]
+    Stream { stream_id: u64 },
+    #[returns(StreamsResponse)]
+    StreamsByRecipient {
+        recipient: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(Escrow)]
+    Escrow { escrow_id: u64 },
+    #[returns(EscrowsResponse)]
+    EscrowsByArbiter {
+        arbiter: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(EscrowsResponse)]
+    EscrowsByParty {
+        party: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(WithdrawalPolicyResponse)]
+    WithdrawalPolicy { account: String },
+    #[returns(PendingWithdrawal)]
+    PendingWithdrawal { withdrawal_id: u64 },
+    #[returns(PendingWithdrawalsResponse)]
+    PendingWithdrawals {
+        account: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(IbcTransfer)]
+    IbcTransfer { channel_id: String, sequence: u64 },
+    #[returns(DenomBalanceResponse)]
+    DenomBalance { address: String, denom: String },
+    #[returns(FailedWithdrawalsResponse)]
+    FailedWithdrawals {
+        account: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(StakingResponse)]
+    Staking {},
+    #[returns(HooksResponse)]
+    Hooks {},
+    #[returns(SendRecord)]
+    Send { send_id: u64 },
+    #[returns(SendRecord)]
+    SendByReference {
+        sender: String,
+        reference_id: String,
+    },
+    #[returns(Invoice)]
+    Invoice { invoice_id: u64 },
+    #[returns(AutoPayoutResponse)]
+    AutoPayout { address: String },
+    #[returns(ExpiringAccountsResponse)]
+    ExpiringAccounts {
+        within_blocks: u64,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(VoucherPool)]
+    VoucherPool {},
+    #[returns(VoucherNonceResponse)]
+    VoucherNonce { nonce: u64 },
+    #[returns(MerkleDistribution)]
+    MerkleDistribution { distribution_id: u64 },
+    #[returns(MerkleClaimResponse)]
+    MerkleClaim {
+        distribution_id: u64,
+        address: String,
+    },
+    #[returns(InvoicesResponse)]
+    InvoicesByCreator {
+        creator: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(InvoicesResponse)]
+    InvoicesByPayer {
+        payer: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(InvoicesResponse)]
+    InvoicesByStatus {
+        status: InvoiceStatus,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(BalanceAtHeightResponse)]
+    BalanceAtHeight { address: String, height: u64 },
+    #[returns(TotalLiabilitiesAtHeightResponse)]
+    TotalLiabilitiesAtHeight { height: u64 },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -44,4 +413,226 @@ pub struct GetWithdrawableCoinQuantityResponse {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum MigrateMsg {}
+pub struct HasRoleResponse {
+    pub role: Role,
+    pub address: String,
+    pub has_role: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RoleMembersResponse {
+    pub role: Role,
+    pub members: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SplitGroupsResponse {
+    pub groups: Vec<SplitGroup>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StreamResponse {
+    pub stream: Stream,
+    // The accrued coins the recipient can withdraw but hasn't yet.
+    pub withdrawable: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StreamsResponse {
+    pub streams: Vec<Stream>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct EscrowsResponse {
+    pub escrows: Vec<Escrow>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WithdrawalPolicyResponse {
+    pub policy: Option<WithdrawalPolicy>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PendingWithdrawalsResponse {
+    pub withdrawals: Vec<PendingWithdrawal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DenomBalanceResponse {
+    pub address: String,
+    pub denom: String,
+    pub balance: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct FailedWithdrawalsResponse {
+    pub withdrawals: Vec<FailedWithdrawal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StakingResponse {
+    pub staking: StakingConfig,
+    pub unbonding: Vec<Unbonding>,
+    pub reward_index: Decimal,
+    pub total_balance: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct InvoicesResponse {
+    pub invoices: Vec<Invoice>,
+    // Where the next page starts, if there may be more invoices. Open invoices can
+    // come back fewer than the limit, or none, with more still to come.
+    pub next_start_after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ExpiringAccount {
+    pub address: String,
+    pub balance: u128,
+    // The height of the account's last activity.
+    pub last_active: u64,
+    // The height from which its balance can be reclaimed.
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ExpiringAccountsResponse {
+    pub accounts: Vec<ExpiringAccount>,
+    // Where the next page starts, if there may be more accounts. Accounts that
+    // aren't expiring still count towards the limit, so a page can be short.
+    pub next_start_after: Option<String>,
+}
+
+// An account's balance as of the end of block `height`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BalanceAtHeightResponse {
+    pub address: String,
+    pub height: u64,
+    pub balance: u128,
+}
+
+// The sum of every account balance as of the end of block `height`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TotalLiabilitiesAtHeightResponse {
+    pub height: u64,
+    pub total: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AutoPayoutResponse {
+    pub address: String,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct VoucherNonceResponse {
+    pub nonce: u64,
+    // When a voucher with the nonce was claimed, if it has been.
+    pub used_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MerkleClaimResponse {
+    pub distribution_id: u64,
+    pub address: String,
+    // The amount claimed, if the address has claimed.
+    pub claimed: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HooksResponse {
+    pub hooks: Vec<String>,
+}
+
+// Sent to every registered hook when an account balance changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BalanceChangedHookMsg {
+    pub address: String,
+    pub old_balance: u128,
+    pub new_balance: u128,
+}
+
+impl BalanceChangedHookMsg {
+    // Serialize the message as the hook contract receives it.
+    pub fn into_binary(self) -> StdResult<Binary> {
+        to_binary(&BalanceChangedExecuteMsg::BalanceChangedHook(self))
+    }
+
+    // Build the message executing the hook on `contract_addr`.
+    pub fn into_cosmos_msg<T: Into<String>>(self, contract_addr: T) -> StdResult<CosmosMsg> {
+        let execute = WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg: self.into_binary()?,
+            funds: vec![],
+        };
+        Ok(execute.into())
+    }
+}
+
+// The execute message a hook contract has to handle, wrapping `BalanceChangedHookMsg`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceChangedExecuteMsg {
+    BalanceChangedHook(BalanceChangedHookMsg),
+}
+
+// Sent to a recipient contract that asked to be told when it's credited by a send.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReceiveSplitMsg {
+    pub sender: String,
+    pub amount: u128,
+    pub memo: Option<String>,
+}
+
+impl ReceiveSplitMsg {
+    // Serialize the message as the recipient contract receives it.
+    pub fn into_binary(self) -> StdResult<Binary> {
+        to_binary(&ReceiveSplitExecuteMsg::ReceiveSplit(self))
+    }
+
+    // Build the message executing the callback on `contract_addr`.
+    pub fn into_cosmos_msg<T: Into<String>>(self, contract_addr: T) -> StdResult<CosmosMsg> {
+        let execute = WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg: self.into_binary()?,
+            funds: vec![],
+        };
+        Ok(execute.into())
+    }
+}
+
+// The execute message a recipient contract has to handle, wrapping `ReceiveSplitMsg`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveSplitExecuteMsg {
+    ReceiveSplit(ReceiveSplitMsg),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrateMsg {
+    // How many balances to backfill in the migration itself. The rest are left to
+    // `ExecuteMsg::ContinueMigration`.
+    pub limit: Option<u32>,
+}
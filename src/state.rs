@@ -5,15 +5,164 @@ use cosmwasm_storage::{
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Storage};
+use cosmwasm_std::{Addr, Binary, Decimal, StdResult, Storage, Timestamp};
+use cw_storage_plus::{SnapshotItem, SnapshotMap, Strategy};
+
+use crate::msg::BalanceChangedHookMsg;
 
 pub static CONFIG_KEY: &[u8] = b"config";
 pub static ACCOUNT_BALANCE_KEY: &[u8] = b"accountbalance";
+pub static ROLES_KEY: &[u8] = b"roles";
+pub static SPLIT_GROUP_KEY: &[u8] = b"splitgroup";
+pub static SPLIT_GROUP_COUNT_KEY: &[u8] = b"splitgroupcount";
+pub static SPLIT_GROUPS_BY_OWNER_KEY: &[u8] = b"splitgroupsbyowner";
+pub static STREAM_KEY: &[u8] = b"stream";
+pub static STREAM_COUNT_KEY: &[u8] = b"streamcount";
+pub static STREAMS_BY_RECIPIENT_KEY: &[u8] = b"streamsbyrecipient";
+pub static ESCROW_KEY: &[u8] = b"escrow";
+pub static ESCROW_COUNT_KEY: &[u8] = b"escrowcount";
+pub static ESCROWS_BY_ARBITER_KEY: &[u8] = b"escrowsbyarbiter";
+pub static ESCROWS_BY_PARTY_KEY: &[u8] = b"escrowsbyparty";
+pub static WITHDRAWAL_POLICY_KEY: &[u8] = b"withdrawalpolicy";
+pub static PENDING_WITHDRAWAL_KEY: &[u8] = b"pendingwithdrawal";
+pub static PENDING_WITHDRAWAL_COUNT_KEY: &[u8] = b"pendingwithdrawalcount";
+pub static PENDING_WITHDRAWALS_BY_ACCOUNT_KEY: &[u8] = b"pendingwithdrawalsbyaccount";
+pub static WITHDRAWAL_HISTORY_KEY: &[u8] = b"withdrawalhistory";
+pub static GLOBAL_WITHDRAWAL_HISTORY_KEY: &[u8] = b"globalwithdrawalhistory";
+pub static UNAPPROVED_WITHDRAWAL_HISTORY_KEY: &[u8] = b"unapprovedwithdrawalhistory";
+pub static IBC_TRANSFER_KEY: &[u8] = b"ibctransfer";
+pub static IBC_TRANSFER_IN_FLIGHT_KEY: &[u8] = b"ibctransferinflight";
+pub static DENOM_BALANCE_KEY: &[u8] = b"denombalance";
+pub static WITHDRAWAL_ATTEMPT_COUNT_KEY: &[u8] = b"withdrawalattemptcount";
+pub static WITHDRAWAL_IN_FLIGHT_KEY: &[u8] = b"withdrawalinflight";
+pub static FAILED_WITHDRAWAL_KEY: &[u8] = b"failedwithdrawal";
+pub static FAILED_WITHDRAWALS_BY_ACCOUNT_KEY: &[u8] = b"failedwithdrawalsbyaccount";
+pub static TOTAL_BALANCE_KEY: &[u8] = b"totalbalance";
+pub static STAKING_CONFIG_KEY: &[u8] = b"stakingconfig";
+pub static UNBONDING_KEY: &[u8] = b"unbonding";
+pub static REWARD_INDEX_KEY: &[u8] = b"rewardindex";
+pub static REWARD_SNAPSHOT_KEY: &[u8] = b"rewardsnapshot";
+pub static HOOKS_KEY: &[u8] = b"hooks";
+pub static BALANCE_CHANGES_KEY: &[u8] = b"balancechanges";
+pub static SEND_KEY: &[u8] = b"send";
+pub static SEND_COUNT_KEY: &[u8] = b"sendcount";
+pub static SENDS_BY_REFERENCE_KEY: &[u8] = b"sendsbyreference";
+pub static INVOICE_KEY: &[u8] = b"invoice";
+pub static INVOICE_COUNT_KEY: &[u8] = b"invoicecount";
+pub static INVOICES_BY_CREATOR_KEY: &[u8] = b"invoicesbycreator";
+pub static INVOICES_BY_PAYER_KEY: &[u8] = b"invoicesbypayer";
+pub static INVOICES_BY_STATUS_KEY: &[u8] = b"invoicesbystatus";
+pub static VOUCHER_POOL_KEY: &[u8] = b"voucherpool";
+pub static USED_VOUCHER_NONCE_KEY: &[u8] = b"usedvouchernonce";
+pub static MERKLE_DISTRIBUTION_KEY: &[u8] = b"merkledistribution";
+pub static MERKLE_DISTRIBUTION_COUNT_KEY: &[u8] = b"merkledistributioncount";
+pub static MERKLE_CLAIM_KEY: &[u8] = b"merkleclaim";
+pub static AUTO_PAYOUT_KEY: &[u8] = b"autopayout";
+pub static LAST_ACTIVITY_KEY: &[u8] = b"lastactivity";
+pub static CREDIT_SOURCES_KEY: &[u8] = b"creditsources";
+pub static BACKFILL_KEY: &[u8] = b"backfill";
+pub static DENOMS_BY_ACCOUNT_KEY: &[u8] = b"denomsbyaccount";
+pub static RESERVED_FOR_WITHDRAWALS_KEY: &[u8] = b"reservedforwithdrawals";
+pub static UNTRACKED_CREDIT_KEY: &[u8] = b"untrackedcredit";
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
     pub fee: Fee,
+    // The fields below were added after the first release, so they default when
+    // loading a config saved by it.
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub self_send_policy: SelfSendPolicy,
+    #[serde(default)]
+    pub duplicate_recipient_policy: DuplicateRecipientPolicy,
+    #[serde(default)]
+    pub withdrawal_limits: WithdrawalLimits,
+    // Receives incoming IBC transfers whose split instructions can't be followed,
+    // or the owner if it's not set.
+    #[serde(default)]
+    pub fallback_account: Option<Addr>,
+    #[serde(default)]
+    pub batch_fee_mode: BatchFeeMode,
+    // The share of the coins paid out by `PushPayouts` that its caller is paid out
+    // of the owner's fees, with the same precision as the percent fee.
+    #[serde(default)]
+    pub payout_bounty: u128,
+    #[serde(default)]
+    pub balance_expiry: Option<BalanceExpiry>,
+}
+
+// When and where the balances of inactive accounts can be reclaimed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BalanceExpiry {
+    pub inactive_blocks: u64,
+    // Receives expired balances, rather than the senders who credited them.
+    pub treasury: Option<Addr>,
+}
+
+// How the owner fee is charged on a `BatchSend`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchFeeMode {
+    // Charge each send's fee as if it had been sent on its own.
+    #[default]
+    PerItem,
+    // Charge a single fee on the batch's total.
+    PerBatch,
+}
+
+// Whether a sender may name themselves as one of the destinations of a send.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfSendPolicy {
+    #[default]
+    Allow,
+    Reject,
+}
+
+// How a send that names the same destination more than once is handled.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateRecipientPolicy {
+    // Credit each occurrence separately, as if they were different addresses.
+    #[default]
+    Allow,
+    Reject,
+    // Combine the occurrences into a single credit for their total share, so no
+    // coins are lost to rounding between them.
+    Merge,
+}
+
+// Caps on how much can be withdrawn from the contract within a rolling window.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalLimits {
+    // The length of the rolling window, in seconds.
+    pub window_seconds: u64,
+    // The most each account can withdraw within the window.
+    pub per_account: Option<u128>,
+    // The most that can be withdrawn from the whole contract within the window.
+    pub global: Option<u128>,
+    pub timelock: Option<Timelock>,
+}
+
+impl Default for WithdrawalLimits {
+    fn default() -> Self {
+        WithdrawalLimits {
+            window_seconds: 24 * 60 * 60,
+            per_account: None,
+            global: None,
+            timelock: None,
+        }
+    }
+}
+
+// Single withdrawals of more than `above` are held for `delay_seconds` before they
+// can be paid out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Timelock {
+    pub above: u128,
+    pub delay_seconds: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -22,11 +171,11 @@ pub struct Fee {
     pub percent_fee: u128,
 }
 
-pub fn config(storage: &mut dyn Storage) -> Singleton<Config> {
+pub fn config(storage: &mut dyn Storage) -> Singleton<'_, Config> {
     singleton(storage, CONFIG_KEY)
 }
 
-pub fn config_read(storage: &dyn Storage) -> ReadonlySingleton<Config> {
+pub fn config_read(storage: &dyn Storage) -> ReadonlySingleton<'_, Config> {
     singleton_read(storage, CONFIG_KEY)
 }
 
@@ -36,10 +185,816 @@ pub struct AccountBalance {
     pub balance: u128,
 }
 
-pub fn resolver(storage: &mut dyn Storage) -> Bucket<AccountBalance> {
+pub fn resolver(storage: &mut dyn Storage) -> Bucket<'_, AccountBalance> {
     bucket(storage, ACCOUNT_BALANCE_KEY)
 }
 
-pub fn resolver_read(storage: &dyn Storage) -> ReadonlyBucket<AccountBalance> {
+pub fn resolver_read(storage: &dyn Storage) -> ReadonlyBucket<'_, AccountBalance> {
     bucket_read(storage, ACCOUNT_BALANCE_KEY)
 }
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // Can grant and revoke every role, including its own.
+    Admin,
+    // Can change the flat and percent fees charged on sends.
+    FeeManager,
+    // Can pause and unpause sends and withdrawals.
+    Pauser,
+    // Can change the contract's behavioural settings.
+    ConfigAdmin,
+    // Can change the owner address that collects the fees.
+    Treasury,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Admin,
+        Role::FeeManager,
+        Role::Pauser,
+        Role::ConfigAdmin,
+        Role::Treasury,
+    ];
+
+    pub fn key(&self) -> &'static [u8] {
+        match self {
+            Role::Admin => b"admin",
+            Role::FeeManager => b"feemanager",
+            Role::Pauser => b"pauser",
+            Role::ConfigAdmin => b"configadmin",
+            Role::Treasury => b"treasury",
+        }
+    }
+}
+
+// Each role is its own bucket of member addresses, keyed by the address.
+pub fn role_members(storage: &mut dyn Storage, role: Role) -> Bucket<'_, Addr> {
+    Bucket::multilevel(storage, &[ROLES_KEY, role.key()])
+}
+
+pub fn role_members_read(storage: &dyn Storage, role: Role) -> ReadonlyBucket<'_, Addr> {
+    ReadonlyBucket::multilevel(storage, &[ROLES_KEY, role.key()])
+}
+
+// Hand out sequential IDs, starting from 1, from the counter stored under `key`.
+pub fn next_id(storage: &mut dyn Storage, key: &[u8]) -> StdResult<u64> {
+    let mut counter = singleton(storage, key);
+    let id = counter.may_load()?.unwrap_or(0u64) + 1;
+    counter.save(&id)?;
+    Ok(id)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SplitGroup {
+    pub id: u64,
+    pub owner: Addr,
+    pub name: String,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GroupMember {
+    pub address: Addr,
+    pub weight: u64,
+}
+
+pub fn split_groups(storage: &mut dyn Storage) -> Bucket<'_, SplitGroup> {
+    bucket(storage, SPLIT_GROUP_KEY)
+}
+
+pub fn split_groups_read(storage: &dyn Storage) -> ReadonlyBucket<'_, SplitGroup> {
+    bucket_read(storage, SPLIT_GROUP_KEY)
+}
+
+// Index of the IDs of the groups created by each owner.
+pub fn split_groups_by_owner<'a>(storage: &'a mut dyn Storage, owner: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[SPLIT_GROUPS_BY_OWNER_KEY, owner.as_bytes()])
+}
+
+pub fn split_groups_by_owner_read<'a>(
+    storage: &'a dyn Storage,
+    owner: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[SPLIT_GROUPS_BY_OWNER_KEY, owner.as_bytes()])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Stream {
+    pub id: u64,
+    pub sender: Addr,
+    pub recipient: Addr,
+    // The coins deposited into the stream, after the owner fee.
+    pub amount: u128,
+    // The coins already moved into the recipient's account balance.
+    pub claimed: u128,
+    pub start: Timestamp,
+    pub end: Timestamp,
+}
+
+impl Stream {
+    // The coins the recipient has earned by `now`, which grow linearly from
+    // nothing at `start` to the full amount at `end`.
+    pub fn accrued(&self, now: Timestamp) -> u128 {
+        if now <= self.start {
+            0
+        } else if now >= self.end {
+            self.amount
+        } else {
+            let elapsed = (now.nanos() - self.start.nanos()) as u128;
+            let duration = (self.end.nanos() - self.start.nanos()) as u128;
+            self.amount * elapsed / duration
+        }
+    }
+
+    // The accrued coins that haven't been moved into the recipient's balance yet.
+    pub fn unclaimed(&self, now: Timestamp) -> u128 {
+        self.accrued(now) - self.claimed
+    }
+}
+
+pub fn streams(storage: &mut dyn Storage) -> Bucket<'_, Stream> {
+    bucket(storage, STREAM_KEY)
+}
+
+pub fn streams_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Stream> {
+    bucket_read(storage, STREAM_KEY)
+}
+
+// Index of the IDs of the open streams paying each recipient.
+pub fn streams_by_recipient<'a>(storage: &'a mut dyn Storage, recipient: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[STREAMS_BY_RECIPIENT_KEY, recipient.as_bytes()])
+}
+
+pub fn streams_by_recipient_read<'a>(
+    storage: &'a dyn Storage,
+    recipient: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[STREAMS_BY_RECIPIENT_KEY, recipient.as_bytes()])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Escrow {
+    pub id: u64,
+    pub sender: Addr,
+    pub arbiter: Addr,
+    // The coins held, after the owner fee.
+    pub amount: u128,
+    // Each recipient's share of the amount, credited once the arbiter releases it.
+    pub shares: Vec<(Addr, u128)>,
+    // After this time the sender can reclaim the coins themselves.
+    pub timeout: Option<Timestamp>,
+    pub status: EscrowStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EscrowStatus {
+    Pending,
+    Released,
+    Refunded,
+}
+
+pub fn escrows(storage: &mut dyn Storage) -> Bucket<'_, Escrow> {
+    bucket(storage, ESCROW_KEY)
+}
+
+pub fn escrows_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Escrow> {
+    bucket_read(storage, ESCROW_KEY)
+}
+
+// Index of the IDs of the escrows each address arbitrates.
+pub fn escrows_by_arbiter<'a>(storage: &'a mut dyn Storage, arbiter: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[ESCROWS_BY_ARBITER_KEY, arbiter.as_bytes()])
+}
+
+pub fn escrows_by_arbiter_read<'a>(
+    storage: &'a dyn Storage,
+    arbiter: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[ESCROWS_BY_ARBITER_KEY, arbiter.as_bytes()])
+}
+
+// Index of the IDs of the escrows each address sent or is a recipient of.
+pub fn escrows_by_party<'a>(storage: &'a mut dyn Storage, party: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[ESCROWS_BY_PARTY_KEY, party.as_bytes()])
+}
+
+pub fn escrows_by_party_read<'a>(
+    storage: &'a dyn Storage,
+    party: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[ESCROWS_BY_PARTY_KEY, party.as_bytes()])
+}
+
+// An account's requirement that its large withdrawals are approved by co-signers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalPolicy {
+    pub cosigners: Vec<Addr>,
+    // The number of co-signer approvals a large withdrawal needs.
+    pub threshold: u32,
+    // Withdrawals need approval once they'd take more than this quantity of a denom
+    // out without approval within the withdrawal limits' window.
+    pub approval_above: u128,
+    // How long a pending withdrawal can wait for approvals, in seconds.
+    pub expiry_seconds: u64,
+    // The co-signers who have approved removing the policy.
+    pub removal_approvals: Vec<Addr>,
+}
+
+pub fn withdrawal_policies(storage: &mut dyn Storage) -> Bucket<'_, WithdrawalPolicy> {
+    bucket(storage, WITHDRAWAL_POLICY_KEY)
+}
+
+pub fn withdrawal_policies_read(storage: &dyn Storage) -> ReadonlyBucket<'_, WithdrawalPolicy> {
+    bucket_read(storage, WITHDRAWAL_POLICY_KEY)
+}
+
+// A withdrawal that has been debited from the account balance, and is waiting for
+// approval or for its timelock before it's paid out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub account: Addr,
+    // Withdrawals held before other denoms could be withdrawn are all usei.
+    #[serde(default = "usei")]
+    pub denom: String,
+    pub amount: u128,
+    pub approvals: Vec<Addr>,
+    // The co-signer approvals needed, which is 0 for a withdrawal that is only
+    // timelocked.
+    pub required_approvals: u32,
+    // The approvals must be gathered before this time.
+    pub expires: Option<Timestamp>,
+    // The withdrawal can't be paid out before this time.
+    pub unlocks_at: Timestamp,
+    // When the withdrawal was counted against the withdrawal limits.
+    #[serde(default)]
+    pub counted_at: Option<Timestamp>,
+}
+
+fn usei() -> String {
+    String::from("usei")
+}
+
+impl PendingWithdrawal {
+    pub fn is_approved(&self) -> bool {
+        self.approvals.len() as u32 >= self.required_approvals
+    }
+
+    // A withdrawal only expires if it's still waiting for approvals.
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        !self.is_approved() && self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+pub fn pending_withdrawals(storage: &mut dyn Storage) -> Bucket<'_, PendingWithdrawal> {
+    bucket(storage, PENDING_WITHDRAWAL_KEY)
+}
+
+pub fn pending_withdrawals_read(storage: &dyn Storage) -> ReadonlyBucket<'_, PendingWithdrawal> {
+    bucket_read(storage, PENDING_WITHDRAWAL_KEY)
+}
+
+// Index of the IDs of each account's pending withdrawals.
+pub fn pending_withdrawals_by_account<'a>(
+    storage: &'a mut dyn Storage,
+    account: &Addr,
+) -> Bucket<'a, u64> {
+    Bucket::multilevel(
+        storage,
+        &[PENDING_WITHDRAWALS_BY_ACCOUNT_KEY, account.as_bytes()],
+    )
+}
+
+pub fn pending_withdrawals_by_account_read<'a>(
+    storage: &'a dyn Storage,
+    account: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(
+        storage,
+        &[PENDING_WITHDRAWALS_BY_ACCOUNT_KEY, account.as_bytes()],
+    )
+}
+
+// The usei owed to pending withdrawals, which is kept out of the coins staked.
+pub fn reserved_for_withdrawals(storage: &mut dyn Storage) -> Singleton<'_, u128> {
+    singleton(storage, RESERVED_FOR_WITHDRAWALS_KEY)
+}
+
+pub fn reserved_for_withdrawals_read(storage: &dyn Storage) -> ReadonlySingleton<'_, u128> {
+    singleton_read(storage, RESERVED_FOR_WITHDRAWALS_KEY)
+}
+
+// The coins withdrawn within one interval of the rate limit window.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalRecord {
+    // The interval's number, counting from the epoch.
+    pub interval: u64,
+    pub amount: u128,
+}
+
+// The withdrawals each account made within the current rate limit window, by
+// interval.
+pub fn withdrawal_history(storage: &mut dyn Storage) -> Bucket<'_, Vec<WithdrawalRecord>> {
+    bucket(storage, WITHDRAWAL_HISTORY_KEY)
+}
+
+pub fn withdrawal_history_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Vec<WithdrawalRecord>> {
+    bucket_read(storage, WITHDRAWAL_HISTORY_KEY)
+}
+
+// The withdrawals an account with a policy made without approval, by denom, within
+// the current rate limit window.
+pub fn unapproved_withdrawal_history<'a>(
+    storage: &'a mut dyn Storage,
+    account: &Addr,
+) -> Bucket<'a, Vec<WithdrawalRecord>> {
+    Bucket::multilevel(
+        storage,
+        &[UNAPPROVED_WITHDRAWAL_HISTORY_KEY, account.as_bytes()],
+    )
+}
+
+pub fn unapproved_withdrawal_history_read<'a>(
+    storage: &'a dyn Storage,
+    account: &Addr,
+) -> ReadonlyBucket<'a, Vec<WithdrawalRecord>> {
+    ReadonlyBucket::multilevel(
+        storage,
+        &[UNAPPROVED_WITHDRAWAL_HISTORY_KEY, account.as_bytes()],
+    )
+}
+
+// The withdrawals from the whole contract within the current rate limit window, by
+// interval.
+pub fn global_withdrawal_history(
+    storage: &mut dyn Storage,
+) -> Singleton<'_, Vec<WithdrawalRecord>> {
+    singleton(storage, GLOBAL_WITHDRAWAL_HISTORY_KEY)
+}
+
+pub fn global_withdrawal_history_read(
+    storage: &dyn Storage,
+) -> ReadonlySingleton<'_, Vec<WithdrawalRecord>> {
+    singleton_read(storage, GLOBAL_WITHDRAWAL_HISTORY_KEY)
+}
+
+// A withdrawal sent to another chain, which is restored to the account balance if
+// the transfer fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IbcTransfer {
+    pub account: Addr,
+    pub amount: u128,
+    pub channel_id: String,
+    pub remote_address: String,
+    // When the transfer was counted against the withdrawal limits.
+    #[serde(default)]
+    pub counted_at: Option<Timestamp>,
+}
+
+// The IBC transfers that haven't been acknowledged yet, by the sequence of their
+// packet on the channel.
+pub fn ibc_transfers<'a>(
+    storage: &'a mut dyn Storage,
+    channel_id: &str,
+) -> Bucket<'a, IbcTransfer> {
+    Bucket::multilevel(storage, &[IBC_TRANSFER_KEY, channel_id.as_bytes()])
+}
+
+pub fn ibc_transfers_read<'a>(
+    storage: &'a dyn Storage,
+    channel_id: &str,
+) -> ReadonlyBucket<'a, IbcTransfer> {
+    ReadonlyBucket::multilevel(storage, &[IBC_TRANSFER_KEY, channel_id.as_bytes()])
+}
+
+// The transfer that was just dispatched, until its reply tells us the packet sequence.
+pub fn ibc_transfer_in_flight(storage: &mut dyn Storage) -> Singleton<'_, IbcTransfer> {
+    singleton(storage, IBC_TRANSFER_IN_FLIGHT_KEY)
+}
+
+// Balances of denoms other than usei, such as those received over IBC, by account.
+pub fn denom_balances<'a>(storage: &'a mut dyn Storage, denom: &str) -> Bucket<'a, u128> {
+    Bucket::multilevel(storage, &[DENOM_BALANCE_KEY, denom.as_bytes()])
+}
+
+pub fn denom_balances_read<'a>(storage: &'a dyn Storage, denom: &str) -> ReadonlyBucket<'a, u128> {
+    ReadonlyBucket::multilevel(storage, &[DENOM_BALANCE_KEY, denom.as_bytes()])
+}
+
+// The denoms other than usei that an account has a balance of, keyed by denom.
+pub fn denoms_by_account<'a>(storage: &'a mut dyn Storage, account: &Addr) -> Bucket<'a, String> {
+    Bucket::multilevel(storage, &[DENOMS_BY_ACCOUNT_KEY, account.as_bytes()])
+}
+
+pub fn denoms_by_account_read<'a>(
+    storage: &'a dyn Storage,
+    account: &Addr,
+) -> ReadonlyBucket<'a, String> {
+    ReadonlyBucket::multilevel(storage, &[DENOMS_BY_ACCOUNT_KEY, account.as_bytes()])
+}
+
+// A withdrawal whose bank send has been dispatched but not yet replied to.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalAttempt {
+    pub account: Addr,
+    pub denom: String,
+    pub amount: u128,
+    // When the withdrawal was counted against the rate limits, if it was then.
+    pub counted_at: Option<Timestamp>,
+    // The caller of `PushPayouts` that's paid a bounty out of the owner's fees once
+    // this payout goes through, and how much.
+    pub bounty: Option<(Addr, u128)>,
+}
+
+// The withdrawals whose bank sends haven't been replied to yet, by attempt ID, so a
+// failed send can be matched back to its account in the reply.
+pub fn withdrawals_in_flight(storage: &mut dyn Storage) -> Bucket<'_, WithdrawalAttempt> {
+    bucket(storage, WITHDRAWAL_IN_FLIGHT_KEY)
+}
+
+pub fn withdrawals_in_flight_read(storage: &dyn Storage) -> ReadonlyBucket<'_, WithdrawalAttempt> {
+    bucket_read(storage, WITHDRAWAL_IN_FLIGHT_KEY)
+}
+
+// A withdrawal whose bank send failed, after its coins were restored to the account.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FailedWithdrawal {
+    pub id: u64,
+    pub account: Addr,
+    pub denom: String,
+    pub amount: u128,
+    pub error: String,
+    pub time: Timestamp,
+}
+
+pub fn failed_withdrawals(storage: &mut dyn Storage) -> Bucket<'_, FailedWithdrawal> {
+    bucket(storage, FAILED_WITHDRAWAL_KEY)
+}
+
+pub fn failed_withdrawals_read(storage: &dyn Storage) -> ReadonlyBucket<'_, FailedWithdrawal> {
+    bucket_read(storage, FAILED_WITHDRAWAL_KEY)
+}
+
+// Index of the IDs of each account's failed withdrawals.
+pub fn failed_withdrawals_by_account<'a>(
+    storage: &'a mut dyn Storage,
+    account: &Addr,
+) -> Bucket<'a, u64> {
+    Bucket::multilevel(
+        storage,
+        &[FAILED_WITHDRAWALS_BY_ACCOUNT_KEY, account.as_bytes()],
+    )
+}
+
+pub fn failed_withdrawals_by_account_read<'a>(
+    storage: &'a dyn Storage,
+    account: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(
+        storage,
+        &[FAILED_WITHDRAWALS_BY_ACCOUNT_KEY, account.as_bytes()],
+    )
+}
+
+// The sum of every account balance.
+pub fn total_balance(storage: &mut dyn Storage) -> Singleton<'_, u128> {
+    singleton(storage, TOTAL_BALANCE_KEY)
+}
+
+pub fn total_balance_read(storage: &dyn Storage) -> ReadonlySingleton<'_, u128> {
+    singleton_read(storage, TOTAL_BALANCE_KEY)
+}
+
+// Every account balance as of each block height, mirroring `resolver` at the end of
+// each entry point that changed it.
+pub const BALANCE_SNAPSHOTS: SnapshotMap<&Addr, u128> = SnapshotMap::new(
+    "balance_snapshots",
+    "balance_snapshots__checkpoints",
+    "balance_snapshots__changelog",
+    Strategy::EveryBlock,
+);
+
+// `total_balance` as of each block height.
+pub const TOTAL_LIABILITIES: SnapshotItem<u128> = SnapshotItem::new(
+    "total_liabilities",
+    "total_liabilities__checkpoints",
+    "total_liabilities__changelog",
+    Strategy::EveryBlock,
+);
+
+// How far `migrate` has got through backfilling balance snapshots and the total.
+// Only present while the backfill is still running.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Backfill {
+    // The last account backfilled so far.
+    pub start_after: Option<Addr>,
+    // The balances backfilled so far.
+    pub total: u128,
+}
+
+pub fn backfill(storage: &mut dyn Storage) -> Singleton<'_, Backfill> {
+    singleton(storage, BACKFILL_KEY)
+}
+
+pub fn backfill_read(storage: &dyn Storage) -> ReadonlySingleton<'_, Backfill> {
+    singleton_read(storage, BACKFILL_KEY)
+}
+
+// How the contract stakes the usei it holds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StakingConfig {
+    pub enabled: bool,
+    // Delegations are spread evenly over these validators.
+    pub validators: Vec<String>,
+    // The share of the contract's usei to delegate, with the same precision as the
+    // percent fee. The rest is kept liquid for withdrawals.
+    pub stake_percent: u128,
+    // The chain's unbonding period, in seconds.
+    pub unbonding_seconds: u64,
+}
+
+impl Default for StakingConfig {
+    fn default() -> Self {
+        StakingConfig {
+            enabled: false,
+            validators: vec![],
+            stake_percent: 0,
+            unbonding_seconds: 21 * 24 * 60 * 60,
+        }
+    }
+}
+
+pub fn staking_config(storage: &mut dyn Storage) -> Singleton<'_, StakingConfig> {
+    singleton(storage, STAKING_CONFIG_KEY)
+}
+
+pub fn staking_config_read(storage: &dyn Storage) -> ReadonlySingleton<'_, StakingConfig> {
+    singleton_read(storage, STAKING_CONFIG_KEY)
+}
+
+// usei that was undelegated, and returns to the contract once unbonding completes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Unbonding {
+    pub amount: u128,
+    pub completes: Timestamp,
+}
+
+pub fn unbonding(storage: &mut dyn Storage) -> Singleton<'_, Vec<Unbonding>> {
+    singleton(storage, UNBONDING_KEY)
+}
+
+pub fn unbonding_read(storage: &dyn Storage) -> ReadonlySingleton<'_, Vec<Unbonding>> {
+    singleton_read(storage, UNBONDING_KEY)
+}
+
+// The staking rewards earned per usei of balance, since staking was first enabled.
+pub fn reward_index(storage: &mut dyn Storage) -> Singleton<'_, Decimal> {
+    singleton(storage, REWARD_INDEX_KEY)
+}
+
+pub fn reward_index_read(storage: &dyn Storage) -> ReadonlySingleton<'_, Decimal> {
+    singleton_read(storage, REWARD_INDEX_KEY)
+}
+
+// The reward index when each account's rewards were last settled into its balance.
+pub fn reward_snapshots(storage: &mut dyn Storage) -> Bucket<'_, Decimal> {
+    bucket(storage, REWARD_SNAPSHOT_KEY)
+}
+
+pub fn reward_snapshots_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Decimal> {
+    bucket_read(storage, REWARD_SNAPSHOT_KEY)
+}
+
+// The contracts notified of every balance change.
+pub fn hooks(storage: &mut dyn Storage) -> Singleton<'_, Vec<Addr>> {
+    singleton(storage, HOOKS_KEY)
+}
+
+pub fn hooks_read(storage: &dyn Storage) -> ReadonlySingleton<'_, Vec<Addr>> {
+    singleton_read(storage, HOOKS_KEY)
+}
+
+// The balance changes made by the current execution, waiting to be snapshotted and
+// sent to the hooks.
+pub fn balance_changes(storage: &mut dyn Storage) -> Singleton<'_, Vec<BalanceChangedHookMsg>> {
+    singleton(storage, BALANCE_CHANGES_KEY)
+}
+
+pub fn balance_changes_read(
+    storage: &dyn Storage,
+) -> ReadonlySingleton<'_, Vec<BalanceChangedHookMsg>> {
+    singleton_read(storage, BALANCE_CHANGES_KEY)
+}
+
+// A completed `SendCoins`, kept for reconciling payments.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SendRecord {
+    pub id: u64,
+    pub sender: Addr,
+    // The coins sent, before the owner fee.
+    pub amount: u128,
+    pub shares: Vec<(Addr, u128)>,
+    pub memo: Option<String>,
+    pub reference_id: Option<String>,
+    pub time: Timestamp,
+}
+
+pub fn sends(storage: &mut dyn Storage) -> Bucket<'_, SendRecord> {
+    bucket(storage, SEND_KEY)
+}
+
+pub fn sends_read(storage: &dyn Storage) -> ReadonlyBucket<'_, SendRecord> {
+    bucket_read(storage, SEND_KEY)
+}
+
+// Index of the IDs of each sender's sends by their reference ID.
+pub fn sends_by_reference<'a>(storage: &'a mut dyn Storage, sender: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[SENDS_BY_REFERENCE_KEY, sender.as_bytes()])
+}
+
+pub fn sends_by_reference_read<'a>(
+    storage: &'a dyn Storage,
+    sender: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[SENDS_BY_REFERENCE_KEY, sender.as_bytes()])
+}
+
+// A request for payment, split between the recipients by weight once paid.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Invoice {
+    pub id: u64,
+    pub creator: Addr,
+    // The only addresses allowed to pay, if restricted.
+    pub payers: Option<Vec<Addr>>,
+    // The coins to be paid, before the owner fee.
+    pub amount: u128,
+    pub split: Vec<GroupMember>,
+    pub expiry: Option<Timestamp>,
+    pub status: InvoiceStatus,
+    pub paid_by: Option<Addr>,
+}
+
+impl Invoice {
+    // Whether the invoice can no longer be paid at `now`, whatever its status.
+    pub fn expired(&self, now: Timestamp) -> bool {
+        self.expiry.is_some_and(|expiry| expiry <= now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Open,
+    Paid,
+    Cancelled,
+}
+
+impl InvoiceStatus {
+    fn key(&self) -> &'static [u8] {
+        match self {
+            InvoiceStatus::Open => b"open",
+            InvoiceStatus::Paid => b"paid",
+            InvoiceStatus::Cancelled => b"cancelled",
+        }
+    }
+}
+
+pub fn invoices(storage: &mut dyn Storage) -> Bucket<'_, Invoice> {
+    bucket(storage, INVOICE_KEY)
+}
+
+pub fn invoices_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Invoice> {
+    bucket_read(storage, INVOICE_KEY)
+}
+
+// Index of the IDs of the invoices each address created.
+pub fn invoices_by_creator<'a>(storage: &'a mut dyn Storage, creator: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[INVOICES_BY_CREATOR_KEY, creator.as_bytes()])
+}
+
+pub fn invoices_by_creator_read<'a>(
+    storage: &'a dyn Storage,
+    creator: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[INVOICES_BY_CREATOR_KEY, creator.as_bytes()])
+}
+
+// Index of the IDs of the invoices each address may pay or has paid.
+pub fn invoices_by_payer<'a>(storage: &'a mut dyn Storage, payer: &Addr) -> Bucket<'a, u64> {
+    Bucket::multilevel(storage, &[INVOICES_BY_PAYER_KEY, payer.as_bytes()])
+}
+
+pub fn invoices_by_payer_read<'a>(
+    storage: &'a dyn Storage,
+    payer: &Addr,
+) -> ReadonlyBucket<'a, u64> {
+    ReadonlyBucket::multilevel(storage, &[INVOICES_BY_PAYER_KEY, payer.as_bytes()])
+}
+
+// Index of the IDs of the invoices in each status.
+pub fn invoices_by_status(storage: &mut dyn Storage, status: InvoiceStatus) -> Bucket<'_, u64> {
+    Bucket::multilevel(storage, &[INVOICES_BY_STATUS_KEY, status.key()])
+}
+
+pub fn invoices_by_status_read(
+    storage: &dyn Storage,
+    status: InvoiceStatus,
+) -> ReadonlyBucket<'_, u64> {
+    ReadonlyBucket::multilevel(storage, &[INVOICES_BY_STATUS_KEY, status.key()])
+}
+
+// Coins set aside for vouchers signed off-chain with the published key.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct VoucherPool {
+    pub pubkey: Option<Binary>,
+    pub balance: u128,
+}
+
+pub fn voucher_pool(storage: &mut dyn Storage) -> Singleton<'_, VoucherPool> {
+    singleton(storage, VOUCHER_POOL_KEY)
+}
+
+pub fn voucher_pool_read(storage: &dyn Storage) -> ReadonlySingleton<'_, VoucherPool> {
+    singleton_read(storage, VOUCHER_POOL_KEY)
+}
+
+// When each claimed voucher nonce was used.
+pub fn used_voucher_nonces(storage: &mut dyn Storage) -> Bucket<'_, Timestamp> {
+    bucket(storage, USED_VOUCHER_NONCE_KEY)
+}
+
+pub fn used_voucher_nonces_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Timestamp> {
+    bucket_read(storage, USED_VOUCHER_NONCE_KEY)
+}
+
+// Coins held for the claimants committed to by a merkle root.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MerkleDistribution {
+    pub id: u64,
+    pub funder: Addr,
+    // Hex encoded.
+    pub merkle_root: String,
+    pub total: u128,
+    pub claimed: u128,
+    // After this time the funder can reclaim what's left.
+    pub expiry: Timestamp,
+    pub reclaimed: bool,
+}
+
+pub fn merkle_distributions(storage: &mut dyn Storage) -> Bucket<'_, MerkleDistribution> {
+    bucket(storage, MERKLE_DISTRIBUTION_KEY)
+}
+
+pub fn merkle_distributions_read(storage: &dyn Storage) -> ReadonlyBucket<'_, MerkleDistribution> {
+    bucket_read(storage, MERKLE_DISTRIBUTION_KEY)
+}
+
+// The amount each address has claimed from a distribution.
+pub fn merkle_claims(storage: &mut dyn Storage, distribution_id: u64) -> Bucket<'_, u128> {
+    Bucket::multilevel(storage, &[MERKLE_CLAIM_KEY, &distribution_id.to_be_bytes()])
+}
+
+pub fn merkle_claims_read(storage: &dyn Storage, distribution_id: u64) -> ReadonlyBucket<'_, u128> {
+    ReadonlyBucket::multilevel(storage, &[MERKLE_CLAIM_KEY, &distribution_id.to_be_bytes()])
+}
+
+// The accounts that opted in to having their balances pushed to them.
+pub fn auto_payouts(storage: &mut dyn Storage) -> Bucket<'_, bool> {
+    bucket(storage, AUTO_PAYOUT_KEY)
+}
+
+pub fn auto_payouts_read(storage: &dyn Storage) -> ReadonlyBucket<'_, bool> {
+    bucket_read(storage, AUTO_PAYOUT_KEY)
+}
+
+// The block height of each account's last activity.
+pub fn last_activity(storage: &mut dyn Storage) -> Bucket<'_, u64> {
+    bucket(storage, LAST_ACTIVITY_KEY)
+}
+
+pub fn last_activity_read(storage: &dyn Storage) -> ReadonlyBucket<'_, u64> {
+    bucket_read(storage, LAST_ACTIVITY_KEY)
+}
+
+// How much each sender has credited an account.
+pub fn credit_sources<'a>(storage: &'a mut dyn Storage, account: &Addr) -> Bucket<'a, u128> {
+    Bucket::multilevel(storage, &[CREDIT_SOURCES_KEY, account.as_bytes()])
+}
+
+pub fn credit_sources_read<'a>(
+    storage: &'a dyn Storage,
+    account: &Addr,
+) -> ReadonlyBucket<'a, u128> {
+    ReadonlyBucket::multilevel(storage, &[CREDIT_SOURCES_KEY, account.as_bytes()])
+}
+
+// How much each account has been credited by senders past the ones it tracks.
+pub fn untracked_credit(storage: &mut dyn Storage) -> Bucket<'_, u128> {
+    bucket(storage, UNTRACKED_CREDIT_KEY)
+}
+
+pub fn untracked_credit_read(storage: &dyn Storage) -> ReadonlyBucket<'_, u128> {
+    bucket_read(storage, UNTRACKED_CREDIT_KEY)
+}
@@ -1,14 +1,43 @@
 #[cfg(test)]
 mod tests {
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coin, coins, from_binary, Addr, BankMsg, CosmosMsg, Deps, DepsMut};
-    use cosmwasm_storage::Bucket;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        coin, coins, from_binary, from_slice, to_binary, to_vec, Addr, BankMsg, Binary, CosmosMsg,
+        Decimal, Deps, DepsMut, DistributionMsg, Env, Event, FullDelegation, Reply, ReplyOn,
+        Response, StakingMsg, StdError, StdResult, Storage, SubMsg, SubMsgResponse, SubMsgResult,
+        Timestamp, Validator, WasmMsg,
+    };
+    use cosmwasm_storage::{to_length_prefixed, Bucket};
+    use k256::ecdsa::signature::Signer;
+    use k256::ecdsa::{Signature, SigningKey};
+    use sha2::{Digest, Sha256};
 
-    use crate::contract::{execute, instantiate, query};
+    use crate::contract::{
+        execute, instantiate, migrate, query, reply, sudo, IBC_TRANSFER_REPLY_ID, MAX_BATCH_SIZE,
+        MAX_CREDIT_SOURCES, MAX_HOOKS, MAX_MEMO_LENGTH, MAX_PAYOUTS_PER_CALL,
+        MAX_REFERENCE_ID_LENGTH, MAX_STREAMS_PER_RECIPIENT, MERKLE_LEAF_PREFIX, MERKLE_NODE_PREFIX,
+        RECEIVE_SPLIT_REPLY_ID,
+    };
+    use crate::helpers::UseiTransferContract;
+    use crate::ibc::MsgTransfer;
     use crate::msg::{
-        ExecuteMsg, GetOwnerResponse, GetWithdrawableCoinQuantityResponse, InstantiateMsg, QueryMsg,
+        AutoPayoutResponse, BalanceAtHeightResponse, BalanceChangedHookMsg, BatchSendItem,
+        DenomBalanceResponse, EscrowsResponse, ExecuteMsg, ExpiringAccount,
+        ExpiringAccountsResponse, FailedWithdrawalsResponse, GetOwnerResponse,
+        GetWithdrawableCoinQuantityResponse, HasRoleResponse, HooksResponse, InstantiateMsg,
+        InvoicesResponse, MerkleClaimResponse, MigrateMsg, PendingWithdrawalsResponse, QueryMsg,
+        ReceiveSplitMsg, RoleMembersResponse, SplitGroupsResponse, StakingResponse, StreamResponse,
+        StreamsResponse, SudoMsg, TotalLiabilitiesAtHeightResponse, VoucherNonceResponse,
+        VoucherPayload, WithdrawalPolicyResponse,
+    };
+    use crate::state::{
+        resolver, AccountBalance, BalanceExpiry, BatchFeeMode, Config, DuplicateRecipientPolicy,
+        Escrow, EscrowStatus, FailedWithdrawal, Fee, GroupMember, IbcTransfer, Invoice,
+        InvoiceStatus, PendingWithdrawal, Role, SelfSendPolicy, SendRecord, SplitGroup,
+        StakingConfig, Timelock, VoucherPool, WithdrawalLimits, CONFIG_KEY,
     };
-    use crate::state::{resolver, AccountBalance, Config, Fee};
     use crate::ContractError;
 
     fn assert_config_state(deps: Deps, expected: Config) {
@@ -44,11 +73,59 @@ mod tests {
         assert_eq!(data.balance, expected_balance);
     }
 
+    fn assert_has_role(deps: Deps, role: Role, address: &str, expected: bool) {
+        let res = query(
+            deps,
+            mock_env(),
+            QueryMsg::HasRole {
+                role,
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+        let data: HasRoleResponse = from_binary(&res).unwrap();
+        assert_eq!(data.has_role, expected);
+    }
+
+    // Assert that an execution was rejected because the sender lacked `expected_role`.
+    fn assert_unauthorized(res: Result<Response, ContractError>, expected_role: Role) {
+        match res.unwrap_err() {
+            ContractError::Unauthorized { role } => assert_eq!(role, expected_role),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    // Assert that `sender` is denied when executing `msg` without `role`, and
+    // allowed once the contract owner ('creator') has granted them `role`.
+    fn assert_requires_role(mut deps: DepsMut, sender: &str, role: Role, msg: ExecuteMsg) {
+        let res = execute(
+            deps.branch(),
+            mock_env(),
+            mock_info(sender, &[]),
+            msg.clone(),
+        );
+        assert_unauthorized(res, role);
+
+        grant_role(deps.branch(), "creator", role, sender);
+        execute(deps, mock_env(), mock_info(sender, &[]), msg)
+            .expect("sender is allowed after being granted the role");
+    }
+
+    fn grant_role(deps: DepsMut, admin: &str, role: Role, address: &str) {
+        let msg = ExecuteMsg::GrantRole {
+            role,
+            address: address.to_string(),
+        };
+        execute(deps, mock_env(), mock_info(admin, &[]), msg).expect("role is granted");
+    }
+
     fn mock_init_no_owner_specified(deps: DepsMut) {
         let msg = InstantiateMsg {
             owner: None,
             flat_fee: None,
             percent_fee: None,
+            self_send_policy: None,
+            duplicate_recipient_policy: None,
         };
 
         let info = mock_info("creator", &coins(2, "token"));
@@ -61,6 +138,26 @@ mod tests {
             owner: None,
             flat_fee: Some(flat_fee),
             percent_fee: Some(percent_fee),
+            self_send_policy: None,
+            duplicate_recipient_policy: None,
+        };
+
+        let info = mock_info("creator", &coins(2, "token"));
+        let _res = instantiate(deps, mock_env(), info, msg)
+            .expect("contract successfully handles InstantiateMsg");
+    }
+
+    fn mock_init_with_policies(
+        deps: DepsMut,
+        self_send_policy: SelfSendPolicy,
+        duplicate_recipient_policy: DuplicateRecipientPolicy,
+    ) {
+        let msg = InstantiateMsg {
+            owner: None,
+            flat_fee: None,
+            percent_fee: None,
+            self_send_policy: Some(self_send_policy),
+            duplicate_recipient_policy: Some(duplicate_recipient_policy),
         };
 
         let info = mock_info("creator", &coins(2, "token"));
@@ -73,6 +170,8 @@ mod tests {
             owner: Some(owner),
             flat_fee: None,
             percent_fee: None,
+            self_send_policy: None,
+            duplicate_recipient_policy: None,
         };
 
         let info = mock_info("creator", &coins(2, "token"));
@@ -92,6 +191,14 @@ mod tests {
                     flat_fee: 0,
                     percent_fee: 0,
                 },
+                paused: false,
+                self_send_policy: SelfSendPolicy::Allow,
+                duplicate_recipient_policy: DuplicateRecipientPolicy::Allow,
+                withdrawal_limits: WithdrawalLimits::default(),
+                fallback_account: None,
+                batch_fee_mode: BatchFeeMode::PerItem,
+                payout_bounty: 0,
+                balance_expiry: None,
             },
         )
     }
@@ -108,6 +215,14 @@ mod tests {
                     flat_fee: 0,
                     percent_fee: 0,
                 },
+                paused: false,
+                self_send_policy: SelfSendPolicy::Allow,
+                duplicate_recipient_policy: DuplicateRecipientPolicy::Allow,
+                withdrawal_limits: WithdrawalLimits::default(),
+                fallback_account: None,
+                batch_fee_mode: BatchFeeMode::PerItem,
+                payout_bounty: 0,
+                balance_expiry: None,
             },
         )
     }
@@ -120,6 +235,8 @@ mod tests {
             owner: None,
             flat_fee: Some(0),
             percent_fee: Some(10000000),
+            self_send_policy: None,
+            duplicate_recipient_policy: None,
         };
 
         let info = mock_info("creator", &coins(2, "token"));
@@ -164,6 +281,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -186,6 +307,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -208,6 +333,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -230,6 +359,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -250,6 +383,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -259,6 +396,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
             .expect("contract successfully sent the coins the second time");
@@ -279,6 +420,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -299,6 +444,10 @@ mod tests {
         let msg = ExecuteMsg::SendCoins {
             dest_addr1: String::from("alice"),
             dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
         };
 
         let _res = execute(deps.as_mut(), mock_env(), info, msg)
@@ -389,4 +538,4143 @@ mod tests {
             e => panic!("unexpected error: {:?}", e),
         }
     }
+
+    #[test]
+    fn owner_holds_every_role() {
+        let mut deps = mock_dependencies();
+        mock_init_owner_specified(deps.as_mut(), String::from("someone"));
+
+        for role in Role::ALL {
+            assert_has_role(deps.as_ref(), role, "someone", true);
+            assert_has_role(deps.as_ref(), role, "creator", false);
+        }
+    }
+
+    // Save the config as the first release of the contract stored it.
+    fn save_legacy_config(storage: &mut dyn Storage, owner: &str) {
+        let legacy = format!(
+            r#"{{"owner":"{}","fee":{{"flat_fee":"0","percent_fee":"0"}}}}"#,
+            owner
+        );
+        storage.set(&to_length_prefixed(CONFIG_KEY), legacy.as_bytes());
+    }
+
+    #[test]
+    fn legacy_config_loads_with_defaults() {
+        let mut deps = mock_dependencies();
+        save_legacy_config(&mut deps.storage, "creator");
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+        assert_eq!(
+            value,
+            Config {
+                owner: Addr::unchecked("creator"),
+                fee: Fee {
+                    flat_fee: 0,
+                    percent_fee: 0,
+                },
+                paused: false,
+                self_send_policy: SelfSendPolicy::Allow,
+                duplicate_recipient_policy: DuplicateRecipientPolicy::Allow,
+                withdrawal_limits: WithdrawalLimits::default(),
+                fallback_account: None,
+                batch_fee_mode: BatchFeeMode::PerItem,
+                payout_bounty: 0,
+                balance_expiry: None,
+            }
+        );
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 50);
+    }
+
+    #[test]
+    fn grant_and_revoke_role() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        grant_role(deps.as_mut(), "creator", Role::Pauser, "alice");
+        assert_has_role(deps.as_ref(), Role::Pauser, "alice", true);
+        // Granting one role doesn't grant any of the others.
+        assert_has_role(deps.as_ref(), Role::FeeManager, "alice", false);
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::RoleMembers { role: Role::Pauser },
+        )
+        .unwrap();
+        let data: RoleMembersResponse = from_binary(&res).unwrap();
+        assert_eq!(data.members, vec!["alice", "creator"]);
+
+        let msg = ExecuteMsg::RevokeRole {
+            role: Role::Pauser,
+            address: String::from("alice"),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg)
+            .expect("role is revoked");
+        assert_has_role(deps.as_ref(), Role::Pauser, "alice", false);
+    }
+
+    #[test]
+    fn grant_and_revoke_role_requires_admin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::GrantRole {
+            role: Role::FeeManager,
+            address: String::from("alice"),
+        };
+        assert_requires_role(deps.as_mut(), "mallory", Role::Admin, msg);
+
+        // A revoked admin loses the ability to manage roles.
+        let msg = ExecuteMsg::RevokeRole {
+            role: Role::Admin,
+            address: String::from("mallory"),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .expect("admin is revoked");
+        let res = execute(deps.as_mut(), mock_env(), mock_info("mallory", &[]), msg);
+        assert_unauthorized(res, Role::Admin);
+    }
+
+    #[test]
+    fn cannot_revoke_last_admin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::RevokeRole {
+            role: Role::Admin,
+            address: String::from("creator"),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        match res.unwrap_err() {
+            ContractError::CannotRevokeLastAdmin {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_has_role(deps.as_ref(), Role::Admin, "creator", true);
+    }
+
+    #[test]
+    fn update_fee_requires_fee_manager() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::UpdateFee {
+            flat_fee: 20,
+            percent_fee: 0,
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::FeeManager, msg);
+
+        // The new flat fee is charged on the next send.
+        let info = mock_info("bob", &[coin(300, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("alice"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg)
+            .expect("contract successfully sent the coins");
+        assert_account_balance(deps.as_ref(), "alice", 140);
+        assert_account_balance(deps.as_ref(), "creator", 20);
+    }
+
+    #[test]
+    fn pause_blocks_sends_and_withdrawals() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 5);
+
+        let msg = ExecuteMsg::SetPaused { paused: true };
+        assert_requires_role(deps.as_mut(), "bob", Role::Pauser, msg);
+
+        let info = mock_info("bob", &[coin(300, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("alice"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::ContractPaused {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 5 };
+        match execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap_err()
+        {
+            ContractError::ContractPaused {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Once unpaused, Alice can withdraw again.
+        let unpause = ExecuteMsg::SetPaused { paused: false };
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), unpause)
+            .expect("pauser can unpause");
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the withdraw event");
+        assert_account_balance(deps.as_ref(), "alice", 0);
+    }
+
+    #[test]
+    fn update_owner_requires_treasury() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 20, 0);
+
+        let msg = ExecuteMsg::UpdateOwner {
+            owner: String::from("vault"),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::Treasury, msg);
+
+        // Fees now go to the new owner.
+        let info = mock_info("bob", &[coin(300, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("alice"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg)
+            .expect("contract successfully sent the coins");
+        assert_account_balance(deps.as_ref(), "vault", 20);
+        assert_account_balance(deps.as_ref(), "creator", 0);
+    }
+
+    #[test]
+    fn helper_builds_execute_messages_with_funds() {
+        let contract = UseiTransferContract(Addr::unchecked("transfer"));
+
+        let msg = contract
+            .send_coins("alice", "bob", coins(300, "usei"))
+            .unwrap();
+        assert_eq!(
+            msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "transfer".into(),
+                msg: to_binary(&ExecuteMsg::SendCoins {
+                    dest_addr1: String::from("alice"),
+                    dest_addr2: String::from("bob"),
+                    notify_addr1: false,
+                    notify_addr2: false,
+                    memo: None,
+                    reference_id: None,
+                })
+                .unwrap(),
+                funds: coins(300, "usei"),
+            })
+        );
+
+        let msg = contract.withdraw_coins(5).unwrap();
+        assert_eq!(
+            msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "transfer".into(),
+                msg: to_binary(&ExecuteMsg::WithdrawCoins { quantity: 5 }).unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+
+    // Send `quantity` usei from 'carol' to the two destinations.
+    fn send_coins_from_carol(
+        deps: DepsMut,
+        dest_addr1: &str,
+        dest_addr2: &str,
+        quantity: u128,
+    ) -> Result<Response, ContractError> {
+        let info = mock_info("carol", &[coin(quantity, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: dest_addr1.to_string(),
+            dest_addr2: dest_addr2.to_string(),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        execute(deps, mock_env(), info, msg)
+    }
+
+    #[test]
+    fn recipient_policies_for_every_combination() {
+        let self_send_policies = [SelfSendPolicy::Allow, SelfSendPolicy::Reject];
+        let duplicate_policies = [
+            DuplicateRecipientPolicy::Allow,
+            DuplicateRecipientPolicy::Reject,
+            DuplicateRecipientPolicy::Merge,
+        ];
+        for self_send_policy in self_send_policies {
+            for duplicate_policy in duplicate_policies {
+                // Distinct destinations other than the sender are always fine.
+                let mut deps = mock_dependencies();
+                mock_init_with_policies(deps.as_mut(), self_send_policy, duplicate_policy);
+                send_coins_from_carol(deps.as_mut(), "alice", "bob", 300)
+                    .expect("contract successfully sent the coins");
+                assert_account_balance(deps.as_ref(), "alice", 150);
+                assert_account_balance(deps.as_ref(), "bob", 150);
+
+                // The sender names themselves as a destination.
+                let res = send_coins_from_carol(deps.as_mut(), "carol", "bob", 300);
+                match self_send_policy {
+                    SelfSendPolicy::Allow => {
+                        res.expect("self-send is allowed");
+                        assert_account_balance(deps.as_ref(), "carol", 150);
+                    }
+                    SelfSendPolicy::Reject => match res.unwrap_err() {
+                        ContractError::SelfSendNotAllowed {} => {}
+                        e => panic!("unexpected error: {:?}", e),
+                    },
+                }
+
+                // The same destination is named twice. An odd quantity shows whether
+                // the two halves were credited separately or merged.
+                let mut deps = mock_dependencies();
+                mock_init_with_policies(deps.as_mut(), self_send_policy, duplicate_policy);
+                let res = send_coins_from_carol(deps.as_mut(), "alice", "alice", 301);
+                match duplicate_policy {
+                    DuplicateRecipientPolicy::Allow => {
+                        res.expect("duplicates are allowed");
+                        assert_account_balance(deps.as_ref(), "alice", 300);
+                    }
+                    DuplicateRecipientPolicy::Reject => match res.unwrap_err() {
+                        ContractError::DuplicateRecipient { address } => {
+                            assert_eq!(address, "alice")
+                        }
+                        e => panic!("unexpected error: {:?}", e),
+                    },
+                    DuplicateRecipientPolicy::Merge => {
+                        res.expect("duplicates are merged");
+                        assert_account_balance(deps.as_ref(), "alice", 301);
+                    }
+                }
+
+                // The sender names themselves twice, so both policies apply and the
+                // self-send policy is checked first.
+                let res = send_coins_from_carol(deps.as_mut(), "carol", "carol", 301);
+                match (self_send_policy, duplicate_policy) {
+                    (SelfSendPolicy::Reject, _) => match res.unwrap_err() {
+                        ContractError::SelfSendNotAllowed {} => {}
+                        e => panic!("unexpected error: {:?}", e),
+                    },
+                    (SelfSendPolicy::Allow, DuplicateRecipientPolicy::Reject) => {
+                        match res.unwrap_err() {
+                            ContractError::DuplicateRecipient { .. } => {}
+                            e => panic!("unexpected error: {:?}", e),
+                        }
+                    }
+                    (SelfSendPolicy::Allow, DuplicateRecipientPolicy::Allow) => {
+                        res.expect("self-send and duplicates are allowed");
+                        assert_account_balance(deps.as_ref(), "carol", 300);
+                    }
+                    (SelfSendPolicy::Allow, DuplicateRecipientPolicy::Merge) => {
+                        res.expect("self-send is allowed and duplicates are merged");
+                        assert_account_balance(deps.as_ref(), "carol", 301);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn update_recipient_policies_requires_config_admin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::UpdateRecipientPolicies {
+            self_send_policy: Some(SelfSendPolicy::Reject),
+            duplicate_recipient_policy: None,
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::ConfigAdmin, msg);
+
+        // Only the policy that was given is changed.
+        assert_config_state(
+            deps.as_ref(),
+            Config {
+                owner: Addr::unchecked("creator"),
+                fee: Fee {
+                    flat_fee: 0,
+                    percent_fee: 0,
+                },
+                paused: false,
+                self_send_policy: SelfSendPolicy::Reject,
+                duplicate_recipient_policy: DuplicateRecipientPolicy::Allow,
+                withdrawal_limits: WithdrawalLimits::default(),
+                fallback_account: None,
+                batch_fee_mode: BatchFeeMode::PerItem,
+                payout_bounty: 0,
+                balance_expiry: None,
+            },
+        );
+        let res = send_coins_from_carol(deps.as_mut(), "carol", "bob", 300);
+        match res.unwrap_err() {
+            ContractError::SelfSendNotAllowed {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    fn create_split_group(deps: DepsMut, owner: &str, members: &[(&str, u64)]) -> u64 {
+        let msg = ExecuteMsg::CreateSplitGroup {
+            name: String::from("payroll"),
+            members: members
+                .iter()
+                .map(|(address, weight)| (address.to_string(), *weight))
+                .collect(),
+        };
+        let res = execute(deps, mock_env(), mock_info(owner, &[]), msg)
+            .expect("contract successfully created the split group");
+        let group_id = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "group_id")
+            .expect("group_id attribute");
+        group_id.value.parse().unwrap()
+    }
+
+    fn query_split_groups_by_owner(
+        deps: Deps,
+        owner: &str,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> Vec<u64> {
+        let res = query(
+            deps,
+            mock_env(),
+            QueryMsg::SplitGroupsByOwner {
+                owner: owner.to_string(),
+                start_after,
+                limit,
+            },
+        )
+        .unwrap();
+        let data: SplitGroupsResponse = from_binary(&res).unwrap();
+        data.groups.into_iter().map(|group| group.id).collect()
+    }
+
+    #[test]
+    fn create_and_query_split_groups() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let group_id = create_split_group(deps.as_mut(), "carol", &[("alice", 3), ("bob", 1)]);
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::SplitGroup { group_id }).unwrap();
+        let group: SplitGroup = from_binary(&res).unwrap();
+        assert_eq!(
+            group,
+            SplitGroup {
+                id: group_id,
+                owner: Addr::unchecked("carol"),
+                name: String::from("payroll"),
+                members: vec![
+                    GroupMember {
+                        address: Addr::unchecked("alice"),
+                        weight: 3,
+                    },
+                    GroupMember {
+                        address: Addr::unchecked("bob"),
+                        weight: 1,
+                    },
+                ],
+            }
+        );
+
+        // Groups are listed by their owner, a page at a time.
+        let second_id = create_split_group(deps.as_mut(), "carol", &[("alice", 1)]);
+        let third_id = create_split_group(deps.as_mut(), "carol", &[("bob", 1)]);
+        create_split_group(deps.as_mut(), "dave", &[("bob", 1)]);
+        assert_eq!(
+            query_split_groups_by_owner(deps.as_ref(), "carol", None, None),
+            vec![group_id, second_id, third_id]
+        );
+        assert_eq!(
+            query_split_groups_by_owner(deps.as_ref(), "carol", Some(group_id), Some(1)),
+            vec![second_id]
+        );
+        assert_eq!(
+            query_split_groups_by_owner(deps.as_ref(), "alice", None, None),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn create_split_group_with_invalid_members() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let create = |members: Vec<(&str, u64)>| ExecuteMsg::CreateSplitGroup {
+            name: String::from("payroll"),
+            members: members
+                .into_iter()
+                .map(|(address, weight)| (address.to_string(), weight))
+                .collect(),
+        };
+
+        let msg = create(vec![]);
+        match execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), msg).unwrap_err() {
+            ContractError::EmptySplitGroup {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = create(vec![("alice", 0)]);
+        match execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), msg).unwrap_err() {
+            ContractError::ZeroWeight { address } => assert_eq!(address, "alice"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = create(vec![("alice", 1), ("alice", 2)]);
+        match execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), msg).unwrap_err() {
+            ContractError::DuplicateRecipient { address } => assert_eq!(address, "alice"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn send_to_group_splits_by_weight() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        let group_id = create_split_group(deps.as_mut(), "carol", &[("alice", 3), ("bob", 1)]);
+
+        // Anyone can send to a group, not just its owner.
+        let info = mock_info("dave", &[coin(410, "usei")]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::SendToGroup { group_id },
+        )
+        .expect("contract successfully sent the coins");
+
+        // The 10 coin fee goes to the owner, and the remaining 400 is split 3:1.
+        assert_account_balance(deps.as_ref(), "alice", 300);
+        assert_account_balance(deps.as_ref(), "bob", 100);
+        assert_account_balance(deps.as_ref(), "creator", 10);
+        assert_account_balance(deps.as_ref(), "carol", 0);
+    }
+
+    #[test]
+    fn only_owner_can_update_or_delete_split_group() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let group_id = create_split_group(deps.as_mut(), "carol", &[("alice", 1), ("bob", 1)]);
+
+        let update = ExecuteMsg::UpdateSplitGroup {
+            group_id,
+            name: None,
+            members: Some(vec![(String::from("mallory"), 1)]),
+        };
+        let delete = ExecuteMsg::DeleteSplitGroup { group_id };
+        for msg in [update, delete] {
+            match execute(deps.as_mut(), mock_env(), mock_info("mallory", &[]), msg).unwrap_err() {
+                ContractError::NotSplitGroupOwner { .. } => {}
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+
+        // The owner replaces the members, and later sends only reach the new ones.
+        let update = ExecuteMsg::UpdateSplitGroup {
+            group_id,
+            name: Some(String::from("contractors")),
+            members: Some(vec![(String::from("dave"), 1)]),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), update)
+            .expect("owner can update the group");
+        let info = mock_info("carol", &[coin(100, "usei")]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::SendToGroup { group_id },
+        )
+        .expect("contract successfully sent the coins");
+        assert_account_balance(deps.as_ref(), "dave", 100);
+        assert_account_balance(deps.as_ref(), "alice", 0);
+
+        // Once deleted, the group can no longer be sent to or listed.
+        let delete = ExecuteMsg::DeleteSplitGroup { group_id };
+        execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), delete)
+            .expect("owner can delete the group");
+        let info = mock_info("carol", &[coin(100, "usei")]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::SendToGroup { group_id },
+        );
+        match res.unwrap_err() {
+            ContractError::Std(StdError::NotFound { .. }) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(
+            query_split_groups_by_owner(deps.as_ref(), "carol", None, None),
+            Vec::<u64>::new()
+        );
+    }
+
+    fn env_at(seconds_after_genesis: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = mock_env().block.time.plus_seconds(seconds_after_genesis);
+        env
+    }
+
+    // Have 'carol' stream `quantity` usei to Alice over the 100 seconds after
+    // the mock block time.
+    fn create_stream_to_alice(deps: DepsMut, quantity: u128) -> u64 {
+        let msg = ExecuteMsg::CreateStream {
+            recipient: String::from("alice"),
+            start: env_at(0).block.time,
+            end: env_at(100).block.time,
+        };
+        let info = mock_info("carol", &[coin(quantity, "usei")]);
+        let res = execute(deps, env_at(0), info, msg).expect("stream is created");
+        let stream_id = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "stream_id")
+            .expect("stream_id attribute");
+        stream_id.value.parse().unwrap()
+    }
+
+    fn assert_account_balance_at(deps: Deps, env: Env, address: &str, expected_balance: u128) {
+        let res = query(
+            deps,
+            env,
+            QueryMsg::GetWithdrawableCoinQuantity {
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+        let data: GetWithdrawableCoinQuantityResponse = from_binary(&res).unwrap();
+        assert_eq!(data.balance, expected_balance);
+    }
+
+    #[test]
+    fn stream_accrues_linearly() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        let stream_id = create_stream_to_alice(deps.as_mut(), 1010);
+
+        // The fee is taken up front, and the remaining 1000 accrues over 100 seconds.
+        assert_account_balance(deps.as_ref(), "creator", 10);
+        assert_account_balance_at(deps.as_ref(), env_at(0), "alice", 0);
+        assert_account_balance_at(deps.as_ref(), env_at(25), "alice", 250);
+        assert_account_balance_at(deps.as_ref(), env_at(100), "alice", 1000);
+        assert_account_balance_at(deps.as_ref(), env_at(500), "alice", 1000);
+
+        let res = query(deps.as_ref(), env_at(40), QueryMsg::Stream { stream_id }).unwrap();
+        let data: StreamResponse = from_binary(&res).unwrap();
+        assert_eq!(data.stream.amount, 1000);
+        assert_eq!(data.withdrawable, 400);
+    }
+
+    #[test]
+    fn withdraw_draws_from_accrued_stream_funds() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 5);
+        create_stream_to_alice(deps.as_mut(), 1000);
+
+        // Alice can't withdraw coins that haven't accrued yet.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 300 };
+        let res = execute(deps.as_mut(), env_at(25), mock_info("alice", &[]), msg);
+        match res.unwrap_err() {
+            ContractError::InsufficientFunds { balance, .. } => assert_eq!(balance, 255),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Her existing 5 coins and the 250 accrued by now can be withdrawn together.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 255 };
+        let res = execute(deps.as_mut(), env_at(25), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the withdraw event");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(255, "usei"),
+            })
+        );
+        assert_account_balance_at(deps.as_ref(), env_at(25), "alice", 0);
+
+        // Once the stream has paid out in full, it's closed.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 750 };
+        execute(deps.as_mut(), env_at(100), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the withdraw event");
+        let res = query(
+            deps.as_ref(),
+            env_at(100),
+            QueryMsg::StreamsByRecipient {
+                recipient: String::from("alice"),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let data: StreamsResponse = from_binary(&res).unwrap();
+        assert_eq!(data.streams, vec![]);
+    }
+
+    #[test]
+    fn cancel_stream_settles_and_refunds() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let stream_id = create_stream_to_alice(deps.as_mut(), 1000);
+
+        let msg = ExecuteMsg::CancelStream { stream_id };
+        let res = execute(
+            deps.as_mut(),
+            env_at(30),
+            mock_info("bob", &[]),
+            msg.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::CannotCancelStream { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Alice keeps the 300 accrued after 30 seconds, and Carol gets back the rest.
+        let res = execute(deps.as_mut(), env_at(30), mock_info("carol", &[]), msg)
+            .expect("sender can cancel the stream");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "carol".into(),
+                amount: coins(700, "usei"),
+            })
+        );
+        assert_account_balance_at(deps.as_ref(), env_at(100), "alice", 300);
+        query(deps.as_ref(), env_at(30), QueryMsg::Stream { stream_id }).unwrap_err();
+    }
+
+    #[test]
+    fn recipient_can_turn_down_streams() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::CreateStream {
+            recipient: String::from("alice"),
+            start: env_at(0).block.time,
+            end: env_at(100).block.time,
+        };
+        let info = mock_info("carol", &[coin(999, "usei")]);
+        match execute(deps.as_mut(), env_at(0), info, msg).unwrap_err() {
+            ContractError::StreamTooSmall { amount, minimum } => {
+                assert_eq!((amount, minimum), (999, 1000))
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let stream_ids: Vec<u64> = (0..MAX_STREAMS_PER_RECIPIENT)
+            .map(|_| create_stream_to_alice(deps.as_mut(), 1000))
+            .collect();
+        let msg = ExecuteMsg::CreateStream {
+            recipient: String::from("alice"),
+            start: env_at(0).block.time,
+            end: env_at(100).block.time,
+        };
+        let info = mock_info("carol", &[coin(1000, "usei")]);
+        match execute(deps.as_mut(), env_at(0), info, msg).unwrap_err() {
+            ContractError::TooManyStreams { recipient } => assert_eq!(recipient, "alice"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Alice keeps what one stream has paid her so far, and frees up its place.
+        let msg = ExecuteMsg::CancelStream {
+            stream_id: stream_ids[0],
+        };
+        let res = execute(deps.as_mut(), env_at(10), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "carol".into(),
+                amount: coins(900, "usei"),
+            })
+        );
+        assert_account_balance_at(deps.as_ref(), env_at(10), "alice", 100 * 30);
+        create_stream_to_alice(deps.as_mut(), 1000);
+
+        // Once the streams have paid out in full, they make room for new ones.
+        let msg = ExecuteMsg::CreateStream {
+            recipient: String::from("alice"),
+            start: env_at(100).block.time,
+            end: env_at(200).block.time,
+        };
+        let info = mock_info("carol", &[coin(1000, "usei")]);
+        execute(deps.as_mut(), env_at(100), info, msg).expect("stream is created");
+    }
+
+    #[test]
+    fn create_stream_with_invalid_schedule() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let schedules = [(50, 50), (60, 40), (0, 10)];
+        for (start, end) in schedules {
+            let msg = ExecuteMsg::CreateStream {
+                recipient: String::from("alice"),
+                start: env_at(start).block.time,
+                end: env_at(end).block.time,
+            };
+            let info = mock_info("carol", &[coin(1000, "usei")]);
+            // The last schedule has already ended by the time it's created.
+            match execute(deps.as_mut(), env_at(20), info, msg).unwrap_err() {
+                ContractError::InvalidStreamSchedule {} => {}
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    // Have 'carol' escrow `quantity` usei for Alice and Bob, arbitrated by 'judge'
+    // and optionally timing out 100 seconds after the mock block time.
+    fn create_escrow(deps: DepsMut, quantity: u128, with_timeout: bool) -> u64 {
+        let msg = ExecuteMsg::EscrowSendCoins {
+            dest_addr1: String::from("alice"),
+            dest_addr2: String::from("bob"),
+            arbiter: String::from("judge"),
+            timeout: with_timeout.then(|| env_at(100).block.time),
+        };
+        let info = mock_info("carol", &[coin(quantity, "usei")]);
+        let res = execute(deps, mock_env(), info, msg).expect("escrow is created");
+        let escrow_id = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "escrow_id")
+            .expect("escrow_id attribute");
+        escrow_id.value.parse().unwrap()
+    }
+
+    fn query_escrow(deps: Deps, escrow_id: u64) -> Escrow {
+        let res = query(deps, mock_env(), QueryMsg::Escrow { escrow_id }).unwrap();
+        from_binary(&res).unwrap()
+    }
+
+    #[test]
+    fn escrow_is_held_until_released() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        let escrow_id = create_escrow(deps.as_mut(), 310, false);
+
+        // The fee is collected, but nothing is withdrawable by the recipients yet.
+        assert_account_balance(deps.as_ref(), "creator", 10);
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_account_balance(deps.as_ref(), "bob", 0);
+        let escrow = query_escrow(deps.as_ref(), escrow_id);
+        assert_eq!(escrow.amount, 300);
+        assert_eq!(
+            escrow.shares,
+            vec![
+                (Addr::unchecked("alice"), 150),
+                (Addr::unchecked("bob"), 150)
+            ]
+        );
+        assert_eq!(escrow.status, EscrowStatus::Pending);
+
+        let msg = ExecuteMsg::ReleaseEscrow { escrow_id };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::NotEscrowArbiter { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("judge", &[]),
+            msg.clone(),
+        )
+        .expect("arbiter can release the escrow");
+        assert_account_balance(deps.as_ref(), "alice", 150);
+        assert_account_balance(deps.as_ref(), "bob", 150);
+        assert_eq!(
+            query_escrow(deps.as_ref(), escrow_id).status,
+            EscrowStatus::Released
+        );
+
+        // An escrow can only be settled once.
+        let res = execute(deps.as_mut(), mock_env(), mock_info("judge", &[]), msg);
+        match res.unwrap_err() {
+            ContractError::EscrowSettled { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn arbiter_refunds_escrow() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        let escrow_id = create_escrow(deps.as_mut(), 310, false);
+
+        let msg = ExecuteMsg::RefundEscrow { escrow_id };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("carol", &[]),
+            msg.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::NotEscrowArbiter { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // The sender gets back everything but the fee, and the recipients get nothing.
+        let res = execute(deps.as_mut(), mock_env(), mock_info("judge", &[]), msg)
+            .expect("arbiter can refund the escrow");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "carol".into(),
+                amount: coins(300, "usei"),
+            })
+        );
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_eq!(
+            query_escrow(deps.as_ref(), escrow_id).status,
+            EscrowStatus::Refunded
+        );
+    }
+
+    #[test]
+    fn sender_reclaims_escrow_after_timeout() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let escrow_id = create_escrow(deps.as_mut(), 300, true);
+        let no_timeout_id = create_escrow(deps.as_mut(), 300, false);
+
+        let msg = ExecuteMsg::ReclaimEscrow { escrow_id };
+        for (env, sender) in [(env_at(99), "carol"), (env_at(100), "alice")] {
+            match execute(deps.as_mut(), env, mock_info(sender, &[]), msg.clone()).unwrap_err() {
+                ContractError::EscrowNotTimedOut { .. } => assert_eq!(sender, "carol"),
+                ContractError::NotEscrowSender { .. } => assert_eq!(sender, "alice"),
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+
+        let res = execute(deps.as_mut(), env_at(100), mock_info("carol", &[]), msg)
+            .expect("sender can reclaim after the timeout");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "carol".into(),
+                amount: coins(300, "usei"),
+            })
+        );
+
+        // Without a timeout, only the arbiter can settle the escrow.
+        let msg = ExecuteMsg::ReclaimEscrow {
+            escrow_id: no_timeout_id,
+        };
+        let res = execute(deps.as_mut(), env_at(10_000), mock_info("carol", &[]), msg);
+        match res.unwrap_err() {
+            ContractError::EscrowNotTimedOut { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn query_escrows_by_arbiter_and_party() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let first_id = create_escrow(deps.as_mut(), 300, false);
+        let second_id = create_escrow(deps.as_mut(), 300, true);
+
+        let escrow_ids = |deps: Deps, msg: QueryMsg| -> Vec<u64> {
+            let res = query(deps, mock_env(), msg).unwrap();
+            let data: EscrowsResponse = from_binary(&res).unwrap();
+            data.escrows.into_iter().map(|escrow| escrow.id).collect()
+        };
+        let by_arbiter = |arbiter: &str| QueryMsg::EscrowsByArbiter {
+            arbiter: arbiter.to_string(),
+            start_after: None,
+            limit: None,
+        };
+        let by_party = |party: &str, start_after: Option<u64>| QueryMsg::EscrowsByParty {
+            party: party.to_string(),
+            start_after,
+            limit: None,
+        };
+
+        assert_eq!(
+            escrow_ids(deps.as_ref(), by_arbiter("judge")),
+            vec![first_id, second_id]
+        );
+        assert_eq!(
+            escrow_ids(deps.as_ref(), by_arbiter("carol")),
+            Vec::<u64>::new()
+        );
+        // Both the sender and the recipients are parties to an escrow.
+        for party in ["carol", "alice", "bob"] {
+            assert_eq!(
+                escrow_ids(deps.as_ref(), by_party(party, None)),
+                vec![first_id, second_id]
+            );
+        }
+        assert_eq!(
+            escrow_ids(deps.as_ref(), by_party("bob", Some(first_id))),
+            vec![second_id]
+        );
+        assert_eq!(
+            escrow_ids(deps.as_ref(), by_party("judge", None)),
+            Vec::<u64>::new()
+        );
+    }
+
+    // Give Alice 1000 coins, and require 2 of her 3 co-signers to approve any
+    // withdrawal of more than 100 within 60 seconds.
+    fn mock_init_with_withdrawal_policy(deps: DepsMut) {
+        set_address_with_coins(&mut resolver(deps.storage), "alice", 1000);
+        let msg = ExecuteMsg::SetWithdrawalPolicy {
+            cosigners: vec![
+                String::from("cosigner1"),
+                String::from("cosigner2"),
+                String::from("cosigner3"),
+            ],
+            threshold: 2,
+            approval_above: 100,
+            expiry_seconds: 60,
+        };
+        execute(deps, mock_env(), mock_info("alice", &[]), msg).expect("policy is set");
+    }
+
+    fn withdraw_pending(deps: DepsMut, quantity: u128) -> u64 {
+        let msg = ExecuteMsg::WithdrawCoins { quantity };
+        let res = execute(deps, mock_env(), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the pending withdrawal");
+        assert!(res.messages.is_empty());
+        let withdrawal_id = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "withdrawal_id")
+            .expect("withdrawal_id attribute");
+        withdrawal_id.value.parse().unwrap()
+    }
+
+    fn approve_withdrawal(
+        deps: DepsMut,
+        env: Env,
+        cosigner: &str,
+        withdrawal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::ApproveWithdrawal { withdrawal_id };
+        execute(deps, env, mock_info(cosigner, &[]), msg)
+    }
+
+    #[test]
+    fn small_withdrawal_skips_approval() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 100 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the withdraw event");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(100, "usei"),
+            })
+        );
+        assert_account_balance(deps.as_ref(), "alice", 900);
+    }
+
+    #[test]
+    fn small_withdrawals_add_up_to_the_approval_threshold() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+
+        let res = withdraw_at(deps.as_mut(), mock_env(), "alice", 60).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        // Another 60 would take more than 100 out without approval within the window.
+        withdraw_pending(deps.as_mut(), 60);
+        let msg = ExecuteMsg::WithdrawIbc {
+            channel_id: String::from("channel-0"),
+            remote_address: String::from("cosmos1remote"),
+            amount: 60,
+            timeout: env_at(600).block.time,
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::IbcWithdrawalHeld {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Once the window has passed, small withdrawals go straight out again.
+        let res = withdraw_at(deps.as_mut(), env_at(2 * 24 * 60 * 60), "alice", 60).unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn cancelled_withdrawal_no_longer_counts_against_limits() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+        let msg = ExecuteMsg::UpdateWithdrawalLimits {
+            limits: WithdrawalLimits {
+                window_seconds: 100,
+                per_account: Some(600),
+                global: Some(600),
+                timelock: None,
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        // Anyone can clean up an expired withdrawal, and Alice can cancel her own.
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 600);
+        let msg = ExecuteMsg::CancelWithdrawal { withdrawal_id };
+        execute(deps.as_mut(), env_at(61), mock_info("bob", &[]), msg).unwrap();
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 600);
+        let msg = ExecuteMsg::CancelWithdrawal { withdrawal_id };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        withdraw_pending(deps.as_mut(), 600);
+        assert_account_balance(deps.as_ref(), "alice", 400);
+    }
+
+    #[test]
+    fn large_withdrawal_waits_for_cosigners() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+
+        // The coins are debited straight away, so they can't be withdrawn again.
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 600);
+        assert_account_balance(deps.as_ref(), "alice", 400);
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PendingWithdrawals {
+                account: String::from("alice"),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let data: PendingWithdrawalsResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            data.withdrawals,
+            vec![PendingWithdrawal {
+                id: withdrawal_id,
+                account: Addr::unchecked("alice"),
+                denom: String::from("usei"),
+                amount: 600,
+                approvals: vec![],
+                required_approvals: 2,
+                expires: Some(env_at(60).block.time),
+                unlocks_at: mock_env().block.time,
+                counted_at: Some(mock_env().block.time),
+            }]
+        );
+
+        match approve_withdrawal(deps.as_mut(), mock_env(), "alice", withdrawal_id).unwrap_err() {
+            ContractError::NotCosigner {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = approve_withdrawal(deps.as_mut(), mock_env(), "cosigner1", withdrawal_id)
+            .expect("co-signer can approve");
+        assert!(res.messages.is_empty());
+        match approve_withdrawal(deps.as_mut(), mock_env(), "cosigner1", withdrawal_id).unwrap_err()
+        {
+            ContractError::AlreadyApproved {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // The second approval meets the threshold and pays out.
+        let res = approve_withdrawal(deps.as_mut(), mock_env(), "cosigner3", withdrawal_id)
+            .expect("co-signer can approve");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(600, "usei"),
+            })
+        );
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PendingWithdrawal { withdrawal_id },
+        )
+        .unwrap_err();
+        assert_account_balance(deps.as_ref(), "alice", 400);
+    }
+
+    #[test]
+    fn expired_withdrawal_is_restored() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 600);
+        approve_withdrawal(deps.as_mut(), mock_env(), "cosigner1", withdrawal_id)
+            .expect("co-signer can approve");
+
+        let res = approve_withdrawal(deps.as_mut(), env_at(60), "cosigner2", withdrawal_id);
+        match res.unwrap_err() {
+            ContractError::WithdrawalExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Only Alice can cancel before the expiry, but anyone can clean up after it.
+        let msg = ExecuteMsg::CancelWithdrawal { withdrawal_id };
+        let res = execute(
+            deps.as_mut(),
+            env_at(59),
+            mock_info("bob", &[]),
+            msg.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::CannotCancelWithdrawal { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        execute(deps.as_mut(), env_at(60), mock_info("bob", &[]), msg)
+            .expect("anyone can cancel an expired withdrawal");
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+
+        // Alice can also cancel her own withdrawal while it's still pending.
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 300);
+        let msg = ExecuteMsg::CancelWithdrawal { withdrawal_id };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg)
+            .expect("account can cancel its withdrawal");
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+    }
+
+    #[test]
+    fn withdrawal_policy_is_removed_by_cosigners() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+
+        // Alice can't replace her own policy with a weaker one.
+        let msg = ExecuteMsg::SetWithdrawalPolicy {
+            cosigners: vec![String::from("alice")],
+            threshold: 1,
+            approval_above: 1000,
+            expiry_seconds: 60,
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::WithdrawalPolicyExists {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::ApprovePolicyRemoval {
+            account: String::from("alice"),
+        };
+        for cosigner in ["cosigner1", "cosigner2"] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(cosigner, &[]),
+                msg.clone(),
+            )
+            .expect("co-signer can approve the removal");
+        }
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::WithdrawalPolicy {
+                account: String::from("alice"),
+            },
+        )
+        .unwrap();
+        let data: WithdrawalPolicyResponse = from_binary(&res).unwrap();
+        assert_eq!(data.policy, None);
+
+        // Without the policy, large withdrawals are paid out straight away.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 600 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the withdraw event");
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn set_withdrawal_policy_with_invalid_threshold() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        // Repeated co-signers only count once.
+        for threshold in [0, 2] {
+            let msg = ExecuteMsg::SetWithdrawalPolicy {
+                cosigners: vec![String::from("cosigner1"), String::from("cosigner1")],
+                threshold,
+                approval_above: 100,
+                expiry_seconds: 60,
+            };
+            match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+                ContractError::InvalidThreshold { cosigner_count, .. } => {
+                    assert_eq!(cosigner_count, 1)
+                }
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    fn mock_init_with_withdrawal_limits(deps: DepsMut, limits: WithdrawalLimits) {
+        set_address_with_coins(&mut resolver(deps.storage), "alice", 1000);
+        set_address_with_coins(&mut resolver(deps.storage), "bob", 1000);
+        let msg = ExecuteMsg::UpdateWithdrawalLimits { limits };
+        execute(deps, mock_env(), mock_info("creator", &[]), msg).expect("limits are updated");
+    }
+
+    fn withdraw_at(
+        deps: DepsMut,
+        env: Env,
+        sender: &str,
+        quantity: u128,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::WithdrawCoins { quantity };
+        execute(deps, env, mock_info(sender, &[]), msg)
+    }
+
+    #[test]
+    fn update_withdrawal_limits_requires_config_admin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let limits = WithdrawalLimits {
+            window_seconds: 100,
+            per_account: Some(500),
+            global: None,
+            timelock: None,
+        };
+        let msg = ExecuteMsg::UpdateWithdrawalLimits {
+            limits: limits.clone(),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::ConfigAdmin, msg);
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+        assert_eq!(value.withdrawal_limits, limits);
+
+        let msg = ExecuteMsg::UpdateWithdrawalLimits {
+            limits: WithdrawalLimits {
+                window_seconds: 0,
+                ..limits
+            },
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::InvalidWithdrawalWindow {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn per_account_withdrawal_limit() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: Some(500),
+                global: None,
+                timelock: None,
+            },
+        );
+
+        withdraw_at(deps.as_mut(), env_at(0), "alice", 300).unwrap();
+        withdraw_at(deps.as_mut(), env_at(50), "alice", 100).unwrap();
+        match withdraw_at(deps.as_mut(), env_at(60), "alice", 200).unwrap_err() {
+            ContractError::WithdrawalLimitExceeded {
+                remaining,
+                resets_at,
+            } => {
+                assert_eq!(remaining, 100);
+                // Withdrawals are counted by 4 second interval, and the first was made
+                // a second before its interval ended.
+                assert_eq!(resets_at.seconds(), env_at(101).block.time.seconds());
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        // The failed withdrawal didn't touch the balance.
+        assert_account_balance(deps.as_ref(), "alice", 600);
+        // Other accounts have their own allowance.
+        withdraw_at(deps.as_mut(), env_at(60), "bob", 500).unwrap();
+
+        // Once the first withdrawal leaves the window its allowance is freed up.
+        withdraw_at(deps.as_mut(), env_at(100), "alice", 400).unwrap_err();
+        withdraw_at(deps.as_mut(), env_at(101), "alice", 400).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 200);
+    }
+
+    #[test]
+    fn global_withdrawal_limit() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: None,
+                global: Some(800),
+                timelock: None,
+            },
+        );
+
+        withdraw_at(deps.as_mut(), env_at(0), "alice", 600).unwrap();
+        match withdraw_at(deps.as_mut(), env_at(10), "bob", 300).unwrap_err() {
+            ContractError::WithdrawalLimitExceeded {
+                remaining,
+                resets_at,
+            } => {
+                assert_eq!(remaining, 200);
+                assert_eq!(resets_at.seconds(), env_at(101).block.time.seconds());
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        withdraw_at(deps.as_mut(), env_at(10), "bob", 200).unwrap();
+    }
+
+    #[test]
+    fn failed_withdrawal_frees_its_allowance() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: Some(500),
+                global: Some(500),
+                timelock: None,
+            },
+        );
+
+        let res = withdraw_at(deps.as_mut(), env_at(0), "alice", 400).unwrap();
+        let reply_msg = Reply {
+            id: res.messages[0].id,
+            result: SubMsgResult::Err(String::from("insufficient funds")),
+        };
+        reply(deps.as_mut(), env_at(0), reply_msg).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+        withdraw_at(deps.as_mut(), env_at(1), "alice", 500).unwrap();
+    }
+
+    #[test]
+    fn timelocked_withdrawal_is_claimed_after_delay() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: None,
+                global: None,
+                timelock: Some(Timelock {
+                    above: 500,
+                    delay_seconds: 30,
+                }),
+            },
+        );
+
+        let res = withdraw_at(deps.as_mut(), env_at(0), "alice", 400).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 600);
+        assert_account_balance(deps.as_ref(), "alice", 0);
+
+        let msg = ExecuteMsg::ClaimWithdrawal { withdrawal_id };
+        match execute(
+            deps.as_mut(),
+            env_at(29),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err()
+        {
+            ContractError::WithdrawalLocked { unlocks_at, .. } => {
+                assert_eq!(unlocks_at, env_at(30).block.time)
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = execute(deps.as_mut(), env_at(30), mock_info("anyone", &[]), msg)
+            .expect("unlocked withdrawal can be claimed");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(600, "usei"),
+            })
+        );
+    }
+
+    #[test]
+    fn timelocked_withdrawal_needs_cosigners_before_claim() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+        let msg = ExecuteMsg::UpdateWithdrawalLimits {
+            limits: WithdrawalLimits {
+                timelock: Some(Timelock {
+                    above: 500,
+                    delay_seconds: 120,
+                }),
+                ..WithdrawalLimits::default()
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 600);
+        let msg = ExecuteMsg::ClaimWithdrawal { withdrawal_id };
+        match execute(
+            deps.as_mut(),
+            env_at(120),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap_err()
+        {
+            ContractError::WithdrawalNotApproved { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Reaching the threshold doesn't pay out while the withdrawal is locked.
+        approve_withdrawal(deps.as_mut(), env_at(10), "cosigner1", withdrawal_id).unwrap();
+        let res =
+            approve_withdrawal(deps.as_mut(), env_at(20), "cosigner2", withdrawal_id).unwrap();
+        assert!(res.messages.is_empty());
+
+        // An approved withdrawal no longer expires, so it can't be cancelled by others.
+        let cancel = ExecuteMsg::CancelWithdrawal { withdrawal_id };
+        match execute(deps.as_mut(), env_at(90), mock_info("bob", &[]), cancel).unwrap_err() {
+            ContractError::CannotCancelWithdrawal { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = execute(deps.as_mut(), env_at(120), mock_info("alice", &[]), msg)
+            .expect("approved and unlocked withdrawal can be claimed");
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    // Have Alice withdraw `amount` to a remote address over "channel-0", and reply as
+    // the chain would once the transfer packet is sent with `sequence`.
+    fn withdraw_ibc_from_alice(mut deps: DepsMut, amount: u128, sequence: u64) {
+        let msg = ExecuteMsg::WithdrawIbc {
+            channel_id: String::from("channel-0"),
+            remote_address: String::from("cosmos1remote"),
+            amount,
+            timeout: env_at(600).block.time,
+        };
+        let res = execute(deps.branch(), mock_env(), mock_info("alice", &[]), msg)
+            .expect("contract successfully created the transfer");
+        let transfer = MsgTransfer {
+            source_port: String::from("transfer"),
+            source_channel: String::from("channel-0"),
+            token: coin(amount, "usei"),
+            sender: String::from(MOCK_CONTRACT_ADDR),
+            receiver: String::from("cosmos1remote"),
+            timeout_timestamp: env_at(600).block.time,
+            memo: format!(r#"{{"ibc_callback":"{}"}}"#, MOCK_CONTRACT_ADDR),
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_on_success(
+                transfer.into_cosmos_msg(),
+                IBC_TRANSFER_REPLY_ID,
+            )]
+        );
+
+        let reply_msg = Reply {
+            id: IBC_TRANSFER_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![Event::new("send_packet")
+                    .add_attribute("packet_src_channel", "channel-0")
+                    .add_attribute("packet_sequence", sequence.to_string())],
+                data: None,
+            }),
+        };
+        reply(deps, mock_env(), reply_msg).expect("transfer is recorded");
+    }
+
+    fn query_ibc_transfer(deps: Deps, sequence: u64) -> StdResult<IbcTransfer> {
+        let msg = QueryMsg::IbcTransfer {
+            channel_id: String::from("channel-0"),
+            sequence,
+        };
+        query(deps, mock_env(), msg).and_then(|res| from_binary(&res))
+    }
+
+    // Report how the transfer went as ibc-hooks would, from its JSON.
+    fn ibc_lifecycle_complete(deps: DepsMut, json: &str) -> Result<Response, ContractError> {
+        let msg: SudoMsg = from_slice(json.as_bytes()).unwrap();
+        sudo(deps, mock_env(), msg)
+    }
+
+    #[test]
+    fn msg_transfer_encodes_as_protobuf() {
+        let transfer = MsgTransfer {
+            source_port: String::from("transfer"),
+            source_channel: String::from("channel-0"),
+            token: coin(400, "usei"),
+            sender: String::from("contract"),
+            receiver: String::from("r"),
+            timeout_timestamp: Timestamp::from_nanos(300),
+            memo: String::from("{}"),
+        };
+        let mut expected = vec![0x0a, 8];
+        expected.extend_from_slice(b"transfer");
+        expected.extend_from_slice(&[0x12, 9]);
+        expected.extend_from_slice(b"channel-0");
+        expected.extend_from_slice(&[0x1a, 11, 0x0a, 4]);
+        expected.extend_from_slice(b"usei");
+        expected.extend_from_slice(&[0x12, 3]);
+        expected.extend_from_slice(b"400");
+        expected.extend_from_slice(&[0x22, 8]);
+        expected.extend_from_slice(b"contract");
+        expected.extend_from_slice(&[0x2a, 1]);
+        expected.extend_from_slice(b"r");
+        // 300 as a varint.
+        expected.extend_from_slice(&[0x38, 0xac, 0x02]);
+        expected.extend_from_slice(&[0x42, 2]);
+        expected.extend_from_slice(b"{}");
+        assert_eq!(
+            transfer.into_cosmos_msg(),
+            CosmosMsg::Stargate {
+                type_url: String::from("/ibc.applications.transfer.v1.MsgTransfer"),
+                value: Binary::from(expected),
+            }
+        );
+    }
+
+    #[test]
+    fn withdraw_ibc_acknowledged() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 1000);
+
+        withdraw_ibc_from_alice(deps.as_mut(), 400, 29);
+        assert_account_balance(deps.as_ref(), "alice", 600);
+        assert_eq!(
+            query_ibc_transfer(deps.as_ref(), 29).unwrap(),
+            IbcTransfer {
+                account: Addr::unchecked("alice"),
+                amount: 400,
+                channel_id: String::from("channel-0"),
+                remote_address: String::from("cosmos1remote"),
+                counted_at: Some(mock_env().block.time),
+            }
+        );
+
+        let ack = r#"{"ibc_lifecycle_complete":{"ibc_ack":{"channel":"channel-0","sequence":29,"ack":"AQ==","success":true}}}"#;
+        ibc_lifecycle_complete(deps.as_mut(), ack).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 600);
+        assert!(query_ibc_transfer(deps.as_ref(), 29).is_err());
+    }
+
+    #[test]
+    fn withdraw_ibc_restored_on_error_ack() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 1000);
+
+        withdraw_ibc_from_alice(deps.as_mut(), 400, 29);
+        let ack = r#"{"ibc_lifecycle_complete":{"ibc_ack":{"channel":"channel-0","sequence":29,"ack":"bad receiver","success":false}}}"#;
+        let res = ibc_lifecycle_complete(deps.as_mut(), ack).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("ibc_lifecycle_complete")
+                .add_attribute("channel_id", "channel-0")
+                .add_attribute("sequence", "29")
+                .add_attribute("account", "alice")
+                .add_attribute("action", "ibc_transfer_failed")
+                .add_attribute("error", "bad receiver")
+                .add_attribute("amount", "400")]
+        );
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+
+        // A packet is only settled once.
+        match ibc_lifecycle_complete(deps.as_mut(), ack).unwrap_err() {
+            ContractError::UnknownIbcTransfer { sequence, .. } => assert_eq!(sequence, 29),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn withdraw_ibc_restored_on_timeout() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: Some(500),
+                global: None,
+                timelock: None,
+            },
+        );
+
+        withdraw_ibc_from_alice(deps.as_mut(), 400, 29);
+        let timeout =
+            r#"{"ibc_lifecycle_complete":{"ibc_timeout":{"channel":"channel-0","sequence":29}}}"#;
+        ibc_lifecycle_complete(deps.as_mut(), timeout).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+        // The timed out transfer no longer counts against Alice's limit.
+        withdraw_at(deps.as_mut(), mock_env(), "alice", 500).unwrap();
+    }
+
+    #[test]
+    fn withdraw_ibc_rejects_held_withdrawals() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+
+        let msg = ExecuteMsg::WithdrawIbc {
+            channel_id: String::from("channel-0"),
+            remote_address: String::from("cosmos1remote"),
+            amount: 600,
+            timeout: env_at(600).block.time,
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::IbcWithdrawalHeld {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::WithdrawIbc {
+            channel_id: String::from("channel-0"),
+            remote_address: String::from("cosmos1remote"),
+            amount: 100,
+            timeout: mock_env().block.time,
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::InvalidIbcTimeout {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+    }
+
+    const IBC_DENOM: &str = "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2";
+
+    fn receive_ibc_transfer(deps: DepsMut, quantity: u128, memo: &str) -> Response {
+        let msg = ExecuteMsg::ReceiveIbcTransfer {
+            memo: memo.to_string(),
+        };
+        let info = mock_info("ibchooks", &coins(quantity, IBC_DENOM));
+        execute(deps, mock_env(), info, msg).expect("transfer is received")
+    }
+
+    fn assert_denom_balance(deps: Deps, address: &str, denom: &str, expected_balance: u128) {
+        let msg = QueryMsg::DenomBalance {
+            address: address.to_string(),
+            denom: denom.to_string(),
+        };
+        let res = query(deps, mock_env(), msg).unwrap();
+        let data: DenomBalanceResponse = from_binary(&res).unwrap();
+        assert_eq!(data.balance, expected_balance);
+    }
+
+    #[test]
+    fn receive_ibc_transfer_splits_by_memo() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 100);
+
+        let memo = r#"{"recipients":[["alice",3],["bob",1]]}"#;
+        receive_ibc_transfer(deps.as_mut(), 1010, memo);
+        // The 1% and flat fee go to the owner, in the received denom.
+        assert_denom_balance(deps.as_ref(), "creator", IBC_DENOM, 20);
+        assert_denom_balance(deps.as_ref(), "alice", IBC_DENOM, 742);
+        assert_denom_balance(deps.as_ref(), "bob", IBC_DENOM, 247);
+        assert_account_balance(deps.as_ref(), "alice", 0);
+
+        let msg = ExecuteMsg::WithdrawDenom {
+            denom: IBC_DENOM.to_string(),
+            quantity: 742,
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .expect("alice can withdraw the received denom");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(742, IBC_DENOM),
+            })
+        );
+        assert_denom_balance(deps.as_ref(), "alice", IBC_DENOM, 0);
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::InsufficientFunds { balance, .. } => assert_eq!(balance, 0),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn withdraw_denom_is_held_and_limited_like_usei() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: Some(700),
+                global: None,
+                timelock: None,
+            },
+        );
+        let memo = r#"{"recipients":[["alice",1]]}"#;
+        receive_ibc_transfer(deps.as_mut(), 1000, memo);
+
+        let msg = ExecuteMsg::WithdrawDenom {
+            denom: IBC_DENOM.to_string(),
+            quantity: 600,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+        assert_denom_balance(deps.as_ref(), "alice", IBC_DENOM, 400);
+        let withdrawal_id = res.attributes[1].value.parse().unwrap();
+        approve_withdrawal(deps.as_mut(), mock_env(), "cosigner1", withdrawal_id).unwrap();
+        let res =
+            approve_withdrawal(deps.as_mut(), mock_env(), "cosigner2", withdrawal_id).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(600, IBC_DENOM),
+            })
+        );
+
+        // The held withdrawal counted against the limit.
+        let msg = ExecuteMsg::WithdrawDenom {
+            denom: IBC_DENOM.to_string(),
+            quantity: 100,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::WithdrawalLimitExceeded { remaining, .. } => assert_eq!(remaining, 0),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn receive_ibc_transfer_with_malformed_memo() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        // Without a fallback account, the owner keeps the coins.
+        let res = receive_ibc_transfer(deps.as_mut(), 100, "not json");
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "fallback_reason"));
+        assert_denom_balance(deps.as_ref(), "creator", IBC_DENOM, 100);
+
+        let msg = ExecuteMsg::UpdateFallbackAccount {
+            address: Some(String::from("recovery")),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::ConfigAdmin, msg);
+        for memo in [
+            r#"{"recipients":[]}"#,
+            r#"{"recipients":[["alice",0]]}"#,
+            r#"{"splits":[["alice",1]]}"#,
+        ] {
+            receive_ibc_transfer(deps.as_mut(), 100, memo);
+        }
+        assert_denom_balance(deps.as_ref(), "recovery", IBC_DENOM, 300);
+        assert_denom_balance(deps.as_ref(), "alice", IBC_DENOM, 0);
+    }
+
+    #[test]
+    fn receive_ibc_transfer_requires_one_coin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::ReceiveIbcTransfer {
+            memo: String::from(r#"{"recipients":[["alice",1]]}"#),
+        };
+        let info = mock_info("ibchooks", &[coin(100, IBC_DENOM), coin(100, "usei")]);
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::InvalidIbcFunds {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    fn assert_sudo_event(res: &Response, action: &str) {
+        let event = res
+            .events
+            .iter()
+            .find(|event| event.ty == "sudo")
+            .expect("sudo event");
+        assert!(event
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "action" && attr.value == action));
+    }
+
+    #[test]
+    fn sudo_update_owner_moves_roles() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = SudoMsg::UpdateOwner {
+            owner: String::from("newowner"),
+        };
+        let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_sudo_event(&res, "update_owner");
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetOwner {}).unwrap();
+        let value: GetOwnerResponse = from_binary(&res).unwrap();
+        assert_eq!("newowner", value.owner);
+        for role in Role::ALL {
+            assert_has_role(deps.as_ref(), role, "creator", false);
+            assert_has_role(deps.as_ref(), role, "newowner", true);
+        }
+        grant_role(deps.as_mut(), "newowner", Role::Pauser, "alice");
+    }
+
+    #[test]
+    fn sudo_update_fee_and_pause() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = SudoMsg::UpdateFee {
+            flat_fee: 5,
+            percent_fee: 200,
+        };
+        let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_sudo_event(&res, "update_fee");
+        let msg = SudoMsg::UpdateFee {
+            flat_fee: 5,
+            percent_fee: 10001,
+        };
+        match sudo(deps.as_mut(), mock_env(), msg).unwrap_err() {
+            ContractError::PercentFeeTooLarge { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let res = sudo(
+            deps.as_mut(),
+            mock_env(),
+            SudoMsg::SetPaused { paused: true },
+        )
+        .unwrap();
+        assert_sudo_event(&res, "set_paused");
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+        assert_eq!(
+            value.fee,
+            Fee {
+                flat_fee: 5,
+                percent_fee: 200,
+            }
+        );
+        assert!(value.paused);
+    }
+
+    #[test]
+    fn sudo_force_refund_ignores_pause_and_policy() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+        let msg = ExecuteMsg::SetPaused { paused: true };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let msg = SudoMsg::ForceRefund {
+            address: String::from("alice"),
+        };
+        let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_sudo_event(&res, "force_refund");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(1000, "usei"),
+            })
+        );
+        assert_account_balance(deps.as_ref(), "alice", 0);
+
+        // There's nothing left to refund.
+        let msg = SudoMsg::ForceRefund {
+            address: String::from("alice"),
+        };
+        let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn sudo_force_refund_pays_denoms_and_pending_withdrawals() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_policy(deps.as_mut());
+        let withdrawal_id = withdraw_pending(deps.as_mut(), 200);
+        receive_ibc_transfer(deps.as_mut(), 50, r#"{"recipients":[["alice",1]]}"#);
+
+        let msg = SudoMsg::ForceRefund {
+            address: String::from("alice"),
+        };
+        let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        let sent: Vec<CosmosMsg> = res.messages.iter().map(|msg| msg.msg.clone()).collect();
+        assert_eq!(
+            sent,
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "alice".into(),
+                    amount: coins(800, "usei"),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "alice".into(),
+                    amount: coins(50, IBC_DENOM),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "alice".into(),
+                    amount: coins(200, "usei"),
+                }),
+            ]
+        );
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_denom_balance(deps.as_ref(), "alice", IBC_DENOM, 0);
+        let msg = ExecuteMsg::CancelWithdrawal { withdrawal_id };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+
+        // There's nothing left to refund.
+        let msg = SudoMsg::ForceRefund {
+            address: String::from("alice"),
+        };
+        let res = sudo(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn failed_withdrawal_is_restored_and_logged() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 1000);
+
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 400 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Always);
+        assert_account_balance(deps.as_ref(), "alice", 600);
+
+        let reply_msg = Reply {
+            id: res.messages[0].id,
+            result: SubMsgResult::Err(String::from("insufficient funds")),
+        };
+        reply(deps.as_mut(), env_at(5), reply_msg).expect("failure is handled");
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+
+        let msg = QueryMsg::FailedWithdrawals {
+            account: String::from("alice"),
+            start_after: None,
+            limit: None,
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let data: FailedWithdrawalsResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            data.withdrawals,
+            vec![FailedWithdrawal {
+                id: 1,
+                account: Addr::unchecked("alice"),
+                denom: String::from("usei"),
+                amount: 400,
+                error: String::from("insufficient funds"),
+                time: env_at(5).block.time,
+            }]
+        );
+
+        // A successful send is forgotten once replied to, so it can't be restored.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 100 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let id = res.messages[0].id;
+        let reply_msg = Reply {
+            id,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        reply(deps.as_mut(), env_at(5), reply_msg).expect("success is handled");
+        let reply_msg = Reply {
+            id,
+            result: SubMsgResult::Err(String::from("insufficient funds")),
+        };
+        reply(deps.as_mut(), env_at(5), reply_msg).unwrap_err();
+        assert_account_balance(deps.as_ref(), "alice", 900);
+    }
+
+    fn validator(address: &str) -> Validator {
+        Validator {
+            address: address.to_string(),
+            commission: Decimal::percent(5),
+            max_commission: Decimal::percent(10),
+            max_change_rate: Decimal::percent(1),
+        }
+    }
+
+    fn delegation(validator: &str, amount: u128, rewards: u128) -> FullDelegation {
+        FullDelegation {
+            delegator: Addr::unchecked(MOCK_CONTRACT_ADDR),
+            validator: validator.to_string(),
+            amount: coin(amount, "usei"),
+            can_redelegate: coin(amount, "usei"),
+            accumulated_rewards: coins(rewards, "usei"),
+        }
+    }
+
+    fn update_staking(deps: DepsMut, enabled: bool, stake_percent: u128) {
+        let msg = ExecuteMsg::UpdateStaking {
+            staking: StakingConfig {
+                enabled,
+                validators: vec![String::from("val1"), String::from("val2")],
+                stake_percent,
+                unbonding_seconds: 100,
+            },
+        };
+        execute(deps, mock_env(), mock_info("creator", &[]), msg).expect("staking is updated");
+    }
+
+    #[test]
+    fn update_staking_requires_treasury() {
+        let mut deps = mock_dependencies();
+        deps.querier
+            .update_staking("usei", &[validator("val1"), validator("val2")], &[]);
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let staking = StakingConfig {
+            enabled: true,
+            validators: vec![String::from("val1")],
+            stake_percent: 5000,
+            unbonding_seconds: 100,
+        };
+        let msg = ExecuteMsg::UpdateStaking {
+            staking: staking.clone(),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::Treasury, msg);
+
+        let msg = ExecuteMsg::UpdateStaking {
+            staking: StakingConfig {
+                stake_percent: 10001,
+                ..staking.clone()
+            },
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::StakePercentTooLarge { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let msg = ExecuteMsg::UpdateStaking {
+            staking: StakingConfig {
+                validators: vec![String::from("val3")],
+                ..staking
+            },
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::UnknownValidator { validator } => assert_eq!(validator, "val3"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rebalance_delegates_staked_share() {
+        let mut deps = mock_dependencies_with_balance(&coins(2501, "usei"));
+        deps.querier
+            .update_staking("usei", &[validator("val1"), validator("val2")], &[]);
+        mock_init_no_owner_specified(deps.as_mut());
+        update_staking(deps.as_mut(), true, 5005);
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 2000).unwrap();
+        create_escrow(deps.as_mut(), 501, false);
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap();
+        // Just over half of the 2000 usei owed to alice and bob is staked, with the
+        // odd coin going to the first validator, and the escrowed coins aren't.
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|sub_msg| sub_msg.msg)
+                .collect::<Vec<CosmosMsg>>(),
+            vec![
+                CosmosMsg::Staking(StakingMsg::Delegate {
+                    validator: String::from("val1"),
+                    amount: coin(501, "usei"),
+                }),
+                CosmosMsg::Staking(StakingMsg::Delegate {
+                    validator: String::from("val2"),
+                    amount: coin(500, "usei"),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rebalance_undelegates_when_disabled() {
+        let mut deps = mock_dependencies_with_balance(&coins(500, "usei"));
+        deps.querier.update_staking(
+            "usei",
+            &[validator("val1"), validator("val2")],
+            &[delegation("val1", 700, 0), delegation("old", 300, 0)],
+        );
+        mock_init_no_owner_specified(deps.as_mut());
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 1500).unwrap();
+
+        // With 1000 of 1500 usei delegated, a third has to come back, starting
+        // with the validator that's no longer configured.
+        update_staking(deps.as_mut(), true, 3334);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|sub_msg| sub_msg.msg)
+                .collect::<Vec<CosmosMsg>>(),
+            vec![
+                CosmosMsg::Staking(StakingMsg::Undelegate {
+                    validator: String::from("old"),
+                    amount: coin(300, "usei"),
+                }),
+                CosmosMsg::Staking(StakingMsg::Undelegate {
+                    validator: String::from("val1"),
+                    amount: coin(200, "usei"),
+                }),
+            ]
+        );
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Staking {}).unwrap();
+        let data: StakingResponse = from_binary(&res).unwrap();
+        assert_eq!(data.unbonding.iter().map(|u| u.amount).sum::<u128>(), 500);
+
+        // Disabling staking brings everything back.
+        update_staking(deps.as_mut(), false, 3334);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 2);
+    }
+
+    #[test]
+    fn rebalance_requires_treasury_and_unpaused() {
+        let mut deps = mock_dependencies_with_balance(&coins(1000, "usei"));
+        deps.querier
+            .update_staking("usei", &[validator("val1"), validator("val2")], &[]);
+        mock_init_no_owner_specified(deps.as_mut());
+        update_staking(deps.as_mut(), true, 5000);
+        assert_requires_role(
+            deps.as_mut(),
+            "alice",
+            Role::Treasury,
+            ExecuteMsg::Rebalance {},
+        );
+
+        let msg = ExecuteMsg::SetPaused { paused: true };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        match execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap_err()
+        {
+            ContractError::ContractPaused {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn withdrawal_waits_for_unbonding_when_liquidity_is_short() {
+        let mut deps = mock_dependencies_with_balance(&coins(100, "usei"));
+        deps.querier.update_staking(
+            "usei",
+            &[validator("val1"), validator("val2")],
+            &[delegation("val1", 900, 0)],
+        );
+        mock_init_no_owner_specified(deps.as_mut());
+        update_staking(deps.as_mut(), true, 9000);
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 1000).unwrap();
+
+        // Only 100 usei is liquid, so the other 400 has to be undelegated first.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 500 };
+        let res = execute(deps.as_mut(), env_at(0), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|sub_msg| sub_msg.msg)
+                .collect::<Vec<CosmosMsg>>(),
+            vec![CosmosMsg::Staking(StakingMsg::Undelegate {
+                validator: String::from("val1"),
+                amount: coin(400, "usei"),
+            })]
+        );
+        let withdrawal_id: u64 = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "withdrawal_id")
+            .expect("withdrawal_id attribute")
+            .value
+            .parse()
+            .unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 0);
+
+        // The liquid coins are held for Alice, so rebalancing doesn't stake them,
+        // and only 90% of Bob's 500 usei stays staked.
+        deps.querier.update_staking(
+            "usei",
+            &[validator("val1"), validator("val2")],
+            &[delegation("val1", 500, 0)],
+        );
+        let res = execute(
+            deps.as_mut(),
+            env_at(1),
+            mock_info("creator", &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|sub_msg| sub_msg.msg)
+                .collect::<Vec<CosmosMsg>>(),
+            vec![CosmosMsg::Staking(StakingMsg::Undelegate {
+                validator: String::from("val1"),
+                amount: coin(50, "usei"),
+            })]
+        );
+
+        let msg = ExecuteMsg::ClaimWithdrawal { withdrawal_id };
+        match execute(
+            deps.as_mut(),
+            env_at(99),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err()
+        {
+            ContractError::WithdrawalLocked { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = execute(deps.as_mut(), env_at(100), mock_info("anyone", &[]), msg)
+            .expect("unbonded withdrawal can be claimed");
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(500, "usei"),
+            })
+        );
+    }
+
+    #[test]
+    fn unbonding_hold_claims_rewards_first() {
+        let mut deps = mock_dependencies_with_balance(&coins(100, "usei"));
+        deps.querier.update_staking(
+            "usei",
+            &[validator("val1"), validator("val2")],
+            &[delegation("val1", 900, 60)],
+        );
+        mock_init_no_owner_specified(deps.as_mut());
+        update_staking(deps.as_mut(), true, 9000);
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 1000).unwrap();
+
+        // The rewards the undelegation would pay out are withdrawn and shared out
+        // first, all to Bob since Alice's balance is on its way out.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 500 };
+        let res = execute(deps.as_mut(), env_at(0), mock_info("alice", &[]), msg).unwrap();
+        let withdrawal_id: u64 = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "withdrawal_id")
+            .expect("withdrawal_id attribute")
+            .value
+            .parse()
+            .unwrap();
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|sub_msg| sub_msg.msg)
+                .collect::<Vec<CosmosMsg>>(),
+            vec![
+                CosmosMsg::Distribution(DistributionMsg::WithdrawDelegatorReward {
+                    validator: String::from("val1"),
+                }),
+                CosmosMsg::Staking(StakingMsg::Undelegate {
+                    validator: String::from("val1"),
+                    amount: coin(400, "usei"),
+                }),
+            ]
+        );
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_account_balance(deps.as_ref(), "bob", 560);
+
+        // Alice still gets the whole 500 once it has unbonded.
+        let msg = ExecuteMsg::ClaimWithdrawal { withdrawal_id };
+        let res = execute(deps.as_mut(), env_at(100), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".into(),
+                amount: coins(500, "usei"),
+            })
+        );
+    }
+
+    #[test]
+    fn claimed_rewards_are_shared_pro_rata() {
+        let mut deps = mock_dependencies();
+        deps.querier.update_staking(
+            "usei",
+            &[validator("val1"), validator("val2")],
+            &[delegation("val1", 1000, 90), delegation("val2", 1000, 30)],
+        );
+        mock_init_no_owner_specified(deps.as_mut());
+        send_coins_from_carol(deps.as_mut(), "alice", "alice", 2000).unwrap();
+        send_coins_from_carol(deps.as_mut(), "bob", "bob", 1000).unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Distribution(DistributionMsg::WithdrawDelegatorReward {
+                validator: String::from("val1"),
+            })
+        );
+        assert_eq!(res.messages.len(), 2);
+        assert_account_balance(deps.as_ref(), "alice", 2080);
+        assert_account_balance(deps.as_ref(), "bob", 1040);
+
+        // Alice's rewards are kept when her balance changes, and only her new
+        // balance counts towards the next ones.
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 1080 };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {},
+        )
+        .unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 1058);
+        assert_account_balance(deps.as_ref(), "bob", 1098);
+    }
+
+    fn add_hook(deps: DepsMut, hook: &str) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::AddHook {
+            addr: hook.to_string(),
+        };
+        execute(deps, mock_env(), mock_info("creator", &[]), msg)
+    }
+
+    fn query_hooks(deps: Deps) -> Vec<String> {
+        let res = query(deps, mock_env(), QueryMsg::Hooks {}).unwrap();
+        let value: HooksResponse = from_binary(&res).unwrap();
+        value.hooks
+    }
+
+    fn balance_changed_hook(
+        hook: &str,
+        address: &str,
+        old_balance: u128,
+        new_balance: u128,
+    ) -> SubMsg {
+        let msg = BalanceChangedHookMsg {
+            address: address.to_string(),
+            old_balance,
+            new_balance,
+        };
+        SubMsg::new(msg.into_cosmos_msg(hook).unwrap())
+    }
+
+    #[test]
+    fn add_and_remove_hooks_require_config_admin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::AddHook {
+            addr: String::from("observer"),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::ConfigAdmin, msg);
+        let msg = ExecuteMsg::RemoveHook {
+            addr: String::from("observer"),
+        };
+        assert_requires_role(deps.as_mut(), "bob", Role::ConfigAdmin, msg);
+        assert!(query_hooks(deps.as_ref()).is_empty());
+    }
+
+    #[test]
+    fn add_and_remove_hooks() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        add_hook(deps.as_mut(), "observer1").unwrap();
+        add_hook(deps.as_mut(), "observer2").unwrap();
+        assert_eq!(query_hooks(deps.as_ref()), vec!["observer1", "observer2"]);
+
+        match add_hook(deps.as_mut(), "observer1").unwrap_err() {
+            ContractError::HookAlreadyRegistered { hook } => assert_eq!(hook, "observer1"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::RemoveHook {
+            addr: String::from("observer1"),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(query_hooks(deps.as_ref()), vec!["observer2"]);
+        match execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err() {
+            ContractError::HookNotRegistered { hook } => assert_eq!(hook, "observer1"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        for i in 1..MAX_HOOKS {
+            add_hook(deps.as_mut(), &format!("extra{}", i)).unwrap();
+        }
+        match add_hook(deps.as_mut(), "onetoomany").unwrap_err() {
+            ContractError::TooManyHooks { max } => assert_eq!(max, MAX_HOOKS),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn hooks_are_called_on_balance_changes() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        // Nothing is sent until a hook is registered.
+        let res = send_coins_from_carol(deps.as_mut(), "alice", "bob", 300).unwrap();
+        assert!(res.messages.is_empty());
+
+        add_hook(deps.as_mut(), "observer1").unwrap();
+        add_hook(deps.as_mut(), "observer2").unwrap();
+        let res = send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                balance_changed_hook("observer1", "alice", 150, 200),
+                balance_changed_hook("observer2", "alice", 150, 200),
+                balance_changed_hook("observer1", "bob", 150, 200),
+                balance_changed_hook("observer2", "bob", 150, 200),
+            ]
+        );
+        let expected = Binary::from(
+            br#"{"balance_changed_hook":{"address":"alice","old_balance":"150","new_balance":"200"}}"#,
+        );
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            }) => {
+                assert_eq!(contract_addr, "observer1");
+                assert_eq!(msg, &expected);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 50 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 3);
+        assert_eq!(
+            res.messages[1..],
+            [
+                balance_changed_hook("observer1", "alice", 200, 150),
+                balance_changed_hook("observer2", "alice", 200, 150),
+            ]
+        );
+    }
+
+    #[test]
+    fn send_coins_calls_back_notified_recipients() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+
+        let info = mock_info("carol", &[coin(310, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("vault"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: true,
+            notify_addr2: false,
+            memo: Some(String::from("invoice 7")),
+            reference_id: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let callback = ReceiveSplitMsg {
+            sender: String::from("carol"),
+            amount: 150,
+            memo: Some(String::from("invoice 7")),
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_on_error(
+                callback.into_cosmos_msg("vault").unwrap(),
+                RECEIVE_SPLIT_REPLY_ID
+            )]
+        );
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => assert_eq!(
+                msg,
+                &Binary::from(
+                    br#"{"receive_split":{"sender":"carol","amount":"150","memo":"invoice 7"}}"#
+                )
+            ),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        // A recipient named twice is called back once, for its whole share.
+        let info = mock_info("carol", &[coin(310, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("vault"),
+            dest_addr2: String::from("vault"),
+            notify_addr1: true,
+            notify_addr2: true,
+            memo: None,
+            reference_id: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let callback = ReceiveSplitMsg {
+            sender: String::from("carol"),
+            amount: 300,
+            memo: None,
+        };
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_on_error(
+                callback.into_cosmos_msg("vault").unwrap(),
+                RECEIVE_SPLIT_REPLY_ID
+            )]
+        );
+        assert_account_balance(deps.as_ref(), "vault", 450);
+    }
+
+    #[test]
+    fn failed_receive_callback_keeps_the_credit() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let info = mock_info("carol", &[coin(300, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("vault"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: true,
+            notify_addr2: true,
+            memo: None,
+            reference_id: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+
+        let msg = Reply {
+            id: RECEIVE_SPLIT_REPLY_ID,
+            result: SubMsgResult::Err(String::from("bob is not a contract")),
+        };
+        let res = reply(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(res.attributes[0].value, "receive_split_failed");
+        assert_account_balance(deps.as_ref(), "vault", 150);
+        assert_account_balance(deps.as_ref(), "bob", 150);
+    }
+
+    fn send_coins_with_reference(
+        deps: DepsMut,
+        memo: Option<&str>,
+        reference_id: Option<&str>,
+    ) -> Result<Response, ContractError> {
+        let info = mock_info("carol", &[coin(300, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("alice"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: memo.map(String::from),
+            reference_id: reference_id.map(String::from),
+        };
+        execute(deps, mock_env(), info, msg)
+    }
+
+    #[test]
+    fn send_coins_is_recorded_with_memo_and_reference() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let res = send_coins_with_reference(deps.as_mut(), Some("rent"), Some("inv-1")).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                ("action", "send_coins"),
+                ("send_id", "1"),
+                ("quantity", "300"),
+                ("memo", "rent"),
+                ("reference_id", "inv-1"),
+            ]
+        );
+
+        let msg = QueryMsg::SendByReference {
+            sender: String::from("carol"),
+            reference_id: String::from("inv-1"),
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let record: SendRecord = from_binary(&res).unwrap();
+        assert_eq!(
+            record,
+            SendRecord {
+                id: 1,
+                sender: Addr::unchecked("carol"),
+                amount: 300,
+                shares: vec![
+                    (Addr::unchecked("alice"), 150),
+                    (Addr::unchecked("bob"), 150)
+                ],
+                memo: Some(String::from("rent")),
+                reference_id: Some(String::from("inv-1")),
+                time: mock_env().block.time,
+            }
+        );
+
+        // Sends without a reference are recorded too.
+        send_coins_with_reference(deps.as_mut(), None, None).unwrap();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Send { send_id: 2 }).unwrap();
+        let record: SendRecord = from_binary(&res).unwrap();
+        assert_eq!(record.reference_id, None);
+    }
+
+    #[test]
+    fn send_coins_rejects_duplicate_reference() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        send_coins_with_reference(deps.as_mut(), None, Some("inv-1")).unwrap();
+        match send_coins_with_reference(deps.as_mut(), None, Some("inv-1")).unwrap_err() {
+            ContractError::DuplicateReferenceId { reference_id } => {
+                assert_eq!(reference_id, "inv-1")
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_account_balance(deps.as_ref(), "alice", 150);
+
+        // Reference IDs only have to be unique per sender.
+        let info = mock_info("dave", &[coin(300, "usei")]);
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: String::from("alice"),
+            dest_addr2: String::from("bob"),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: Some(String::from("inv-1")),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 300);
+    }
+
+    #[test]
+    fn send_coins_with_oversized_memo_or_reference() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let memo = "m".repeat(MAX_MEMO_LENGTH + 1);
+        match send_coins_with_reference(deps.as_mut(), Some(&memo), None).unwrap_err() {
+            ContractError::MemoTooLong { max } => assert_eq!(max, MAX_MEMO_LENGTH),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let reference_id = "r".repeat(MAX_REFERENCE_ID_LENGTH + 1);
+        match send_coins_with_reference(deps.as_mut(), None, Some(&reference_id)).unwrap_err() {
+            ContractError::ReferenceIdTooLong { max } => assert_eq!(max, MAX_REFERENCE_ID_LENGTH),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        send_coins_with_reference(deps.as_mut(), Some(&memo[1..]), None).unwrap();
+    }
+
+    fn create_invoice(deps: DepsMut, payers: Option<Vec<&str>>, expiry: Option<u64>) -> u64 {
+        let msg = ExecuteMsg::CreateInvoice {
+            payers: payers.map(|payers| payers.into_iter().map(String::from).collect()),
+            amount: 310,
+            split: vec![(String::from("alice"), 1), (String::from("bob"), 2)],
+            expiry: expiry.map(|seconds| env_at(seconds).block.time),
+        };
+        let res = execute(deps, env_at(0), mock_info("alice", &[]), msg).unwrap();
+        res.attributes[1].value.parse().unwrap()
+    }
+
+    fn pay_invoice(
+        deps: DepsMut,
+        env: Env,
+        payer: &str,
+        quantity: u128,
+        invoice_id: u64,
+    ) -> Result<Response, ContractError> {
+        let info = mock_info(payer, &[coin(quantity, "usei")]);
+        execute(deps, env, info, ExecuteMsg::PayInvoice { invoice_id })
+    }
+
+    fn query_invoices(deps: Deps, msg: QueryMsg) -> Vec<u64> {
+        let res = query(deps, mock_env(), msg).unwrap();
+        let value: InvoicesResponse = from_binary(&res).unwrap();
+        value
+            .invoices
+            .into_iter()
+            .map(|invoice| invoice.id)
+            .collect()
+    }
+
+    #[test]
+    fn pay_invoice_credits_split_after_fee() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        let invoice_id = create_invoice(deps.as_mut(), None, None);
+
+        match pay_invoice(deps.as_mut(), env_at(1), "carol", 300, invoice_id).unwrap_err() {
+            ContractError::WrongInvoicePayment { expected, received } => {
+                assert_eq!((expected, received), (310, 300))
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        pay_invoice(deps.as_mut(), env_at(1), "carol", 310, invoice_id).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 100);
+        assert_account_balance(deps.as_ref(), "bob", 200);
+        assert_account_balance(deps.as_ref(), "creator", 10);
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Invoice { invoice_id }).unwrap();
+        let invoice: Invoice = from_binary(&res).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+        assert_eq!(invoice.paid_by, Some(Addr::unchecked("carol")));
+        match pay_invoice(deps.as_mut(), env_at(1), "carol", 310, invoice_id).unwrap_err() {
+            ContractError::InvoiceNotOpen { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn invoice_payers_and_expiry() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let invoice_id = create_invoice(deps.as_mut(), Some(vec!["carol"]), Some(100));
+
+        match pay_invoice(deps.as_mut(), env_at(1), "dave", 310, invoice_id).unwrap_err() {
+            ContractError::NotInvoicePayer { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match pay_invoice(deps.as_mut(), env_at(100), "carol", 310, invoice_id).unwrap_err() {
+            ContractError::InvoiceExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        pay_invoice(deps.as_mut(), env_at(99), "carol", 310, invoice_id).unwrap();
+
+        let msg = ExecuteMsg::CreateInvoice {
+            payers: None,
+            amount: 310,
+            split: vec![(String::from("alice"), 1)],
+            expiry: Some(env_at(0).block.time),
+        };
+        match execute(deps.as_mut(), env_at(0), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::InvalidInvoiceExpiry {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn only_creator_can_cancel_invoice() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let invoice_id = create_invoice(deps.as_mut(), None, None);
+
+        let msg = ExecuteMsg::CancelInvoice { invoice_id };
+        match execute(deps.as_mut(), env_at(1), mock_info("bob", &[]), msg.clone()).unwrap_err() {
+            ContractError::NotInvoiceCreator { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        execute(deps.as_mut(), env_at(1), mock_info("alice", &[]), msg).unwrap();
+        match pay_invoice(deps.as_mut(), env_at(1), "carol", 310, invoice_id).unwrap_err() {
+            ContractError::InvoiceNotOpen { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn query_invoices_by_creator_payer_and_status() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let first = create_invoice(deps.as_mut(), Some(vec!["carol", "dave"]), None);
+        let second = create_invoice(deps.as_mut(), None, None);
+        let third = create_invoice(deps.as_mut(), None, None);
+        pay_invoice(deps.as_mut(), env_at(1), "dave", 310, second).unwrap();
+        let msg = ExecuteMsg::CancelInvoice { invoice_id: third };
+        execute(deps.as_mut(), env_at(1), mock_info("alice", &[]), msg).unwrap();
+
+        let msg = QueryMsg::InvoicesByCreator {
+            creator: String::from("alice"),
+            start_after: Some(first),
+            limit: None,
+        };
+        assert_eq!(query_invoices(deps.as_ref(), msg), vec![second, third]);
+        let msg = QueryMsg::InvoicesByPayer {
+            payer: String::from("dave"),
+            start_after: None,
+            limit: None,
+        };
+        assert_eq!(query_invoices(deps.as_ref(), msg), vec![first, second]);
+        let msg = QueryMsg::InvoicesByPayer {
+            payer: String::from("carol"),
+            start_after: None,
+            limit: None,
+        };
+        assert_eq!(query_invoices(deps.as_ref(), msg), vec![first]);
+        for (status, expected) in [
+            (InvoiceStatus::Open, first),
+            (InvoiceStatus::Paid, second),
+            (InvoiceStatus::Cancelled, third),
+        ] {
+            let msg = QueryMsg::InvoicesByStatus {
+                status,
+                start_after: None,
+                limit: None,
+            };
+            assert_eq!(query_invoices(deps.as_ref(), msg), vec![expected]);
+        }
+    }
+
+    #[test]
+    fn open_invoices_leave_out_expired_ones() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let expiring = create_invoice(deps.as_mut(), None, Some(100));
+        let lasting = create_invoice(deps.as_mut(), None, None);
+
+        let msg = QueryMsg::InvoicesByStatus {
+            status: InvoiceStatus::Open,
+            start_after: None,
+            limit: Some(1),
+        };
+        let res = query(deps.as_ref(), env_at(99), msg.clone()).unwrap();
+        let value: InvoicesResponse = from_binary(&res).unwrap();
+        assert_eq!(value.invoices[0].id, expiring);
+
+        // The expired invoice still uses up the page, which says where to carry on.
+        let res = query(deps.as_ref(), env_at(100), msg).unwrap();
+        let value: InvoicesResponse = from_binary(&res).unwrap();
+        assert_eq!(value.invoices, vec![]);
+        assert_eq!(value.next_start_after, Some(expiring));
+        let msg = QueryMsg::InvoicesByStatus {
+            status: InvoiceStatus::Open,
+            start_after: value.next_start_after,
+            limit: Some(1),
+        };
+        let res = query(deps.as_ref(), env_at(100), msg).unwrap();
+        let value: InvoicesResponse = from_binary(&res).unwrap();
+        assert_eq!(value.invoices[0].id, lasting);
+    }
+
+    #[test]
+    fn invoice_requires_split_recipients() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::CreateInvoice {
+            payers: None,
+            amount: 310,
+            split: vec![],
+            expiry: None,
+        };
+        match execute(deps.as_mut(), env_at(0), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::EmptyInvoiceSplit {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    const VOUCHER_KEY: [u8; 32] = [7; 32];
+
+    fn mock_init_with_voucher_pool(mut deps: DepsMut, quantity: u128) {
+        mock_init_no_owner_specified(deps.branch());
+        let pubkey = SigningKey::from_bytes(&VOUCHER_KEY)
+            .unwrap()
+            .verifying_key()
+            .to_bytes();
+        let msg = ExecuteMsg::SetVoucherKey {
+            pubkey: Binary::from(pubkey.as_slice()),
+        };
+        execute(deps.branch(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let info = mock_info("creator", &coins(quantity, "usei"));
+        execute(deps, mock_env(), info, ExecuteMsg::FundVoucherPool {}).unwrap();
+    }
+
+    fn sign_voucher(key: &[u8], recipient: &str, amount: u128, nonce: u64) -> Binary {
+        let payload = VoucherPayload {
+            chain_id: mock_env().block.chain_id,
+            contract: String::from(MOCK_CONTRACT_ADDR),
+            recipient: recipient.to_string(),
+            amount,
+            nonce,
+            expiry: env_at(100).block.time,
+        };
+        let signature: Signature = SigningKey::from_bytes(key)
+            .unwrap()
+            .sign(&to_vec(&payload).unwrap());
+        Binary::from(signature.as_ref())
+    }
+
+    fn claim_voucher(
+        deps: DepsMut,
+        env: Env,
+        recipient: &str,
+        amount: u128,
+        nonce: u64,
+        signature: Binary,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::ClaimVoucher {
+            amount,
+            nonce,
+            expiry: env_at(100).block.time,
+            signature,
+        };
+        execute(deps, env, mock_info(recipient, &[]), msg)
+    }
+
+    #[test]
+    fn voucher_pool_requires_treasury() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::SetVoucherKey {
+            pubkey: Binary::from([2; 33]),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::Treasury, msg);
+        let msg = ExecuteMsg::FundVoucherPool {};
+        assert_requires_role(deps.as_mut(), "bob", Role::Treasury, msg);
+
+        let msg = ExecuteMsg::SetVoucherKey {
+            pubkey: Binary::from([2; 32]),
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err() {
+            ContractError::InvalidVoucherKey {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn withdraw_voucher_pool_returns_coins_to_treasury() {
+        let mut deps = mock_dependencies();
+        mock_init_with_voucher_pool(deps.as_mut(), 1000);
+
+        let msg = ExecuteMsg::WithdrawVoucherPool { amount: 400 };
+        assert_requires_role(deps.as_mut(), "alice", Role::Treasury, msg);
+
+        let msg = ExecuteMsg::WithdrawVoucherPool { amount: 601 };
+        match execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err() {
+            ContractError::InsufficientVoucherPool { available } => assert_eq!(available, 600),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::WithdrawVoucherPool { amount: 200 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "creator".into(),
+                amount: coins(200, "usei"),
+            })
+        );
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::VoucherPool {}).unwrap();
+        let pool: VoucherPool = from_binary(&res).unwrap();
+        assert_eq!(pool.balance, 400);
+
+        // Vouchers can only be paid from what's left.
+        let signature = sign_voucher(&VOUCHER_KEY, "alice", 500, 1);
+        match claim_voucher(deps.as_mut(), env_at(1), "alice", 500, 1, signature).unwrap_err() {
+            ContractError::InsufficientVoucherPool { available } => assert_eq!(available, 400),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn claim_voucher_credits_recipient_once() {
+        let mut deps = mock_dependencies();
+        mock_init_with_voucher_pool(deps.as_mut(), 1000);
+
+        let signature = sign_voucher(&VOUCHER_KEY, "alice", 600, 1);
+        claim_voucher(deps.as_mut(), env_at(1), "alice", 600, 1, signature.clone()).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 600);
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::VoucherPool {}).unwrap();
+        let pool: VoucherPool = from_binary(&res).unwrap();
+        assert_eq!(pool.balance, 400);
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::VoucherNonce { nonce: 1 },
+        )
+        .unwrap();
+        let value: VoucherNonceResponse = from_binary(&res).unwrap();
+        assert_eq!(value.used_at, Some(env_at(1).block.time));
+
+        match claim_voucher(deps.as_mut(), env_at(1), "alice", 600, 1, signature).unwrap_err() {
+            ContractError::VoucherNonceUsed { nonce } => assert_eq!(nonce, 1),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let signature = sign_voucher(&VOUCHER_KEY, "bob", 600, 2);
+        match claim_voucher(deps.as_mut(), env_at(1), "bob", 600, 2, signature).unwrap_err() {
+            ContractError::InsufficientVoucherPool { available } => assert_eq!(available, 400),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn claim_voucher_rejects_bad_vouchers() {
+        let mut deps = mock_dependencies();
+        mock_init_with_voucher_pool(deps.as_mut(), 1000);
+
+        // Signed for someone else, for a different amount, or with another key.
+        let signature = sign_voucher(&VOUCHER_KEY, "alice", 100, 1);
+        let res = claim_voucher(deps.as_mut(), env_at(1), "bob", 100, 1, signature.clone());
+        match res.unwrap_err() {
+            ContractError::InvalidVoucherSignature {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = claim_voucher(deps.as_mut(), env_at(1), "alice", 200, 1, signature.clone());
+        match res.unwrap_err() {
+            ContractError::InvalidVoucherSignature {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let forged = sign_voucher(&[9; 32], "alice", 100, 1);
+        match claim_voucher(deps.as_mut(), env_at(1), "alice", 100, 1, forged).unwrap_err() {
+            ContractError::InvalidVoucherSignature {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = claim_voucher(
+            deps.as_mut(),
+            env_at(100),
+            "alice",
+            100,
+            1,
+            signature.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::VoucherExpired {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        claim_voucher(deps.as_mut(), env_at(99), "alice", 100, 1, signature).unwrap();
+    }
+
+    fn merkle_leaf(address: &str, amount: u128) -> [u8; 32] {
+        Sha256::new()
+            .chain([MERKLE_LEAF_PREFIX])
+            .chain(format!("{}:{}", address, amount))
+            .finalize()
+            .into()
+    }
+
+    fn merkle_parent(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        let (first, second) = if a < b { (a, b) } else { (b, a) };
+        Sha256::new()
+            .chain([MERKLE_NODE_PREFIX])
+            .chain(first)
+            .chain(second)
+            .finalize()
+            .into()
+    }
+
+    // A tree of alice 100, bob 200 and carol 300, with each claimant's proof.
+    fn merkle_tree() -> (String, Vec<Vec<String>>) {
+        let alice = merkle_leaf("alice", 100);
+        let bob = merkle_leaf("bob", 200);
+        let carol = merkle_leaf("carol", 300);
+        let alice_bob = merkle_parent(alice, bob);
+        let root = merkle_parent(alice_bob, carol);
+        let proofs = vec![
+            vec![hex::encode(bob), hex::encode(carol)],
+            vec![hex::encode(alice), hex::encode(carol)],
+            vec![hex::encode(alice_bob)],
+        ];
+        (hex::encode(root), proofs)
+    }
+
+    fn fund_merkle_distribution(deps: DepsMut, merkle_root: String, total: u128) -> u64 {
+        let msg = ExecuteMsg::FundMerkleDistribution {
+            merkle_root,
+            total,
+            expiry: env_at(100).block.time,
+        };
+        let info = mock_info("dave", &coins(total, "usei"));
+        let res = execute(deps, env_at(0), info, msg).unwrap();
+        res.attributes[1].value.parse().unwrap()
+    }
+
+    fn claim_merkle(
+        deps: DepsMut,
+        env: Env,
+        claimant: &str,
+        distribution_id: u64,
+        amount: u128,
+        proof: Vec<String>,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::ClaimMerkle {
+            distribution_id,
+            amount,
+            proof,
+        };
+        execute(deps, env, mock_info(claimant, &[]), msg)
+    }
+
+    #[test]
+    fn claim_merkle_with_valid_proofs() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let (root, proofs) = merkle_tree();
+        let id = fund_merkle_distribution(deps.as_mut(), root, 600);
+
+        for ((claimant, amount), proof) in vec![("alice", 100), ("bob", 200), ("carol", 300)]
+            .into_iter()
+            .zip(proofs.clone())
+        {
+            claim_merkle(deps.as_mut(), env_at(1), claimant, id, amount, proof).unwrap();
+            assert_account_balance(deps.as_ref(), claimant, amount);
+        }
+
+        let msg = QueryMsg::MerkleClaim {
+            distribution_id: id,
+            address: String::from("bob"),
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: MerkleClaimResponse = from_binary(&res).unwrap();
+        assert_eq!(value.claimed, Some(200));
+
+        let res = claim_merkle(deps.as_mut(), env_at(1), "bob", id, 200, proofs[1].clone());
+        match res.unwrap_err() {
+            ContractError::MerkleAlreadyClaimed { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn claim_merkle_rejects_invalid_claims() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let (root, proofs) = merkle_tree();
+        let id = fund_merkle_distribution(deps.as_mut(), root.clone(), 600);
+
+        // Someone else's proof, or the right proof for a larger amount.
+        for (claimant, amount) in [("dave", 100), ("alice", 101)] {
+            let res = claim_merkle(
+                deps.as_mut(),
+                env_at(1),
+                claimant,
+                id,
+                amount,
+                proofs[0].clone(),
+            );
+            match res.unwrap_err() {
+                ContractError::InvalidMerkleProof {} => {}
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+        let res = claim_merkle(
+            deps.as_mut(),
+            env_at(100),
+            "alice",
+            id,
+            100,
+            proofs[0].clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::MerkleDistributionExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // The tree adds up to 600, but only 250 was funded.
+        let underfunded = fund_merkle_distribution(deps.as_mut(), root, 250);
+        claim_merkle(
+            deps.as_mut(),
+            env_at(1),
+            "bob",
+            underfunded,
+            200,
+            proofs[1].clone(),
+        )
+        .unwrap();
+        let res = claim_merkle(
+            deps.as_mut(),
+            env_at(1),
+            "alice",
+            underfunded,
+            100,
+            proofs[0].clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::MerkleDistributionExhausted { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn funder_reclaims_unclaimed_remainder_after_expiry() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        let (root, proofs) = merkle_tree();
+        let id = fund_merkle_distribution(deps.as_mut(), root, 600);
+        claim_merkle(
+            deps.as_mut(),
+            env_at(1),
+            "carol",
+            id,
+            300,
+            proofs[2].clone(),
+        )
+        .unwrap();
+
+        let msg = ExecuteMsg::ReclaimMerkleDistribution {
+            distribution_id: id,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env_at(99),
+            mock_info("dave", &[]),
+            msg.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::MerkleDistributionNotExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = execute(
+            deps.as_mut(),
+            env_at(100),
+            mock_info("carol", &[]),
+            msg.clone(),
+        );
+        match res.unwrap_err() {
+            ContractError::NotMerkleFunder { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = execute(
+            deps.as_mut(),
+            env_at(100),
+            mock_info("dave", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: String::from("dave"),
+                amount: coins(300, "usei"),
+            })]
+        );
+        let res = execute(deps.as_mut(), env_at(100), mock_info("dave", &[]), msg);
+        match res.unwrap_err() {
+            ContractError::MerkleDistributionReclaimed { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    fn payroll() -> Vec<BatchSendItem> {
+        vec![
+            BatchSendItem {
+                amount: 300,
+                recipients: vec![(String::from("alice"), 1), (String::from("bob"), 2)],
+            },
+            BatchSendItem {
+                amount: 50,
+                recipients: vec![(String::from("alice"), 1)],
+            },
+        ]
+    }
+
+    fn batch_send(deps: DepsMut, quantity: u128) -> Result<Response, ContractError> {
+        let info = mock_info("carol", &coins(quantity, "usei"));
+        execute(
+            deps,
+            mock_env(),
+            info,
+            ExecuteMsg::BatchSend { sends: payroll() },
+        )
+    }
+
+    #[test]
+    fn batch_send_with_fee_per_item() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+
+        match batch_send(deps.as_mut(), 360).unwrap_err() {
+            ContractError::BatchFundsMismatch { expected, received } => {
+                assert_eq!((expected, received), (370, 360))
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = batch_send(deps.as_mut(), 370).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 150);
+        assert_account_balance(deps.as_ref(), "bob", 200);
+        assert_account_balance(deps.as_ref(), "creator", 20);
+        assert_eq!(
+            res.events,
+            vec![
+                Event::new("batch_send_item")
+                    .add_attribute("index", "0")
+                    .add_attribute("amount", "300")
+                    .add_attribute("fee", "10")
+                    .add_attribute("recipient", "alice")
+                    .add_attribute("share", "100")
+                    .add_attribute("recipient", "bob")
+                    .add_attribute("share", "200"),
+                Event::new("batch_send_item")
+                    .add_attribute("index", "1")
+                    .add_attribute("amount", "50")
+                    .add_attribute("fee", "10")
+                    .add_attribute("recipient", "alice")
+                    .add_attribute("share", "50"),
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_send_with_fee_per_batch() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+
+        let msg = ExecuteMsg::UpdateBatchFeeMode {
+            mode: BatchFeeMode::PerBatch,
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::FeeManager, msg);
+        batch_send(deps.as_mut(), 360).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 150);
+        assert_account_balance(deps.as_ref(), "bob", 200);
+        assert_account_balance(deps.as_ref(), "creator", 10);
+    }
+
+    #[test]
+    fn batch_send_rejects_invalid_batches() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let info = mock_info("carol", &[]);
+        let msg = ExecuteMsg::BatchSend { sends: vec![] };
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::EmptyBatch {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let mut sends = payroll();
+        sends[1].recipients.clear();
+        let info = mock_info("carol", &coins(350, "usei"));
+        let msg = ExecuteMsg::BatchSend { sends };
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::EmptyBatchSend { index } => assert_eq!(index, 1),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let sends = vec![payroll().remove(1); MAX_BATCH_SIZE + 1];
+        let info = mock_info("carol", &coins(50 * (MAX_BATCH_SIZE as u128 + 1), "usei"));
+        let msg = ExecuteMsg::BatchSend { sends };
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::BatchTooLarge { max } => assert_eq!(max, MAX_BATCH_SIZE),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn batch_send_rejects_overflowing_totals() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+
+        let item = |amount| BatchSendItem {
+            amount,
+            recipients: vec![(String::from("alice"), 1)],
+        };
+        let info = mock_info("carol", &coins(1, "usei"));
+        let msg = ExecuteMsg::BatchSend {
+            sends: vec![item(u128::MAX), item(1)],
+        };
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::Overflow(_) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // The amounts fit, but not once the fees are added.
+        let info = mock_info("carol", &coins(1, "usei"));
+        let msg = ExecuteMsg::BatchSend {
+            sends: vec![item(u128::MAX - 5)],
+        };
+        match execute(deps.as_mut(), mock_env(), info, msg).unwrap_err() {
+            ContractError::Overflow(_) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    fn push_payouts(deps: DepsMut, addresses: &[&str]) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::PushPayouts {
+            addresses: addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+        };
+        execute(deps, mock_env(), mock_info("keeper", &[]), msg)
+    }
+
+    // Reply to each of the payouts in `res` as if its bank send went through.
+    fn settle_payouts(mut deps: DepsMut, res: &Response) {
+        for sub_msg in &res.messages {
+            let reply_msg = Reply {
+                id: sub_msg.id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            };
+            reply(deps.branch(), mock_env(), reply_msg).expect("success is handled");
+        }
+    }
+
+    #[test]
+    fn push_payouts_pays_opted_in_accounts_and_bounty() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 310).unwrap();
+        // 3% of the coins paid out.
+        let msg = ExecuteMsg::UpdatePayoutBounty { bounty: 300 };
+        assert_requires_role(deps.as_mut(), "dave", Role::FeeManager, msg);
+        let msg = ExecuteMsg::SetAutoPayout { enabled: true };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+
+        let res = push_payouts(deps.as_mut(), &["alice", "bob", "nobody"]).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: coins(150, "usei"),
+            })
+        );
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_account_balance(deps.as_ref(), "bob", 150);
+        assert_account_balance(deps.as_ref(), "keeper", 0);
+        settle_payouts(deps.as_mut(), &res);
+        assert_account_balance(deps.as_ref(), "keeper", 4);
+        assert_account_balance(deps.as_ref(), "creator", 6);
+
+        // Nothing is left to pay out, so no bounty either.
+        let res = push_payouts(deps.as_mut(), &["alice"]).unwrap();
+        assert!(res.messages.is_empty());
+        assert_account_balance(deps.as_ref(), "keeper", 4);
+
+        let msg = ExecuteMsg::SetAutoPayout { enabled: false };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let msg = QueryMsg::AutoPayout {
+            address: String::from("alice"),
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: AutoPayoutResponse = from_binary(&res).unwrap();
+        assert!(!value.enabled);
+    }
+
+    #[test]
+    fn push_payouts_bounty_is_capped_by_fees() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 310).unwrap();
+        let msg = ExecuteMsg::UpdatePayoutBounty { bounty: 500 };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        for account in ["alice", "bob"] {
+            let msg = ExecuteMsg::SetAutoPayout { enabled: true };
+            execute(deps.as_mut(), mock_env(), mock_info(account, &[]), msg).unwrap();
+        }
+
+        let res = push_payouts(deps.as_mut(), &["alice", "bob"]).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        settle_payouts(deps.as_mut(), &res);
+        assert_account_balance(deps.as_ref(), "keeper", 10);
+        assert_account_balance(deps.as_ref(), "creator", 0);
+
+        let addresses = vec!["alice"; MAX_PAYOUTS_PER_CALL + 1];
+        match push_payouts(deps.as_mut(), &addresses).unwrap_err() {
+            ContractError::TooManyPayouts { max } => assert_eq!(max, MAX_PAYOUTS_PER_CALL),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::UpdatePayoutBounty { bounty: 10000 };
+        match execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err() {
+            ContractError::PayoutBountyTooLarge { bounty } => assert_eq!(bounty, 10000),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn push_payouts_bounty_is_only_paid_for_settled_payouts_to_others() {
+        let mut deps = mock_dependencies();
+        mock_init_with_fees(deps.as_mut(), 10, 0);
+        send_coins_from_carol(deps.as_mut(), "alice", "keeper", 310).unwrap();
+        let msg = ExecuteMsg::UpdatePayoutBounty { bounty: 1000 };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        for account in ["alice", "keeper"] {
+            let msg = ExecuteMsg::SetAutoPayout { enabled: true };
+            execute(deps.as_mut(), mock_env(), mock_info(account, &[]), msg).unwrap();
+        }
+
+        // The keeper's own payout earns nothing, and Alice's fails, so neither does it.
+        let res = push_payouts(deps.as_mut(), &["alice", "keeper"]).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        let reply_msg = Reply {
+            id: res.messages[0].id,
+            result: SubMsgResult::Err(String::from("insufficient funds")),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).expect("failure is handled");
+        let reply_msg = Reply {
+            id: res.messages[1].id,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).expect("success is handled");
+        assert_account_balance(deps.as_ref(), "alice", 150);
+        assert_account_balance(deps.as_ref(), "keeper", 0);
+        assert_account_balance(deps.as_ref(), "creator", 10);
+
+        // Once Alice is paid, the keeper earns 10% of it, up to the fees collected.
+        let res = push_payouts(deps.as_mut(), &["alice"]).unwrap();
+        settle_payouts(deps.as_mut(), &res);
+        assert_account_balance(deps.as_ref(), "keeper", 10);
+        assert_account_balance(deps.as_ref(), "creator", 0);
+    }
+
+    #[test]
+    fn push_payouts_skips_accounts_over_rate_limit() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        mock_init_with_withdrawal_limits(
+            deps.as_mut(),
+            WithdrawalLimits {
+                window_seconds: 100,
+                per_account: Some(500),
+                global: None,
+                timelock: None,
+            },
+        );
+        set_address_with_coins(&mut resolver(&mut deps.storage), "bob", 400);
+        for account in ["alice", "bob"] {
+            let msg = ExecuteMsg::SetAutoPayout { enabled: true };
+            execute(deps.as_mut(), mock_env(), mock_info(account, &[]), msg).unwrap();
+        }
+
+        // Alice's 1000 is over her limit, but Bob's 400 fits in his.
+        let res = push_payouts(deps.as_mut(), &["alice", "bob"]).unwrap();
+        assert_eq!(
+            res.messages
+                .into_iter()
+                .map(|msg| msg.msg)
+                .collect::<Vec<_>>(),
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("bob"),
+                amount: coins(400, "usei"),
+            })]
+        );
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+    }
+
+    fn env_at_height(blocks_after_genesis: u64) -> Env {
+        let mut env = mock_env();
+        env.block.height += blocks_after_genesis;
+        env
+    }
+
+    fn mock_init_with_balance_expiry(mut deps: DepsMut, treasury: Option<&str>) {
+        mock_init_no_owner_specified(deps.branch());
+        let msg = ExecuteMsg::UpdateBalanceExpiry {
+            inactive_blocks: Some(100),
+            treasury: treasury.map(String::from),
+        };
+        execute(deps, mock_env(), mock_info("creator", &[]), msg).unwrap();
+    }
+
+    fn send_at_height(deps: DepsMut, height: u64, sender: &str, dest: &str, quantity: u128) {
+        let info = mock_info(sender, &coins(quantity, "usei"));
+        let msg = ExecuteMsg::SendCoins {
+            dest_addr1: dest.to_string(),
+            dest_addr2: dest.to_string(),
+            notify_addr1: false,
+            notify_addr2: false,
+            memo: None,
+            reference_id: None,
+        };
+        execute(deps, env_at_height(height), info, msg).unwrap();
+    }
+
+    fn reclaim_expired(
+        deps: DepsMut,
+        height: u64,
+        address: &str,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::ReclaimExpired {
+            address: address.to_string(),
+        };
+        execute(deps, env_at_height(height), mock_info("keeper", &[]), msg)
+    }
+
+    #[test]
+    fn update_balance_expiry_requires_config_admin() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+
+        let msg = ExecuteMsg::UpdateBalanceExpiry {
+            inactive_blocks: Some(100),
+            treasury: Some(String::from("vault")),
+        };
+        assert_requires_role(deps.as_mut(), "alice", Role::ConfigAdmin, msg);
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+        assert_eq!(
+            value.balance_expiry,
+            Some(BalanceExpiry {
+                inactive_blocks: 100,
+                treasury: Some(Addr::unchecked("vault")),
+            })
+        );
+
+        let msg = ExecuteMsg::UpdateBalanceExpiry {
+            inactive_blocks: Some(0),
+            treasury: None,
+        };
+        match execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err() {
+            ContractError::InvalidBalanceExpiry {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn expired_balance_returns_to_senders_pro_rata() {
+        let mut deps = mock_dependencies();
+        mock_init_with_balance_expiry(deps.as_mut(), None);
+        send_at_height(deps.as_mut(), 0, "carol", "alice", 300);
+        send_at_height(deps.as_mut(), 10, "dave", "alice", 100);
+        send_at_height(deps.as_mut(), 0, "carol", "bob", 100);
+        // Bob's own activity restarts his inactivity period.
+        let msg = ExecuteMsg::SetAutoPayout { enabled: false };
+        execute(deps.as_mut(), env_at_height(50), mock_info("bob", &[]), msg).unwrap();
+
+        let msg = QueryMsg::ExpiringAccounts {
+            within_blocks: 10,
+            start_after: None,
+            limit: None,
+        };
+        let res = query(deps.as_ref(), env_at_height(95), msg).unwrap();
+        let value: ExpiringAccountsResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            value.accounts,
+            vec![ExpiringAccount {
+                address: String::from("alice"),
+                balance: 400,
+                last_active: mock_env().block.height,
+                expires_at: mock_env().block.height + 100,
+            }]
+        );
+        assert_eq!(value.next_start_after, None);
+
+        match reclaim_expired(deps.as_mut(), 99, "alice").unwrap_err() {
+            ContractError::BalanceNotExpired { address } => assert_eq!(address, "alice"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match reclaim_expired(deps.as_mut(), 100, "bob").unwrap_err() {
+            ContractError::BalanceNotExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let res = reclaim_expired(deps.as_mut(), 100, "alice").unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("reclaim_expired")
+                .add_attribute("address", "alice")
+                .add_attribute("quantity", "400")
+                .add_attribute("recipient", "carol")
+                .add_attribute("refund", "300")
+                .add_attribute("recipient", "dave")
+                .add_attribute("refund", "100")]
+        );
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_account_balance(deps.as_ref(), "carol", 300);
+        assert_account_balance(deps.as_ref(), "dave", 100);
+        match reclaim_expired(deps.as_mut(), 200, "alice").unwrap_err() {
+            ContractError::BalanceNotExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn expired_balance_goes_to_treasury() {
+        let mut deps = mock_dependencies();
+        mock_init_with_balance_expiry(deps.as_mut(), Some("vault"));
+        send_at_height(deps.as_mut(), 0, "carol", "alice", 300);
+
+        reclaim_expired(deps.as_mut(), 100, "alice").unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_account_balance(deps.as_ref(), "vault", 300);
+        assert_account_balance(deps.as_ref(), "carol", 0);
+    }
+
+    #[test]
+    fn expired_refunds_are_capped_at_what_each_sender_sent() {
+        let mut deps = mock_dependencies();
+        mock_init_with_balance_expiry(deps.as_mut(), None);
+        send_at_height(deps.as_mut(), 0, "carol", "alice", 300);
+        // Coins credited without a recorded sender can't be refunded to Carol.
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 500);
+
+        let res = reclaim_expired(deps.as_mut(), 100, "alice").unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("reclaim_expired")
+                .add_attribute("address", "alice")
+                .add_attribute("quantity", "500")
+                .add_attribute("recipient", "carol")
+                .add_attribute("refund", "300")
+                .add_attribute("recipient", "creator")
+                .add_attribute("refund", "200")]
+        );
+        assert_account_balance(deps.as_ref(), "carol", 300);
+        assert_account_balance(deps.as_ref(), "creator", 200);
+    }
+
+    #[test]
+    fn expired_refunds_only_go_to_tracked_senders() {
+        let mut deps = mock_dependencies();
+        mock_init_with_balance_expiry(deps.as_mut(), None);
+        let senders: Vec<String> = (0..=MAX_CREDIT_SOURCES)
+            .map(|i| format!("sender{:02}", i))
+            .collect();
+        for sender in &senders {
+            send_at_height(deps.as_mut(), 0, sender, "alice", 100);
+        }
+        // An earlier sender is still tracked when it sends again.
+        send_at_height(deps.as_mut(), 0, &senders[0], "alice", 100);
+
+        reclaim_expired(deps.as_mut(), 100, "alice").unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 0);
+        assert_account_balance(deps.as_ref(), &senders[0], 200);
+        for sender in &senders[1..MAX_CREDIT_SOURCES] {
+            assert_account_balance(deps.as_ref(), sender, 100);
+        }
+        // The sender past the tracked ones can't be refunded, so the owner gets it.
+        assert_account_balance(deps.as_ref(), &senders[MAX_CREDIT_SOURCES], 0);
+        assert_account_balance(deps.as_ref(), "creator", 100);
+    }
+
+    #[test]
+    fn emptied_account_forgets_its_senders() {
+        let mut deps = mock_dependencies();
+        mock_init_with_balance_expiry(deps.as_mut(), None);
+        send_at_height(deps.as_mut(), 0, "carol", "alice", 300);
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 300 };
+        execute(
+            deps.as_mut(),
+            env_at_height(10),
+            mock_info("alice", &[]),
+            msg,
+        )
+        .unwrap();
+
+        // The inactivity period restarts with the next credit, and only its sender
+        // is refunded.
+        send_at_height(deps.as_mut(), 20, "dave", "alice", 100);
+        match reclaim_expired(deps.as_mut(), 119, "alice").unwrap_err() {
+            ContractError::BalanceNotExpired { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        reclaim_expired(deps.as_mut(), 120, "alice").unwrap();
+        assert_account_balance(deps.as_ref(), "dave", 100);
+        assert_account_balance(deps.as_ref(), "carol", 0);
+    }
+
+    #[test]
+    fn expiring_accounts_pages_count_skipped_accounts() {
+        let mut deps = mock_dependencies();
+        mock_init_with_balance_expiry(deps.as_mut(), None);
+        send_at_height(deps.as_mut(), 0, "carol", "alice", 300);
+        send_at_height(deps.as_mut(), 0, "carol", "bob", 100);
+        let msg = ExecuteMsg::SetAutoPayout { enabled: false };
+        execute(
+            deps.as_mut(),
+            env_at_height(50),
+            mock_info("alice", &[]),
+            msg,
+        )
+        .unwrap();
+
+        let expiring = |deps: Deps, start_after: Option<&str>| {
+            let msg = QueryMsg::ExpiringAccounts {
+                within_blocks: 10,
+                start_after: start_after.map(String::from),
+                limit: Some(1),
+            };
+            let res = query(deps, env_at_height(95), msg).unwrap();
+            let value: ExpiringAccountsResponse = from_binary(&res).unwrap();
+            value
+        };
+        // Alice isn't expiring yet, but still fills the first page.
+        let page = expiring(deps.as_ref(), None);
+        assert!(page.accounts.is_empty());
+        assert_eq!(page.next_start_after, Some(String::from("alice")));
+        let page = expiring(deps.as_ref(), Some("alice"));
+        assert_eq!(
+            page.accounts
+                .iter()
+                .map(|account| account.address.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["bob"]
+        );
+        // Carol never held a balance, so she isn't tracked at all.
+        let page = expiring(deps.as_ref(), Some("bob"));
+        assert!(page.accounts.is_empty());
+        assert_eq!(page.next_start_after, None);
+    }
+
+    fn balance_at_height(deps: Deps, address: &str, height: u64) -> u128 {
+        let msg = QueryMsg::BalanceAtHeight {
+            address: address.to_string(),
+            height: mock_env().block.height + height,
+        };
+        let res = query(deps, mock_env(), msg).unwrap();
+        let value: BalanceAtHeightResponse = from_binary(&res).unwrap();
+        assert_eq!(value.address, address);
+        value.balance
+    }
+
+    fn total_liabilities_at_height(deps: Deps, height: u64) -> u128 {
+        let msg = QueryMsg::TotalLiabilitiesAtHeight {
+            height: mock_env().block.height + height,
+        };
+        let res = query(deps, mock_env(), msg).unwrap();
+        let value: TotalLiabilitiesAtHeightResponse = from_binary(&res).unwrap();
+        value.total
+    }
+
+    #[test]
+    fn balances_are_queryable_at_past_heights() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        send_at_height(deps.as_mut(), 10, "carol", "alice", 300);
+        send_at_height(deps.as_mut(), 10, "carol", "bob", 100);
+        let msg = ExecuteMsg::WithdrawCoins { quantity: 100 };
+        execute(
+            deps.as_mut(),
+            env_at_height(20),
+            mock_info("alice", &[]),
+            msg,
+        )
+        .unwrap();
+        send_at_height(deps.as_mut(), 20, "carol", "alice", 50);
+
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 9), 0);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 10), 300);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 19), 300);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 20), 250);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 1000), 250);
+        assert_eq!(balance_at_height(deps.as_ref(), "bob", 20), 100);
+
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 9), 0);
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 10), 400);
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 20), 350);
+    }
+
+    #[test]
+    fn migrate_backfills_balance_snapshots() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        // Deployed before the version was recorded.
+        cw2::CONTRACT.remove(&mut deps.storage);
+        // Balances from before they were snapshotted or counted in the total.
+        let mut bucket = resolver(&mut deps.storage);
+        set_address_with_coins(&mut bucket, "alice", 500);
+        set_address_with_coins(&mut bucket, "bob", 200);
+
+        migrate(deps.as_mut(), env_at_height(10), MigrateMsg { limit: None }).unwrap();
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 9), 0);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 10), 500);
+        assert_eq!(balance_at_height(deps.as_ref(), "bob", 10), 200);
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 10), 700);
+
+        send_at_height(deps.as_mut(), 20, "carol", "alice", 100);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 19), 500);
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 20), 600);
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 20), 800);
+    }
+
+    #[test]
+    fn snapshot_queries_accept_the_last_height() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap();
+
+        let msg = QueryMsg::BalanceAtHeight {
+            address: String::from("alice"),
+            height: u64::MAX,
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: BalanceAtHeightResponse = from_binary(&res).unwrap();
+        assert_eq!(value.balance, 50);
+        let msg = QueryMsg::TotalLiabilitiesAtHeight { height: u64::MAX };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: TotalLiabilitiesAtHeightResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total, 100);
+    }
+
+    #[test]
+    fn migrate_upgrades_legacy_config_and_roles() {
+        let mut deps = mock_dependencies();
+        save_legacy_config(&mut deps.storage, "creator");
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 500);
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg { limit: None }).unwrap();
+        let raw = deps.storage.get(&to_length_prefixed(CONFIG_KEY)).unwrap();
+        let saved: Config = from_slice(&raw).unwrap();
+        assert_eq!(saved.withdrawal_limits, WithdrawalLimits::default());
+        assert!(String::from_utf8(raw).unwrap().contains("payout_bounty"));
+        for role in Role::ALL {
+            assert_has_role(deps.as_ref(), role, "creator", true);
+        }
+
+        grant_role(deps.as_mut(), "creator", Role::Pauser, "bob");
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap();
+        assert_account_balance(deps.as_ref(), "alice", 550);
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 0), 600);
+    }
+
+    #[test]
+    fn migrate_backfills_in_pages() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        // Deployed before the version was recorded.
+        cw2::CONTRACT.remove(&mut deps.storage);
+        let mut bucket = resolver(&mut deps.storage);
+        set_address_with_coins(&mut bucket, "alice", 500);
+        set_address_with_coins(&mut bucket, "bob", 200);
+        set_address_with_coins(&mut bucket, "dave", 100);
+
+        let res = migrate(
+            deps.as_mut(),
+            env_at_height(10),
+            MigrateMsg { limit: Some(2) },
+        )
+        .unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "complete" && attr.value == "false"));
+        // Balances can't change until every one of them has been counted.
+        match send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap_err() {
+            ContractError::MigrationInProgress {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        let msg = SudoMsg::ForceRefund {
+            address: String::from("alice"),
+        };
+        match sudo(deps.as_mut(), mock_env(), msg).unwrap_err() {
+            ContractError::MigrationInProgress {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = ExecuteMsg::ContinueMigration { limit: Some(2) };
+        execute(
+            deps.as_mut(),
+            env_at_height(11),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(balance_at_height(deps.as_ref(), "alice", 10), 500);
+        assert_eq!(balance_at_height(deps.as_ref(), "dave", 11), 100);
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 11), 800);
+        match execute(
+            deps.as_mut(),
+            env_at_height(12),
+            mock_info("anyone", &[]),
+            msg,
+        )
+        .unwrap_err()
+        {
+            ContractError::NoMigrationInProgress {} => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap();
+    }
+
+    #[test]
+    fn ibc_transfer_restored_during_backfill() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        set_address_with_coins(&mut resolver(&mut deps.storage), "alice", 1000);
+        withdraw_ibc_from_alice(deps.as_mut(), 400, 29);
+        set_address_with_coins(&mut resolver(&mut deps.storage), "bob", 200);
+        cw2::CONTRACT.remove(&mut deps.storage);
+        migrate(
+            deps.as_mut(),
+            env_at_height(10),
+            MigrateMsg { limit: Some(1) },
+        )
+        .unwrap();
+
+        // Alice has already been backfilled, so her refund is added to the total.
+        let timeout =
+            r#"{"ibc_lifecycle_complete":{"ibc_timeout":{"channel":"channel-0","sequence":29}}}"#;
+        ibc_lifecycle_complete(deps.as_mut(), timeout).expect("refund isn't held up");
+        assert_account_balance(deps.as_ref(), "alice", 1000);
+
+        let msg = ExecuteMsg::ContinueMigration { limit: None };
+        execute(
+            deps.as_mut(),
+            env_at_height(11),
+            mock_info("anyone", &[]),
+            msg,
+        )
+        .unwrap();
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 11), 1200);
+    }
+
+    #[test]
+    fn migrate_skips_backfill_when_snapshots_are_kept() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).unwrap();
+
+        let res = migrate(
+            deps.as_mut(),
+            env_at_height(10),
+            MigrateMsg { limit: Some(1) },
+        )
+        .unwrap();
+        assert!(!res.attributes.iter().any(|attr| attr.key == "complete"));
+        send_coins_from_carol(deps.as_mut(), "alice", "bob", 100).expect("nothing is frozen");
+        assert_eq!(total_liabilities_at_height(deps.as_ref(), 10), 200);
+    }
+
+    #[test]
+    fn migrate_rejects_other_contracts() {
+        let mut deps = mock_dependencies();
+        mock_init_no_owner_specified(deps.as_mut());
+        cw2::set_contract_version(&mut deps.storage, "crates.io:cw20-base", "0.13.2").unwrap();
+
+        match migrate(deps.as_mut(), mock_env(), MigrateMsg { limit: None }).unwrap_err() {
+            ContractError::CannotMigrate { contract } => {
+                assert_eq!(contract, "crates.io:cw20-base")
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
 }